@@ -1,3 +1,4 @@
 pub mod error;
 pub mod file_system;
-mod zlib;
+pub mod lock;
+pub(crate) mod zlib;
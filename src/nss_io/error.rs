@@ -1,7 +1,11 @@
+use std::path::PathBuf;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Nss IO error: {0}")]
     IOError(#[from] std::io::Error),
+
+    #[error("Locked by another process: {}", .0.display())]
+    Locked(PathBuf),
 }
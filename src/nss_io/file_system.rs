@@ -1,17 +1,37 @@
 // Std
 use std::fs;
-use std::fs::OpenOptions;
-use std::io::prelude::*;
-use std::path::Path;
+use std::fs::{File, OpenOptions};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 // Intenal
 use super::error::Error;
-use super::zlib::{read_decoder, write_encoder};
+use super::zlib::{is_zlib, read_decoder, write_encoder, write_encoder_with_level};
 
+// flate2's default
+const DEFAULT_ZLIB_LEVEL: u32 = 6;
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// How `write_content` opens the target.
+///
+/// `Atomic*` modes write a temp file in the same directory, fsync it and
+/// move it onto the target, then fsync the directory. A crash leaves either
+/// the old file or the new one, never a partial one. `AtomicCreateNew*`
+/// fails like `create_new` when the target already exists, except without
+/// hard links, see [`rename_new`].
+/// `AtomicCreateNewWithZlibLevel` compresses with the given level (0-9).
 #[derive(Default)]
 #[allow(dead_code)]
 pub(crate) enum WriteMode {
     #[default]
+    Atomic,
+    AtomicWithZlib,
+    AtomicCreateNew,
+    AtomicCreateNewWithZlib,
+    AtomicCreateNewWithZlibLevel(u32),
     Trucate,
     TrucateWithZlib,
     CreateTrucate,
@@ -29,6 +49,17 @@ pub(crate) fn write_content<P: AsRef<Path>>(
     option.write(true);
 
     let mut writer: Box<dyn Write> = match mode {
+        WriteMode::Atomic => return write_atomic(p, content, None, false),
+        WriteMode::AtomicWithZlib => {
+            return write_atomic(p, content, Some(DEFAULT_ZLIB_LEVEL), false)
+        }
+        WriteMode::AtomicCreateNew => return write_atomic(p, content, None, true),
+        WriteMode::AtomicCreateNewWithZlib => {
+            return write_atomic(p, content, Some(DEFAULT_ZLIB_LEVEL), true)
+        }
+        WriteMode::AtomicCreateNewWithZlibLevel(level) => {
+            return write_atomic(p, content, Some(level), true)
+        }
         WriteMode::Trucate => Box::new(option.truncate(true).open(p)?),
         WriteMode::TrucateWithZlib => {
             let file = option.truncate(true).open(p)?;
@@ -52,30 +83,138 @@ pub(crate) fn write_content<P: AsRef<Path>>(
     Ok(())
 }
 
+fn write_atomic<P: AsRef<Path>>(
+    p: P,
+    content: &[u8],
+    zlib_level: Option<u32>,
+    create_new: bool,
+) -> Result<(), Error> {
+    let p = p.as_ref();
+    let temp_path = temp_path(p);
+
+    let result = write_temp(&temp_path, content, zlib_level).and_then(|_| match create_new {
+        true => rename_new(&temp_path, p).map_err(Error::from),
+        false => fs::rename(&temp_path, p).map_err(Error::from),
+    });
+    if create_new || result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result?;
+
+    sync_dir(p.parent().unwrap_or(Path::new(".")))
+}
+
+/// Move `temp` to `p`, failing with `AlreadyExists` when `p` exists.
+///
+/// A hard link refuses an existing target, unlike rename. File systems
+/// without hard links (FAT, some network mounts) get a plain rename, which
+/// replaces an existing `p`. Only content-addressed files, whose
+/// replacement is identical, should rely on this.
+/// `temp` may be left behind, callers remove it.
+pub(crate) fn rename_new(temp: &Path, p: &Path) -> io::Result<()> {
+    match fs::hard_link(temp, p) {
+        Err(e) if is_link_unsupported(&e) => fs::rename(temp, p),
+        result => result,
+    }
+}
+
+fn is_link_unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Unsupported | io::ErrorKind::PermissionDenied
+    )
+}
+
+fn write_temp(temp_path: &Path, content: &[u8], zlib_level: Option<u32>) -> Result<(), Error> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temp_path)?;
+
+    let file = match zlib_level {
+        Some(level) => {
+            let mut encoder = write_encoder_with_level(file, level);
+            encoder.write_all(content)?;
+            encoder.finish()?
+        }
+        None => {
+            let mut file = file;
+            file.write_all(content)?;
+            file
+        }
+    };
+    file.sync_all()?;
+
+    Ok(())
+}
+
+/// Hidden temp file next to `p`, unique within this process.
+pub(crate) fn temp_path(p: &Path) -> PathBuf {
+    let name = p.file_name().unwrap_or_default().to_string_lossy();
+    let count = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    p.with_file_name(format!(".{}.tmp-{}-{}", name, process::id(), count))
+}
+
+/// Persist renames and new entries in `dir`.
+pub(crate) fn sync_dir<P: AsRef<Path>>(dir: P) -> Result<(), Error> {
+    // Directories cannot be opened as files on Windows
+    if cfg!(unix) {
+        let dir = match dir.as_ref().as_os_str().is_empty() {
+            true => Path::new("."),
+            false => dir.as_ref(),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// How `read_content` decodes the file. `DetectZlib` inflates zlib data
+/// and returns anything else as is.
 #[derive(Default)]
 #[allow(dead_code)]
 pub(crate) enum ReadMode {
     #[default]
     None,
     WithZlib,
+    DetectZlib,
 }
 
 pub(crate) fn read_content<P: AsRef<Path>>(p: P, mode: ReadMode) -> Result<Vec<u8>, Error> {
+    let mut reader = open_content(p, mode)?;
+
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+/// Decoded content of `p` as a stream, see [`read_content`].
+pub(crate) fn open_content<P: AsRef<Path>>(p: P, mode: ReadMode) -> Result<Box<dyn Read>, Error> {
     let mut option = OpenOptions::new();
     option.read(true);
 
-    let mut reader: Box<dyn Read> = match mode {
+    let reader: Box<dyn Read> = match mode {
         ReadMode::None => Box::new(option.open(p)?),
         ReadMode::WithZlib => {
             let file = option.open(p)?;
             Box::new(read_decoder(file))
         }
+        ReadMode::DetectZlib => {
+            let mut file = option.open(p)?;
+            let mut header = [0u8; 2];
+            let read = file.read(&mut header)?;
+            file.rewind()?;
+
+            match is_zlib(&header[..read]) {
+                true => Box::new(read_decoder(file)),
+                false => Box::new(file),
+            }
+        }
     };
 
-    let mut bytes = Vec::new();
-    reader.read_to_end(&mut bytes)?;
-
-    Ok(bytes)
+    Ok(reader)
 }
 
 pub(crate) fn create_dir<P: AsRef<Path>>(p: P) -> Result<(), Error> {
@@ -149,6 +288,110 @@ fn commit(message: &str) -> std::io::Result<()> {
         Ok(())
     }
 
+    #[test]
+    fn test_write_content_atomic() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        let target = temp_dir.join("config");
+
+        // Created and replaced
+        write_content(&target, b"first", WriteMode::default())?;
+        assert_eq!(fs::read(&target)?, b"first");
+        write_content(&target, b"second", WriteMode::Atomic)?;
+        assert_eq!(fs::read(&target)?, b"second");
+
+        // Create new refuses an existing target and keeps it
+        let result = write_content(&target, b"third", WriteMode::AtomicCreateNew);
+        assert!(result.is_err());
+        assert_eq!(fs::read(&target)?, b"second");
+
+        write_content(
+            temp_dir.join("object"),
+            b"compressed",
+            WriteMode::AtomicCreateNewWithZlib,
+        )?;
+        let content = read_content(temp_dir.join("object"), ReadMode::WithZlib)?;
+        assert_eq!(content, b"compressed");
+
+        // Missing directory fails without leftovers
+        let result = write_content(temp_dir.join("no").join("file"), b"", WriteMode::Atomic);
+        assert!(result.is_err());
+
+        // No temp file is left behind
+        let mut names: Vec<_> = fs::read_dir(&temp_dir)?
+            .map(|e| e.map(|e| e.file_name()))
+            .collect::<Result<_, _>>()?;
+        names.sort();
+        assert_eq!(names, vec!["config", "object"]);
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_new() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        let target = temp_dir.join("object");
+
+        fs::write(temp_dir.join("first"), b"first")?;
+        rename_new(&temp_dir.join("first"), &target)?;
+        assert_eq!(fs::read(&target)?, b"first");
+
+        fs::write(temp_dir.join("second"), b"second")?;
+        let result = rename_new(&temp_dir.join("second"), &target);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&target)?, b"first");
+
+        // Only missing link support falls back
+        let unsupported = io::Error::from(io::ErrorKind::Unsupported);
+        assert!(is_link_unsupported(&unsupported));
+        let exists = io::Error::from(io::ErrorKind::AlreadyExists);
+        assert!(!is_link_unsupported(&exists));
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_content_detect_zlib() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let content = b"blob 13\0legacy object".repeat(20);
+        fs::write(temp_dir.join("raw"), &content)?;
+        for level in [0, 1, 9] {
+            let p = temp_dir.join(format!("zlib{}", level));
+            write_content(&p, &content, WriteMode::AtomicCreateNewWithZlibLevel(level))?;
+            assert_eq!(read_content(&p, ReadMode::DetectZlib)?, content);
+        }
+        assert_eq!(
+            read_content(temp_dir.join("raw"), ReadMode::DetectZlib)?,
+            content
+        );
+
+        // Higher levels are smaller
+        let stored = fs::metadata(temp_dir.join("zlib0"))?.len();
+        let best = fs::metadata(temp_dir.join("zlib9"))?.len();
+        assert!(best < stored);
+
+        // Short files
+        fs::write(temp_dir.join("empty"), b"")?;
+        assert!(read_content(temp_dir.join("empty"), ReadMode::DetectZlib)?.is_empty());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
     #[test]
     fn test_create_dir() {
         let temp_dir = testdir!();
@@ -0,0 +1,215 @@
+// Std
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// Intenal
+use super::error::Error;
+use super::file_system::{sync_dir, write_content, WriteMode};
+
+const LOCK_EXTENSION: &str = "lock";
+
+/// **LockFile Struct**
+///
+/// Exclusive `<file>.lock` next to the target. The new content is written
+/// into the lock file and renamed over the target on `commit`; dropping an
+/// uncommitted lock removes it and leaves the target untouched.
+#[derive(Debug)]
+pub(crate) struct LockFile {
+    target: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+}
+
+impl LockFile {
+    pub(crate) fn acquire<P: AsRef<Path>>(target: P) -> Result<Self, Error> {
+        let target = target.as_ref().to_path_buf();
+        let lock_path = lock_path(&target);
+
+        let file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => return Err(Error::Locked(lock_path)),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            target,
+            lock_path,
+            file: Some(file),
+        })
+    }
+
+    pub(crate) fn write_all(&mut self, content: &[u8]) -> Result<(), Error> {
+        let file = self.file.as_mut().unwrap();
+        file.write_all(content)?;
+
+        Ok(())
+    }
+
+    /// Replace the target with `content` but keep holding the lock, for
+    /// work that must follow the change under it, like logging it.
+    ///
+    /// The target is replaced atomically, as by [`WriteMode::Atomic`].
+    pub(crate) fn replace(&self, content: &[u8]) -> Result<(), Error> {
+        write_content(&self.target, content, WriteMode::Atomic)
+    }
+
+    /// Replace the target with the written content and release the lock.
+    ///
+    /// The content is fsynced before the rename and the directory after it.
+    /// The lock is released on failure too, leaving the target untouched.
+    pub(crate) fn commit(mut self) -> Result<(), Error> {
+        let file = self.file.as_mut().unwrap();
+        file.flush()?;
+        file.sync_all()?;
+        drop(self.file.take());
+
+        if let Err(e) = fs::rename(&self.lock_path, &self.target) {
+            let _ = fs::remove_file(&self.lock_path);
+            return Err(e.into());
+        }
+
+        sync_dir(self.target.parent().unwrap_or(Path::new(".")))
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+pub(crate) fn lock_path<P: AsRef<Path>>(target: P) -> PathBuf {
+    let mut name = target.as_ref().as_os_str().to_os_string();
+    name.push(".");
+    name.push(LOCK_EXTENSION);
+
+    PathBuf::from(name)
+}
+
+pub(crate) fn is_lock_path<P: AsRef<Path>>(p: P) -> bool {
+    p.as_ref().extension() == Some(LOCK_EXTENSION.as_ref())
+}
+
+/// Whether the lock file at `lock_path` was left longer than `max_age` ago.
+pub(crate) fn is_stale_lock<P: AsRef<Path>>(
+    lock_path: P,
+    max_age: Duration,
+) -> Result<bool, Error> {
+    let modified = lock_path.as_ref().metadata()?.modified()?;
+    let age = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default();
+
+    Ok(age >= max_age)
+}
+
+/// Write `content` to `p` under its lock: the target is replaced in one
+/// rename, or left as it was.
+pub(crate) fn write_locked<P: AsRef<Path>>(p: P, content: &[u8]) -> Result<(), Error> {
+    let mut lock = LockFile::acquire(p)?;
+    lock.write_all(content)?;
+    lock.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::Result;
+    use testdir::testdir;
+
+    #[test]
+    fn test_lock_file_commit() -> Result<()> {
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        let target = temp_dir.join("INDEX");
+        fs::write(&target, b"old")?;
+
+        let mut lock = LockFile::acquire(&target)?;
+        assert!(temp_dir.join("INDEX.lock").is_file());
+
+        // Held lock is refused
+        let result = LockFile::acquire(&target);
+        assert!(matches!(result, Err(Error::Locked(p)) if p == temp_dir.join("INDEX.lock")));
+
+        lock.write_all(b"new")?;
+        assert_eq!(fs::read(&target)?, b"old");
+
+        lock.commit()?;
+        assert_eq!(fs::read(&target)?, b"new");
+        assert!(!temp_dir.join("INDEX.lock").exists());
+
+        // Replaced while still held
+        let lock = LockFile::acquire(&target)?;
+        lock.replace(b"newer")?;
+        assert_eq!(fs::read(&target)?, b"newer");
+        assert!(LockFile::acquire(&target).is_err());
+        drop(lock);
+        assert!(!temp_dir.join("INDEX.lock").exists());
+        assert_eq!(fs::read(&target)?, b"newer");
+
+        // A failed rename releases the lock
+        let target = temp_dir.join("dir");
+        fs::create_dir(&target)?;
+        fs::write(target.join("file"), b"kept")?;
+        let mut lock = LockFile::acquire(&target)?;
+        lock.write_all(b"new")?;
+        assert!(lock.commit().is_err());
+        assert!(!temp_dir.join("dir.lock").exists());
+        assert_eq!(fs::read(target.join("file"))?, b"kept");
+
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_file_drop() -> Result<()> {
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        let target = temp_dir.join("HEAD");
+        fs::write(&target, b"old")?;
+
+        {
+            let mut lock = LockFile::acquire(&target)?;
+            lock.write_all(b"half")?;
+        }
+        assert_eq!(fs::read(&target)?, b"old");
+        assert!(!temp_dir.join("HEAD.lock").exists());
+
+        // Target does not need to exist
+        write_locked(temp_dir.join("main"), b"hash")?;
+        assert_eq!(fs::read(temp_dir.join("main"))?, b"hash");
+
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_stale_lock() -> Result<()> {
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        let target = temp_dir.join("HEAD");
+
+        let lock = LockFile::acquire(&target)?;
+        assert!(is_lock_path(lock_path(&target)));
+        assert!(!is_lock_path(&target));
+        assert!(!is_stale_lock(lock_path(&target), Duration::from_secs(60))?);
+        assert!(is_stale_lock(lock_path(&target), Duration::ZERO)?);
+        drop(lock);
+
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+}
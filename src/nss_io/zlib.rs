@@ -1,11 +1,15 @@
 // Std
 use std::fs::File;
+use std::io::prelude::*;
 
 // External
 use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
 
+// Internal
+use super::error::Error;
+
 pub(crate) fn read_decoder(reader: File) -> ZlibDecoder<File> {
     ZlibDecoder::new(reader)
 }
@@ -13,3 +17,35 @@ pub(crate) fn read_decoder(reader: File) -> ZlibDecoder<File> {
 pub(crate) fn write_encoder(writer: File) -> ZlibEncoder<File> {
     ZlibEncoder::new(writer, Compression::default())
 }
+
+/// Encoder with `level` from 0 (stored) to 9 (smallest), clamped to 9.
+pub(crate) fn write_encoder_with_level(writer: File, level: u32) -> ZlibEncoder<File> {
+    ZlibEncoder::new(writer, Compression::new(level.min(9)))
+}
+
+/// Whether `bytes` start with a zlib header: deflate method (`0x?8`) and a
+/// header checksum divisible by 31. Raw objects start with an ascii type
+/// name, which never passes this check.
+pub(crate) fn is_zlib(bytes: &[u8]) -> bool {
+    match bytes {
+        [cmf, flg, ..] => {
+            cmf & 0x0f == 8 && cmf >> 4 <= 7 && ((u16::from(*cmf) << 8) | u16::from(*flg)) % 31 == 0
+        }
+        _ => false,
+    }
+}
+
+/// Compress `bytes` in memory with `level` (0-9, clamped to 9).
+pub(crate) fn compress(bytes: &[u8], level: u32) -> Result<Vec<u8>, Error> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level.min(9)));
+    encoder.write_all(bytes)?;
+
+    Ok(encoder.finish()?)
+}
+
+pub(crate) fn decompress(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut decoded = Vec::new();
+    ZlibDecoder::new(bytes).read_to_end(&mut decoded)?;
+
+    Ok(decoded)
+}
@@ -1,3 +1,17 @@
+pub mod cache;
 pub mod config;
+pub mod database;
+pub mod discovery;
 pub mod error;
+pub mod fsck;
+pub mod gc;
+pub mod iter;
+pub mod large;
+pub mod migration;
+pub mod pack;
+pub mod reflog;
 pub mod repository;
+pub mod stream;
+pub mod tags;
+pub mod transaction;
+pub mod worktree;
@@ -0,0 +1,246 @@
+//! Object cache
+//!
+//! Parsed objects kept in memory by [`ObjectRepository`] reads, so walking
+//! history does not read and parse the same commits and trees again.
+//!     - least recently used objects are evicted first
+//!     - the budget bounds the estimated size of cached objects
+//!     - objects larger than the whole budget are never cached
+//!     - a budget of 0 disables the cache
+//!
+//! [`ObjectRepository`]: super::repository::ObjectRepository
+
+// Std
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+// Internal
+use crate::struct_set::Object;
+
+/// 32 MiB of parsed objects.
+pub const DEFAULT_CACHE_SIZE: usize = 32 * 1024 * 1024;
+
+// Rough fixed cost of an entry beside its content
+const ENTRY_OVERHEAD: usize = 64;
+
+/// **CacheStats Struct**
+///
+/// Counters of an [`ObjectCache`] since it was created or cleared.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub bytes: usize,
+    pub budget: usize,
+}
+
+impl CacheStats {
+    /// Share of lookups served from memory, 0 before any lookup.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    // hash -> (object, estimated size, last use)
+    entries: HashMap<String, (Object, usize, u64)>,
+    // last use -> hash, oldest first
+    order: BTreeMap<u64, String>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+/// **ObjectCache Struct**
+///
+/// Bounded LRU map from hash to parsed object, shared by clones of an
+/// [`super::repository::ObjectRepository`].
+#[derive(Debug)]
+pub struct ObjectCache {
+    state: Mutex<CacheState>,
+}
+
+impl ObjectCache {
+    pub fn new(budget: usize) -> Self {
+        let state = CacheState {
+            stats: CacheStats {
+                budget,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        Self {
+            state: Mutex::new(state),
+        }
+    }
+
+    pub fn get(&self, hash: &str) -> Option<Object> {
+        let mut state = self.state.lock().unwrap();
+        if state.stats.budget == 0 {
+            return None;
+        }
+
+        state.clock += 1;
+        let clock = state.clock;
+        let CacheState {
+            entries,
+            order,
+            stats,
+            ..
+        } = &mut *state;
+        match entries.get_mut(hash) {
+            Some((object, _, last_use)) => {
+                order.remove(last_use);
+                order.insert(clock, hash.to_string());
+                *last_use = clock;
+                stats.hits += 1;
+
+                Some(object.clone())
+            }
+            None => {
+                stats.misses += 1;
+
+                None
+            }
+        }
+    }
+
+    /// Keep `object`, evicting the least recently used ones over budget.
+    pub fn insert(&self, hash: &str, object: &Object) {
+        let size = estimated_size(object);
+        let mut state = self.state.lock().unwrap();
+        if size > state.stats.budget || state.entries.contains_key(hash) {
+            return;
+        }
+
+        while state.stats.bytes + size > state.stats.budget {
+            let Some((_, oldest)) = state.order.pop_first() else {
+                break;
+            };
+            if let Some((_, evicted, _)) = state.entries.remove(&oldest) {
+                state.stats.bytes -= evicted;
+                state.stats.evictions += 1;
+            }
+        }
+
+        state.clock += 1;
+        let clock = state.clock;
+        state.order.insert(clock, hash.to_string());
+        state
+            .entries
+            .insert(hash.to_string(), (object.clone(), size, clock));
+        state.stats.bytes += size;
+    }
+
+    /// Drop every object and reset the counters, keeping the budget.
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        let budget = state.stats.budget;
+        *state = CacheState::default();
+        state.stats.budget = budget;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+        CacheStats {
+            entries: state.entries.len(),
+            ..state.stats
+        }
+    }
+}
+
+/// Approximate heap size of a parsed object.
+fn estimated_size(object: &Object) -> usize {
+    let content = match object {
+        Object::Blob(blob) => blob.content.len(),
+        Object::Tree(tree) => tree
+            .entries
+            .iter()
+            .map(|e| e.name.len() + e.hash.as_bytes().len() + ENTRY_OVERHEAD)
+            .sum(),
+        Object::Commit(commit) => {
+            commit.tree_hash.as_bytes().len()
+                + commit
+                    .parents
+                    .iter()
+                    .map(|p| p.as_bytes().len())
+                    .sum::<usize>()
+                + commit.author.len()
+                + commit.committer.len()
+                + commit.message.len()
+        }
+        Object::Tag(tag) => {
+            tag.object.as_bytes().len()
+                + tag.object_type.len()
+                + tag.name.len()
+                + tag.tagger.len()
+                + tag.message.len()
+        }
+    };
+
+    content + ENTRY_OVERHEAD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::struct_set::Blob;
+
+    fn blob(size: usize) -> Object {
+        Object::Blob(Blob {
+            content: vec![0; size],
+        })
+    }
+
+    #[test]
+    fn test_object_cache_lru() {
+        let entry = estimated_size(&blob(100));
+        let cache = ObjectCache::new(entry * 2);
+
+        assert!(cache.get("a").is_none());
+        cache.insert("a", &blob(100));
+        cache.insert("b", &blob(100));
+        assert!(cache.get("a").is_some());
+
+        // "b" is the least recently used
+        cache.insert("c", &blob(100));
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("c").is_some());
+
+        // Too large for the budget
+        cache.insert("d", &blob(entry * 2));
+        assert!(cache.get("d").is_none());
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.misses, 3);
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.bytes, entry * 2);
+        assert_eq!(stats.hit_rate(), 0.5);
+
+        cache.clear();
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                budget: entry * 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_object_cache_disabled() {
+        let cache = ObjectCache::new(0);
+        cache.insert("a", &blob(1));
+        assert!(cache.get("a").is_none());
+        assert_eq!(cache.stats().misses, 0);
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
@@ -3,11 +3,26 @@
 //!     User:
 //!         - name
 //!         - email
+//!     Core:
+//!         - repositoryformatversion
+//!         - bare
+//!         - compression  ... zlib level of loose objects, 0-9
+//!         - objectformat ... hash of object ids, `sha1` or `sha256`
+//!         - objectencoding ... layout of trees, commits and tags, `nss` or `git`
+//!         - cachesize    ... bytes of parsed objects kept in memory, 0 disables
+//!     Large:
+//!         - patterns     ... globs of files kept in the large-object store
 //!     Descprition
 //!
 
 use serde::{Deserialize, Serialize};
 
+use super::cache::DEFAULT_CACHE_SIZE;
+use crate::struct_set::{HashAlgorithm, ObjectEncoding};
+
+/// zlib's default trade-off between speed and size.
+pub const DEFAULT_COMPRESSION: u32 = 6;
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct User {
     name: String,
@@ -20,14 +35,56 @@ impl User {
     }
 }
 
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Core {
+    #[serde(default)]
+    repositoryformatversion: u32,
+    #[serde(default)]
+    bare: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compression: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    objectformat: Option<HashAlgorithm>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    objectencoding: Option<ObjectEncoding>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cachesize: Option<usize>,
+}
+
+impl Core {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Large {
+    #[serde(default)]
+    patterns: Vec<String>,
+}
+
+impl Large {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct Config {
     user: User,
+    #[serde(default, skip_serializing_if = "Core::is_default")]
+    core: Core,
+    #[serde(default, skip_serializing_if = "Large::is_default")]
+    large: Large,
 }
 
 impl Config {
     pub fn new(user: User) -> Self {
-        Self { user }
+        Self {
+            user,
+            core: Core::default(),
+            large: Large::default(),
+        }
     }
     pub fn username(&self) -> String {
         self.user.name.to_owned()
@@ -36,6 +93,88 @@ impl Config {
     pub fn useremail(&self) -> Option<String> {
         self.user.email.to_owned()
     }
+
+    pub fn is_bare(&self) -> bool {
+        self.core.bare
+    }
+
+    pub fn set_bare(&mut self, bare: bool) {
+        self.core.bare = bare
+    }
+
+    /// On-disk format version, `0` for repositories without a marker.
+    pub fn repository_format_version(&self) -> u32 {
+        self.core.repositoryformatversion
+    }
+
+    pub fn set_repository_format_version(&mut self, version: u32) {
+        self.core.repositoryformatversion = version
+    }
+
+    /// Zlib level for new loose objects, [`DEFAULT_COMPRESSION`] when unset.
+    pub fn compression(&self) -> u32 {
+        self.core.compression.unwrap_or(DEFAULT_COMPRESSION)
+    }
+
+    pub fn set_compression(&mut self, level: u32) {
+        self.core.compression = Some(level.min(9))
+    }
+
+    /// Hash function of object ids, SHA-1 when unset.
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.core.objectformat.unwrap_or_default()
+    }
+
+    /// Only takes effect on init, objects cannot be renamed afterwards.
+    pub fn set_hash_algorithm(&mut self, algorithm: HashAlgorithm) {
+        self.core.objectformat = Some(algorithm)
+    }
+
+    pub(crate) fn is_hash_algorithm_set(&self) -> bool {
+        self.core.objectformat.is_some()
+    }
+
+    /// Layout of trees, commits and tags, nss when unset.
+    pub fn object_encoding(&self) -> ObjectEncoding {
+        self.core.objectencoding.unwrap_or_default()
+    }
+
+    /// Only takes effect on init, like [`Config::set_hash_algorithm`].
+    pub fn set_object_encoding(&mut self, encoding: ObjectEncoding) {
+        self.core.objectencoding = Some(encoding)
+    }
+
+    pub(crate) fn is_object_encoding_set(&self) -> bool {
+        self.core.objectencoding.is_some()
+    }
+
+    /// Memory budget of the object cache, [`DEFAULT_CACHE_SIZE`] when unset.
+    pub fn cache_size(&self) -> usize {
+        self.core.cachesize.unwrap_or(DEFAULT_CACHE_SIZE)
+    }
+
+    pub fn set_cache_size(&mut self, bytes: usize) {
+        self.core.cachesize = Some(bytes)
+    }
+
+    /// Globs of files stored as pointers, see [`crate::repo::large`].
+    pub fn large_patterns(&self) -> &[String] {
+        &self.large.patterns
+    }
+
+    pub fn add_large_pattern<S: Into<String>>(&mut self, pattern: S) {
+        let pattern = pattern.into();
+        if !self.large.patterns.contains(&pattern) {
+            self.large.patterns.push(pattern)
+        }
+    }
+}
+
+impl Default for Config {
+    /// Config for the current os user, used when no identity is given.
+    fn default() -> Self {
+        Self::new(User::new(whoami::username(), None))
+    }
 }
 
 #[cfg(test)]
@@ -63,6 +202,13 @@ mod tests {
         assert_eq!(config.useremail(), Some("noshishi@nope.com".to_string()));
     }
 
+    #[test]
+    fn test_config_default() {
+        let config = Config::default();
+        assert_eq!(config.username(), whoami::username());
+        assert_eq!(config.useremail(), None);
+    }
+
     #[test]
     fn test_config_serialize_to_toml() {
         // User has no email
@@ -121,6 +267,143 @@ email = "noshishi@nope.com"
         assert_eq!(result.unwrap(), test_config);
     }
 
+    #[test]
+    fn test_config_bare_toml() {
+        let user = User::new("noshishi".to_string(), None);
+        let mut config = Config::new(user);
+        assert!(!config.is_bare());
+
+        config.set_bare(true);
+        assert!(config.is_bare());
+
+        let toml = toml::to_string(&config).unwrap();
+        let test_toml = r#"[user]
+name = "noshishi"
+
+[core]
+repositoryformatversion = 0
+bare = true
+"#;
+        assert_eq!(toml, test_toml);
+
+        let result = toml::from_str::<Config>(&toml).unwrap();
+        assert_eq!(result, config);
+    }
+
+    #[test]
+    fn test_config_format_version_toml() {
+        let toml = r#"[user]
+name = "noshishi"
+"#;
+        let mut config = toml::from_str::<Config>(toml).unwrap();
+        assert_eq!(config.repository_format_version(), 0);
+
+        config.set_repository_format_version(1);
+        let toml = toml::to_string(&config).unwrap();
+        let test_toml = r#"[user]
+name = "noshishi"
+
+[core]
+repositoryformatversion = 1
+bare = false
+"#;
+        assert_eq!(toml, test_toml);
+    }
+
+    #[test]
+    fn test_config_compression_toml() {
+        let toml = r#"[user]
+name = "noshishi"
+"#;
+        let mut config = toml::from_str::<Config>(toml).unwrap();
+        assert_eq!(config.compression(), DEFAULT_COMPRESSION);
+
+        config.set_compression(12);
+        assert_eq!(config.compression(), 9);
+
+        let toml = toml::to_string(&config).unwrap();
+        let test_toml = r#"[user]
+name = "noshishi"
+
+[core]
+repositoryformatversion = 0
+bare = false
+compression = 9
+"#;
+        assert_eq!(toml, test_toml);
+    }
+
+    #[test]
+    fn test_config_objectformat_toml() {
+        let toml = r#"[user]
+name = "noshishi"
+"#;
+        let mut config = toml::from_str::<Config>(toml).unwrap();
+        assert_eq!(config.hash_algorithm(), HashAlgorithm::Sha1);
+
+        config.set_hash_algorithm(HashAlgorithm::Sha256);
+        let toml = toml::to_string(&config).unwrap();
+        let test_toml = r#"[user]
+name = "noshishi"
+
+[core]
+repositoryformatversion = 0
+bare = false
+objectformat = "sha256"
+"#;
+        assert_eq!(toml, test_toml);
+        assert_eq!(toml::from_str::<Config>(&toml).unwrap(), config);
+
+        // Unknown algorithms are refused
+        let toml = test_toml.replace("sha256", "md5");
+        assert!(toml::from_str::<Config>(&toml).is_err());
+    }
+
+    #[test]
+    fn test_config_objectencoding_toml() {
+        let toml = r#"[user]
+name = "noshishi"
+"#;
+        let mut config = toml::from_str::<Config>(toml).unwrap();
+        assert_eq!(config.object_encoding(), ObjectEncoding::Nss);
+        assert!(!config.is_object_encoding_set());
+
+        config.set_object_encoding(ObjectEncoding::Git);
+        let toml = toml::to_string(&config).unwrap();
+        let test_toml = r#"[user]
+name = "noshishi"
+
+[core]
+repositoryformatversion = 0
+bare = false
+objectencoding = "git"
+"#;
+        assert_eq!(toml, test_toml);
+        assert_eq!(toml::from_str::<Config>(&toml).unwrap(), config);
+    }
+
+    #[test]
+    fn test_config_large_toml() {
+        let user = User::new("noshishi".to_string(), None);
+        let mut config = Config::new(user);
+        assert!(config.large_patterns().is_empty());
+
+        config.add_large_pattern("*.psd");
+        config.add_large_pattern("assets/*.bin");
+        config.add_large_pattern("*.psd");
+        assert_eq!(config.large_patterns(), ["*.psd", "assets/*.bin"]);
+
+        let toml = toml::to_string(&config).unwrap();
+        let test_toml = r#"[user]
+name = "noshishi"
+
+[large]
+patterns = ["*.psd", "assets/*.bin"]
+"#;
+        assert_eq!(toml, test_toml);
+        assert_eq!(toml::from_str::<Config>(&toml).unwrap(), config);
+    }
+
     #[test]
     fn test_user_debug() {
         let user = User::new("noshishi".to_string(), None);
@@ -139,7 +422,8 @@ email = "noshishi@nope.com"
 
         let debug = format!("{:?}", config);
 
-        let test_debug = "Config { user: User { name: \"noshishi\", email: None } }";
+        let test_debug =
+            "Config { user: User { name: \"noshishi\", email: None }, core: Core { repositoryformatversion: 0, bare: false, compression: None, objectformat: None, objectencoding: None, cachesize: None }, large: Large { patterns: [] } }";
 
         assert_eq!(debug, test_debug);
     }
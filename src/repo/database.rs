@@ -0,0 +1,497 @@
+//! Object database
+//!
+//! [`ObjectDatabase`] is what the rest of the crate needs from an object
+//! store. Backends:
+//!     - [`ObjectRepository`] ... loose files and packs under `objects/`
+//!     - [`MemoryObjectDatabase`] ... a map in memory, for tests and
+//!       embedded uses that never touch disk
+//!
+
+// Std
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+// Internal
+use super::error::Error;
+use super::iter::collected_ids;
+use super::repository::{NssRepository, ObjectRepository, PathRepository};
+use crate::struct_set::error::Error as StructError;
+use crate::struct_set::{Commit, HashAlgorithm, Hashable, Object, ObjectEncoding, ObjectId};
+
+// Shortest prefix accepted, same as `try_get_objects_path`
+const MIN_PREFIX_SIZE: usize = 6;
+
+pub trait ObjectDatabase {
+    /// Hash function naming the stored objects.
+    fn hash_algorithm(&self) -> HashAlgorithm;
+
+    /// Layout new trees, commits and tags must have.
+    fn object_encoding(&self) -> ObjectEncoding {
+        ObjectEncoding::default()
+    }
+
+    fn read_object(&self, hash: &str) -> Result<Object, Error>;
+
+    /// Store `object` and return its hash.
+    fn write_object(&self, object: &Object) -> Result<String, Error>;
+
+    fn has_object(&self, hash: &str) -> Result<bool, Error>;
+
+    /// Every stored hash, sorted.
+    fn hashes(&self) -> Result<Vec<String>, Error>;
+
+    /// Id of every stored object of `object_type`, or of any type on `None`.
+    ///
+    /// Collects [`ObjectDatabase::hashes`] unless the backend can walk its
+    /// objects lazily.
+    fn iter_objects<'a>(
+        &'a self,
+        object_type: Option<&'a str>,
+    ) -> Box<dyn Iterator<Item = Result<ObjectId, Error>> + 'a> {
+        collected_ids(self, object_type)
+    }
+
+    /// Type and content size of `hash`.
+    ///
+    /// Parses the whole object unless the backend can do better.
+    fn object_info(&self, hash: &str) -> Result<ObjectInfo, Error> {
+        Ok(ObjectInfo::from_object(&self.read_object(hash)?))
+    }
+
+    /// Stored hashes starting with the hex `prefix`, sorted.
+    fn hashes_with_prefix(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let prefix = prefix.to_ascii_lowercase();
+
+        Ok(self
+            .hashes()?
+            .into_iter()
+            .filter(|h| h.starts_with(&prefix))
+            .collect())
+    }
+
+    /// Full hash of the only object starting with `prefix`.
+    fn resolve_prefix(&self, prefix: &str) -> Result<String, Error> {
+        self.resolve_prefix_as(prefix, None)
+    }
+
+    /// Full hash of the only object starting with `prefix` whose type is
+    /// `expected`, e.g. `Some("commit")`, or of any type on `None`.
+    ///
+    /// Fails with [`Error::AmbiguousPrefix`] listing every candidate when
+    /// more than one is left.
+    fn resolve_prefix_as(&self, prefix: &str, expected: Option<&str>) -> Result<String, Error> {
+        if prefix.len() < MIN_PREFIX_SIZE {
+            return Err(Error::NssStruct(StructError::LessObjectHash));
+        }
+        if !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(Error::NssStruct(StructError::NotFoundObject));
+        }
+
+        let hashes = self.hashes_with_prefix(prefix)?;
+        let mut candidates = Vec::with_capacity(hashes.len());
+        for hash in hashes {
+            let object_type = self.object_info(&hash)?.object_type;
+            candidates.push(PrefixCandidate { hash, object_type });
+        }
+
+        let only_type = match &candidates[..] {
+            [only] => Some(only.object_type.clone()),
+            _ => None,
+        };
+        if let Some(expected) = expected {
+            candidates.retain(|c| c.object_type == expected);
+        }
+
+        match (candidates.len(), only_type) {
+            (1, _) => Ok(candidates.remove(0).hash),
+            // The only object of the prefix has another type
+            (0, Some(object_type)) => Err(Error::NssStruct(StructError::DontMatchType(
+                object_type,
+                prefix.to_string(),
+            ))),
+            (0, _) => Err(Error::NssStruct(StructError::NotFoundObject)),
+            _ => Err(Error::AmbiguousPrefix(prefix.to_string(), candidates)),
+        }
+    }
+
+    /// Shortest prefix length, at least 6, telling `hash` apart from every
+    /// other stored object.
+    ///
+    /// Only objects sharing the first 6 digits are listed.
+    fn abbreviation_len(&self, hash: &str) -> Result<usize, Error> {
+        let prefix = &hash[..MIN_PREFIX_SIZE.min(hash.len())];
+
+        Ok(unique_prefix_len(&self.hashes_with_prefix(prefix)?, hash))
+    }
+
+    /// `hash` cut to [`ObjectDatabase::abbreviation_len`], for display.
+    fn abbreviate(&self, hash: &str) -> Result<String, Error> {
+        let len = self.abbreviation_len(hash)?;
+
+        Ok(hash[..len].to_string())
+    }
+
+    fn read_commit(&self, hash: &str) -> Result<Commit, Error> {
+        match self.read_object(hash)? {
+            Object::Commit(c) => Ok(c),
+            o => Err(Error::NssStruct(StructError::DontMatchType(
+                o.as_str().to_string(),
+                hash.to_string(),
+            ))),
+        }
+    }
+}
+
+impl ObjectDatabase for ObjectRepository {
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        ObjectRepository::hash_algorithm(self)
+    }
+
+    fn object_encoding(&self) -> ObjectEncoding {
+        ObjectRepository::object_encoding(self)
+    }
+
+    fn read_object(&self, hash: &str) -> Result<Object, Error> {
+        self.read(hash)
+    }
+
+    fn write_object(&self, object: &Object) -> Result<String, Error> {
+        let hash = hex::encode(object.to_hash_with(self.hash_algorithm()));
+        self.write(object.clone())?;
+
+        Ok(hash)
+    }
+
+    fn has_object(&self, hash: &str) -> Result<bool, Error> {
+        ObjectRepository::has_object(self, hash)
+    }
+
+    fn hashes(&self) -> Result<Vec<String>, Error> {
+        let mut hashes = self.packed_objects()?;
+        hashes.extend(
+            self.loose_objects()?
+                .into_iter()
+                .map(|(h, _)| h)
+                .filter(|h| hex::decode(h).is_ok()),
+        );
+        hashes.sort();
+        hashes.dedup();
+
+        Ok(hashes)
+    }
+    fn object_info(&self, hash: &str) -> Result<ObjectInfo, Error> {
+        ObjectRepository::object_info(self, hash)
+    }
+
+    /// Walks loose directories and packs lazily, see [`crate::repo::iter`].
+    fn iter_objects<'a>(
+        &'a self,
+        object_type: Option<&'a str>,
+    ) -> Box<dyn Iterator<Item = Result<ObjectId, Error>> + 'a> {
+        match object_type {
+            Some(object_type) => Box::new(self.object_ids().with_type(object_type)),
+            None => Box::new(self.object_ids()),
+        }
+    }
+
+    /// Lists only the loose directory of the prefix and searches packs.
+    fn hashes_with_prefix(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        ObjectRepository::hashes_with_prefix(self, prefix)
+    }
+}
+
+impl ObjectDatabase for NssRepository {
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        NssRepository::hash_algorithm(self)
+    }
+
+    fn object_encoding(&self) -> ObjectEncoding {
+        NssRepository::object_encoding(self)
+    }
+
+    fn read_object(&self, hash: &str) -> Result<Object, Error> {
+        self.objects().read_object(hash)
+    }
+
+    fn write_object(&self, object: &Object) -> Result<String, Error> {
+        self.objects().write_object(object)
+    }
+
+    fn has_object(&self, hash: &str) -> Result<bool, Error> {
+        self.objects().has_object(hash)
+    }
+
+    fn hashes(&self) -> Result<Vec<String>, Error> {
+        self.objects().hashes()
+    }
+    fn object_info(&self, hash: &str) -> Result<ObjectInfo, Error> {
+        self.objects().object_info(hash)
+    }
+
+    fn hashes_with_prefix(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        self.objects().hashes_with_prefix(prefix)
+    }
+
+    fn iter_objects<'a>(
+        &'a self,
+        object_type: Option<&'a str>,
+    ) -> Box<dyn Iterator<Item = Result<ObjectId, Error>> + 'a> {
+        self.objects().iter_objects(object_type)
+    }
+}
+
+/// **ObjectInfo Struct**
+///
+/// What the header of a stored object tells: `<object_type> <size>\0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub object_type: String,
+    pub size: u64,
+}
+
+impl ObjectInfo {
+    pub fn from_object(object: &Object) -> Self {
+        let bytes = object.as_bytes();
+        let header_size = bytes.iter().position(|&b| b == b'\0').unwrap() + 1;
+
+        Self {
+            object_type: object.as_str().to_string(),
+            size: (bytes.len() - header_size) as u64,
+        }
+    }
+}
+
+/// **PrefixCandidate Struct**
+///
+/// An object matching an ambiguous prefix, shown as `<hash> <object_type>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixCandidate {
+    pub hash: String,
+    pub object_type: String,
+}
+
+impl std::fmt::Display for PrefixCandidate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}", self.hash, self.object_type)
+    }
+}
+
+/// Shortest prefix length, at least 6, that no hash of `sorted` other than
+/// `hash` itself starts with.
+pub fn unique_prefix_len(sorted: &[String], hash: &str) -> usize {
+    let position = sorted.partition_point(|h| h.as_str() < hash);
+    let common = |other: &String| {
+        other
+            .bytes()
+            .zip(hash.bytes())
+            .take_while(|(a, b)| a == b)
+            .count()
+    };
+
+    // Only the sorted neighbours can share a longer prefix
+    let before = sorted[..position].last();
+    let after = sorted[position..].iter().find(|h| h.as_str() != hash);
+    let longest = before.into_iter().chain(after).map(common).max();
+
+    (longest.unwrap_or(0) + 1)
+        .max(MIN_PREFIX_SIZE)
+        .min(hash.len())
+}
+
+/// **MemoryObjectDatabase Struct**
+///
+/// Objects kept in memory, lost when dropped.
+#[derive(Debug, Default)]
+pub struct MemoryObjectDatabase {
+    hash_algorithm: HashAlgorithm,
+    objects: RwLock<BTreeMap<String, Object>>,
+}
+
+impl MemoryObjectDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_hash_algorithm(algorithm: HashAlgorithm) -> Self {
+        Self {
+            hash_algorithm: algorithm,
+            ..Self::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ObjectDatabase for MemoryObjectDatabase {
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+
+    fn read_object(&self, hash: &str) -> Result<Object, Error> {
+        self.objects
+            .read()
+            .unwrap()
+            .get(hash)
+            .cloned()
+            .ok_or(Error::NssStruct(StructError::NotFoundObject))
+    }
+
+    fn write_object(&self, object: &Object) -> Result<String, Error> {
+        let hash = hex::encode(object.to_hash_with(self.hash_algorithm));
+        self.objects
+            .write()
+            .unwrap()
+            .insert(hash.clone(), object.clone());
+
+        Ok(hash)
+    }
+
+    fn has_object(&self, hash: &str) -> Result<bool, Error> {
+        Ok(self.objects.read().unwrap().contains_key(hash))
+    }
+
+    fn hashes(&self) -> Result<Vec<String>, Error> {
+        Ok(self.objects.read().unwrap().keys().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::config::Config;
+    use crate::struct_set::Blob;
+
+    use anyhow::Result;
+    use std::fs;
+    use testdir::testdir;
+
+    fn blob(content: &str) -> Object {
+        Object::Blob(Blob {
+            content: content.as_bytes().to_vec(),
+        })
+    }
+
+    fn check_database<D: ObjectDatabase + ?Sized>(database: &D) -> Result<()> {
+        assert!(database.hashes()?.is_empty());
+
+        let first = database.write_object(&blob("first"))?;
+        let second = database.write_object(&blob("second"))?;
+        assert_eq!(
+            first,
+            hex::encode(blob("first").to_hash_with(database.hash_algorithm()))
+        );
+
+        assert!(database.has_object(&first)?);
+        assert_eq!(
+            database.object_info(&first)?,
+            ObjectInfo {
+                object_type: "blob".to_string(),
+                size: 5
+            }
+        );
+
+        // Writing again is a no-op
+        assert_eq!(database.write_object(&blob("first"))?, first);
+        assert!(!database.has_object("0000000000000000000000000000000000000000")?);
+        assert_eq!(
+            database.read_object(&second)?.as_bytes(),
+            blob("second").as_bytes()
+        );
+        assert!(database
+            .read_object("0000000000000000000000000000000000000000")
+            .is_err());
+
+        let mut hashes = vec![first.clone(), second.clone()];
+        hashes.sort();
+        assert_eq!(database.hashes()?, hashes);
+
+        let mut ids = database
+            .iter_objects(None)
+            .map(|id| id.map(|id| id.to_hex()))
+            .collect::<Result<Vec<_>, _>>()?;
+        ids.sort();
+        assert_eq!(ids, hashes);
+        assert_eq!(database.iter_objects(Some("blob")).count(), 2);
+        assert_eq!(database.iter_objects(Some("commit")).count(), 0);
+
+        assert_eq!(database.resolve_prefix(&first[..8])?, first);
+        assert!(matches!(
+            database.resolve_prefix(&first[..3]),
+            Err(Error::NssStruct(StructError::LessObjectHash))
+        ));
+        assert!(matches!(
+            database.resolve_prefix("ffffffff"),
+            Err(Error::NssStruct(StructError::NotFoundObject))
+        ));
+        assert!(matches!(
+            database.resolve_prefix("zzzzzzzz"),
+            Err(Error::NssStruct(StructError::NotFoundObject))
+        ));
+        assert_eq!(
+            database.resolve_prefix_as(&first[..8], Some("blob"))?,
+            first
+        );
+        assert!(matches!(
+            database.resolve_prefix_as(&first[..8], Some("commit")),
+            Err(Error::NssStruct(StructError::DontMatchType(t, p)))
+                if t == "blob" && p == first[..8]
+        ));
+        assert_eq!(database.abbreviate(&first)?, &first[..6]);
+
+        assert!(matches!(
+            database.read_commit(&first),
+            Err(Error::NssStruct(StructError::DontMatchType(_, _)))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unique_prefix_len() {
+        let hashes: Vec<String> = ["a1b2c3d4e5", "a1b2c3d4f6", "a1ffffffff", "b000000000"]
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+
+        assert_eq!(unique_prefix_len(&hashes, "a1b2c3d4e5"), 9);
+        assert_eq!(unique_prefix_len(&hashes, "a1b2c3d4f6"), 9);
+        assert_eq!(unique_prefix_len(&hashes, "a1ffffffff"), 6);
+        // Not stored, still told apart from its neighbours
+        assert_eq!(unique_prefix_len(&hashes, "a1b2c3d4e0"), 10);
+        assert_eq!(unique_prefix_len(&[], "a1b2c3d4e5"), 6);
+    }
+
+    #[test]
+    fn test_memory_object_database() -> Result<()> {
+        let database = MemoryObjectDatabase::new();
+        check_database(&database)?;
+        assert_eq!(database.len(), 2);
+
+        // Usable as a trait object
+        let database: Box<dyn ObjectDatabase> = Box::new(MemoryObjectDatabase::new());
+        check_database(database.as_ref())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_object_repository_database() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        check_database(repository.objects())?;
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,383 @@
+//! Repository discovery
+//!
+//! Finds the repository a directory belongs to.
+//!     Environment:
+//!         - NSS_DIR                  ... repository directory to use as is
+//!         - NSS_WORK_TREE            ... working tree paired with NSS_DIR
+//!         - NSS_CEILING_DIRECTORIES  ... `:` separated dirs the search never goes above
+//!     `.nss` may be a directory or a file `nssdir: <path>` pointing elsewhere.
+//!
+
+// Std
+use std::env;
+use std::path::{Path, PathBuf};
+
+// External
+use dirs::home_dir;
+
+// Internal
+use super::config::Config;
+use super::error::Error;
+use super::migration::check_format_version;
+use super::repository::{
+    ConfigRepository, NssRepository, Repository, CONFIG_NAME, HEAD_NAME, OBJECT_NAME, REPO_NAME,
+};
+use crate::nss_io::file_system::{read_content, ReadMode};
+
+pub const NSS_DIR_ENV: &str = "NSS_DIR";
+pub const NSS_WORK_TREE_ENV: &str = "NSS_WORK_TREE";
+pub const NSS_CEILING_DIRECTORIES_ENV: &str = "NSS_CEILING_DIRECTORIES";
+
+pub(crate) const NSS_DIR_PREFIX: &str = "nssdir:";
+
+/// **Discovery Struct**
+///
+/// Settings for finding a repository from a start directory.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Discovery {
+    pub nss_dir: Option<PathBuf>,
+    pub work_tree: Option<PathBuf>,
+    pub ceiling_dirs: Vec<PathBuf>,
+}
+
+impl Discovery {
+    /// Settings from `NSS_DIR`, `NSS_WORK_TREE` and `NSS_CEILING_DIRECTORIES`.
+    ///
+    /// The home directory, when there is one, is always a ceiling.
+    pub fn from_env() -> Self {
+        let nss_dir = env::var_os(NSS_DIR_ENV)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from);
+        let work_tree = env::var_os(NSS_WORK_TREE_ENV)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from);
+
+        let mut ceiling_dirs: Vec<PathBuf> = match env::var_os(NSS_CEILING_DIRECTORIES_ENV) {
+            Some(v) => env::split_paths(&v)
+                .filter(|p| !p.as_os_str().is_empty())
+                .collect(),
+            None => vec![],
+        };
+        ceiling_dirs.extend(home_dir());
+
+        Self {
+            nss_dir,
+            work_tree,
+            ceiling_dirs,
+        }
+    }
+
+    /// Find the repository for `start`.
+    ///
+    /// An explicit `nss_dir` wins over the upward search. Otherwise each
+    /// directory from `start` upwards is checked for a `.nss` directory,
+    /// a `.nss` file or a bare repository, stopping before any ceiling.
+    /// Paths are compared canonicalized, so symlinks and `..` do not get
+    /// past a ceiling.
+    ///
+    /// The repository found is refused when its format version is newer
+    /// than this crate supports, or when its hashes do not match its
+    /// configured algorithm.
+    pub fn discover<P: AsRef<Path>>(&self, start: P) -> Result<NssRepository, Error> {
+        let repository = self.find(start)?;
+        check_format_version(&repository)?;
+        repository.check_hash_algorithm()?;
+
+        Ok(repository)
+    }
+
+    fn find<P: AsRef<Path>>(&self, start: P) -> Result<NssRepository, Error> {
+        let start = canonical(env::current_dir()?.join(start));
+
+        if let Some(nss_dir) = &self.nss_dir {
+            return self.open_nss_dir(&start, start.join(nss_dir));
+        }
+
+        let ceiling_dirs: Vec<PathBuf> = self
+            .ceiling_dirs
+            .iter()
+            .map(|c| canonical(c.clone()))
+            .collect();
+        for dir in start.ancestors() {
+            // Like git, a ceiling itself is never searched
+            if ceiling_dirs.iter().any(|c| c == dir) {
+                break;
+            }
+
+            let dot_nss = dir.join(REPO_NAME);
+            if dot_nss.is_dir() {
+                let work_tree = self.work_tree_or(dir)?;
+                return Ok(NssRepository::with_nss_dir(work_tree, dot_nss));
+            }
+            if dot_nss.is_file() {
+                let nss_dir = read_nss_file(&dot_nss)?;
+                let work_tree = self.work_tree_or(dir)?;
+                return Ok(NssRepository::with_nss_dir(work_tree, nss_dir));
+            }
+            if is_bare_dir(dir) {
+                return Ok(NssRepository::new_bare(dir.to_path_buf()));
+            }
+        }
+
+        Err(Error::NotFoundRepository)
+    }
+
+    fn open_nss_dir(&self, start: &Path, nss_dir: PathBuf) -> Result<NssRepository, Error> {
+        let nss_dir = match nss_dir.is_file() {
+            true => read_nss_file(&nss_dir)?,
+            false => nss_dir,
+        };
+
+        if !nss_dir.join(HEAD_NAME).is_file() {
+            return Err(Error::NotFoundRepository);
+        }
+
+        if self.work_tree.is_some() {
+            return Ok(NssRepository::with_nss_dir(
+                self.work_tree_or(start)?,
+                nss_dir,
+            ));
+        }
+        if is_bare_dir(&nss_dir) {
+            return Ok(NssRepository::new_bare(nss_dir));
+        }
+
+        let work_tree = match nss_dir.file_name() == Some(REPO_NAME.as_ref()) {
+            true => nss_dir.parent().unwrap().to_path_buf(),
+            false => start.to_path_buf(),
+        };
+
+        Ok(NssRepository::with_nss_dir(work_tree, nss_dir))
+    }
+
+    /// `work_tree`, relative to the current directory like git's
+    /// `GIT_WORK_TREE`, or `dir` without one.
+    fn work_tree_or(&self, dir: &Path) -> Result<PathBuf, Error> {
+        match &self.work_tree {
+            Some(w) => Ok(env::current_dir()?.join(w)),
+            None => Ok(dir.to_path_buf()),
+        }
+    }
+}
+
+/// Resolve a `.nss` file (`nssdir: <path>`) to the directory it points at.
+///
+/// A relative path is taken from the directory holding the file.
+pub(crate) fn read_nss_file<P: AsRef<Path>>(path: P) -> Result<PathBuf, Error> {
+    let bytes = read_content(path.as_ref(), ReadMode::default())?;
+    let content = String::from_utf8(bytes)?;
+
+    let target = content
+        .trim()
+        .strip_prefix(NSS_DIR_PREFIX)
+        .map(|p| PathBuf::from(p.trim()))
+        .ok_or(Error::NotFoundRepository)?;
+
+    let base = path.as_ref().parent().unwrap();
+    let nss_dir = base.join(target);
+    if !nss_dir.is_dir() {
+        return Err(Error::NotFoundRepository);
+    }
+
+    Ok(nss_dir)
+}
+
+fn is_bare_dir(dir: &Path) -> bool {
+    if !dir.join(HEAD_NAME).is_file() || !dir.join(OBJECT_NAME).is_dir() {
+        return false;
+    }
+
+    let config: Result<Config, Error> = ConfigRepository::from(dir.join(CONFIG_NAME)).read();
+    config.map(|c| c.is_bare()).unwrap_or(false)
+}
+
+/// `p` with symlinks and `..` resolved, as is when it does not exist.
+fn canonical(p: PathBuf) -> PathBuf {
+    p.canonicalize().unwrap_or(p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::Result;
+    use std::fs;
+    use testdir::testdir;
+
+    #[test]
+    fn test_discover_repository() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let sub_dir = temp_dir.join("sub").join("sub_sub");
+        fs::create_dir_all(&sub_dir)?;
+
+        let repository = Discovery::default().discover(&sub_dir)?;
+        assert_eq!(repository.path(), temp_dir);
+        assert_eq!(repository.nss_dir(), temp_dir.join(".nss"));
+
+        // Work tree override
+        let discovery = Discovery {
+            work_tree: Some(temp_dir.join("sub")),
+            ..Default::default()
+        };
+        let repository = discovery.discover(&sub_dir)?;
+        assert_eq!(repository.path(), temp_dir.join("sub"));
+        assert_eq!(repository.nss_dir(), temp_dir.join(".nss"));
+
+        // A relative work tree is taken from the current directory
+        let discovery = Discovery {
+            work_tree: Some(PathBuf::from("tree")),
+            ..Default::default()
+        };
+        let repository = discovery.discover(&sub_dir)?;
+        assert_eq!(repository.path(), env::current_dir()?.join("tree"));
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_discover_ceiling_dirs() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let sub_dir = temp_dir.join("sub").join("sub_sub");
+        fs::create_dir_all(&sub_dir)?;
+
+        let discovery = Discovery {
+            ceiling_dirs: vec![temp_dir.join("sub")],
+            ..Default::default()
+        };
+        let result = discovery.discover(&sub_dir);
+        assert!(matches!(result, Err(Error::NotFoundRepository)));
+
+        // Starting outside the ceiling still finds it
+        let result = discovery.discover(&temp_dir);
+        assert!(result.is_ok());
+
+        // Ceilings are compared canonicalized
+        let discovery = Discovery {
+            ceiling_dirs: vec![temp_dir.join("sub").join("sub_sub").join("..")],
+            ..Default::default()
+        };
+        let result = discovery.discover(&sub_dir);
+        assert!(matches!(result, Err(Error::NotFoundRepository)));
+
+        // The ceiling itself is not searched, like `$HOME/.nss` from `$HOME`
+        let discovery = Discovery {
+            ceiling_dirs: vec![temp_dir.clone()],
+            ..Default::default()
+        };
+        let result = discovery.discover(&temp_dir);
+        assert!(matches!(result, Err(Error::NotFoundRepository)));
+        let result = discovery.discover(&sub_dir);
+        assert!(matches!(result, Err(Error::NotFoundRepository)));
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_discover_nss_dir() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repo_dir = temp_dir.join("repo");
+        let other_dir = temp_dir.join("other");
+        fs::create_dir_all(&other_dir)?;
+        NssRepository::init(repo_dir.clone(), Config::default(), false)?;
+
+        // `.nss` directory given explicitly
+        let discovery = Discovery {
+            nss_dir: Some(repo_dir.join(".nss")),
+            ..Default::default()
+        };
+        let repository = discovery.discover(&other_dir)?;
+        assert_eq!(repository.path(), repo_dir);
+
+        // Explicit work tree
+        let discovery = Discovery {
+            nss_dir: Some(repo_dir.join(".nss")),
+            work_tree: Some(other_dir.clone()),
+            ..Default::default()
+        };
+        let repository = discovery.discover(&temp_dir)?;
+        assert_eq!(repository.path(), other_dir);
+        assert_eq!(repository.nss_dir(), repo_dir.join(".nss"));
+
+        // Not a repository
+        let discovery = Discovery {
+            nss_dir: Some(other_dir.clone()),
+            ..Default::default()
+        };
+        assert!(discovery.discover(&temp_dir).is_err());
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_discover_nss_file() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repo_dir = temp_dir.join("repo");
+        let linked_dir = temp_dir.join("linked");
+        fs::create_dir_all(linked_dir.join("sub"))?;
+        NssRepository::init(repo_dir.clone(), Config::default(), false)?;
+
+        fs::write(linked_dir.join(".nss"), "nssdir: ../repo/.nss\n")?;
+
+        let repository = Discovery::default().discover(linked_dir.join("sub"))?;
+        assert_eq!(repository.path(), linked_dir);
+        assert_eq!(repository.nss_dir(), linked_dir.join("../repo/.nss"));
+
+        // Broken pointer
+        fs::write(linked_dir.join(".nss"), "nssdir: ../missing\n")?;
+        let result = Discovery::default().discover(&linked_dir);
+        assert!(matches!(result, Err(Error::NotFoundRepository)));
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_discover_bare_repository() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let bare_dir = temp_dir.join("bare");
+        NssRepository::init_bare(bare_dir.clone(), Config::default(), false)?;
+
+        let repository = Discovery::default().discover(bare_dir.join("objects"))?;
+        assert!(repository.is_bare());
+        assert_eq!(repository.path(), bare_dir);
+
+        let discovery = Discovery {
+            nss_dir: Some(bare_dir.clone()),
+            ..Default::default()
+        };
+        assert!(discovery.discover(&temp_dir)?.is_bare());
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+}
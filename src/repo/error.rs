@@ -1,5 +1,7 @@
+use super::database::PrefixCandidate;
 use crate::nss_io::error::Error as NssIoError;
 use crate::struct_set::error::Error as NssStructError;
+use std::path::PathBuf;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -7,9 +9,15 @@ pub enum Error {
     #[error("Detached head")]
     DetachHead,
 
+    #[error("Bookmark {0} has no commit yet")]
+    UnbornBookmark(String),
+
     #[error("Dismatch hash bookmark {0}")]
     DontMatchHashAtBookmarker(String),
 
+    #[error("Bookmark {0} is updated twice in one transaction")]
+    DuplicateBookmarkUpdate(String),
+
     #[error("{0}")]
     NssFileSystem(#[from] NssIoError),
 
@@ -30,4 +38,73 @@ pub enum Error {
 
     #[error("No nss repository (or any of the parent directories): .nss")]
     NotFoundRepository,
+
+    #[error("Already exists nss repository: {}", .0.display())]
+    AlreadyExistsRepository(PathBuf),
+
+    #[error("Repository is locked by another process: {}", .0.display())]
+    RepositoryLocked(PathBuf),
+
+    #[error("Unsupported repository format version: {0}")]
+    UnsupportedFormatVersion(u32),
+
+    #[error("No migration from repository format version {0}")]
+    NotFoundMigration(u32),
+
+    #[error("Repository format version {0} does not allow {1}, migrate it first")]
+    FormatVersionTooOld(u32, String),
+
+    #[error("Hash algorithm {0} does not match the repository's {1}")]
+    MismatchedHashAlgorithm(String, String),
+
+    #[error("Object encoding {0} does not match the repository's {1}")]
+    MismatchedObjectEncoding(String, String),
+
+    #[error("Already exists worktree: {0}")]
+    AlreadyExistsWorktree(String),
+
+    #[error("No existed worktree: {0}")]
+    NotFoundWorktree(String),
+
+    #[error("Invalid worktree name: {0}")]
+    InvalidWorktreeName(String),
+
+    #[error("{0} is not empty")]
+    NotEmptyWorktreePath(PathBuf),
+
+    #[error("Worktree {0} has uncommitted or untracked changes, force to remove it")]
+    DirtyWorktree(String),
+
+    #[error("Broken pack: {0}")]
+    InvalidPack(String),
+
+    #[error("Broken reflog entry: {0}")]
+    InvalidReflog(String),
+
+    #[error("No large object {0} in the store")]
+    NotFoundLargeObject(String),
+
+    #[error("Large object {0} does not match its pointer")]
+    CorruptLargeObject(String),
+
+    #[error(
+        "Short hash {0} is ambiguous, candidates:{}",
+        .1.iter().map(|c| format!("\n  {}", c)).collect::<String>()
+    )]
+    AmbiguousPrefix(String, Vec<PrefixCandidate>),
+
+    #[error("No existed tag: {0}")]
+    NotFoundTag(String),
+
+    #[error("Already exists tag: {0}")]
+    AlreadyExistsTag(String),
+
+    #[error("Invalid tag name: {0:?}")]
+    InvalidTagName(String),
+
+    #[error("No such revision: {0}")]
+    NotFoundRevision(String),
+
+    #[error("Bookmark {0} is already checked out at {}", .1.display())]
+    BookmarkCheckedOut(String, PathBuf),
 }
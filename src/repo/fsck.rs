@@ -0,0 +1,536 @@
+//! Object database integrity verification
+//!
+//! Checks every loose and packed object and reference.
+//!     - the content hashes to the path it is stored under
+//!     - the content parses as an object
+//!     - every tree entry, commit tree, commit parent and tag target exists
+//!     - HEAD, bookmarks and tags point at existing objects, tag files parse
+//!     - content behind large-file pointers is in the large-object store
+//!       with the size and hash of the pointer
+//!     Objects not reachable from HEAD, any bookmark or any tag are reported as
+//!     unreachable, and those no other object refers to as dangling.
+//!
+
+// Std
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+// Internal
+use super::error::Error;
+use super::large::LargePointer;
+use super::repository::{
+    HeadRepository, NssRepository, PathRepository, Repository, BOOKMARK_NAME, HEAD_NAME, TAG_NAME,
+};
+use crate::nss_io::lock::is_lock_path;
+use crate::struct_set::{Hashable, Head, Object};
+
+/// **FsckIssue Enum**
+///
+/// One problem found in the object database.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsckIssue {
+    /// The content does not hash to the name it is stored under.
+    HashMismatch { hash: String, actual: String },
+    /// The object could not be read or parsed.
+    Unreadable { hash: String, reason: String },
+    /// An object or reference points at an object that does not exist.
+    MissingObject { from: String, to: String },
+    /// A file in the object directory that is not named by a hash.
+    StrayFile(PathBuf),
+    /// A large-file pointer whose content is not in the store.
+    MissingLargeObject { hash: String, key: String },
+    /// Stored large-file content that no longer matches its pointer.
+    CorruptLargeObject { hash: String, key: String },
+    /// A HEAD, bookmark or tag file that could not be read.
+    BrokenReference { name: String, reason: String },
+}
+
+/// **FsckReport Struct**
+///
+/// Result of [`fsck`]. Hashes are sorted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FsckReport {
+    pub checked: usize,
+    pub issues: Vec<FsckIssue>,
+    pub unreachable: Vec<String>,
+    pub dangling: Vec<String>,
+}
+
+impl FsckReport {
+    /// No corruption or missing objects. Unreachable objects are not errors.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Verify the whole object database of `repository`.
+pub fn fsck(repository: &NssRepository) -> Result<FsckReport, Error> {
+    let mut report = FsckReport::default();
+
+    // Outgoing references of every readable object
+    let mut graph: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut pointers: Vec<(String, LargePointer)> = vec![];
+    for (hash, path) in repository.objects().loose_objects()? {
+        if !repository.hash_algorithm().is_hex_hash(&hash) {
+            report.issues.push(FsckIssue::StrayFile(path));
+            continue;
+        }
+        report.checked += 1;
+
+        // From disk, a cached copy would hide corruption
+        let object = match repository.objects().read_uncached(&hash) {
+            Ok(o) => o,
+            Err(e) => {
+                report.issues.push(FsckIssue::Unreadable {
+                    hash,
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+
+        let actual = hex::encode(object.to_hash_with(repository.hash_algorithm()));
+        if actual != hash {
+            report.issues.push(FsckIssue::HashMismatch {
+                hash: hash.clone(),
+                actual,
+            });
+        }
+
+        pointers.extend(large_pointer(&hash, &object));
+        graph.insert(hash, references(&object));
+    }
+
+    // Packed objects without a loose copy
+    for hash in repository.objects().packed_objects()? {
+        if graph.contains_key(&hash) {
+            continue;
+        }
+        report.checked += 1;
+
+        match repository.objects().read_uncached(&hash) {
+            Ok(object) => {
+                pointers.extend(large_pointer(&hash, &object));
+                graph.insert(hash, references(&object));
+            }
+            Err(e) => report.issues.push(FsckIssue::Unreadable {
+                hash,
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    // Content of large files
+    for (hash, pointer) in pointers {
+        let key = pointer.key.clone();
+        match repository.large_objects().verify(&pointer) {
+            Ok(true) => {}
+            Ok(false) => report
+                .issues
+                .push(FsckIssue::CorruptLargeObject { hash, key }),
+            Err(Error::NotFoundLargeObject(_)) => report
+                .issues
+                .push(FsckIssue::MissingLargeObject { hash, key }),
+            Err(e) => report.issues.push(FsckIssue::Unreadable {
+                hash,
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    // Broken links between objects
+    let mut referenced: BTreeSet<&String> = BTreeSet::new();
+    for (from, tos) in &graph {
+        for to in tos {
+            referenced.insert(to);
+            if !graph.contains_key(to) {
+                report.issues.push(FsckIssue::MissingObject {
+                    from: from.clone(),
+                    to: to.clone(),
+                });
+            }
+        }
+    }
+
+    // Broken references
+    let mut roots = vec![];
+    for (name, hash) in ref_roots(repository, &mut report.issues)? {
+        match graph.contains_key(&hash) {
+            true => roots.push(hash),
+            false => report.issues.push(FsckIssue::MissingObject {
+                from: name,
+                to: hash,
+            }),
+        }
+    }
+
+    let reachable = reachable_from(roots, |hash| graph.get(hash).cloned().unwrap_or_default());
+    for hash in graph.keys() {
+        if reachable.contains(hash) {
+            continue;
+        }
+        report.unreachable.push(hash.clone());
+        if !referenced.contains(hash) {
+            report.dangling.push(hash.clone());
+        }
+    }
+
+    Ok(report)
+}
+
+/// Hashes an object refers to: a commit's tree and parents, a tree's entries,
+/// a tag's target.
+pub(crate) fn references(object: &Object) -> Vec<String> {
+    object.object_ids().iter().map(|id| id.to_hex()).collect()
+}
+
+fn large_pointer(hash: &str, object: &Object) -> Option<(String, LargePointer)> {
+    match object {
+        Object::Blob(blob) => LargePointer::from_blob(blob).map(|p| (hash.to_string(), p)),
+        _ => None,
+    }
+}
+
+/// Every hash reachable from `roots`, following `children`.
+pub(crate) fn reachable_from<F>(roots: Vec<String>, mut children: F) -> BTreeSet<String>
+where
+    F: FnMut(&str) -> Vec<String>,
+{
+    let mut seen: BTreeSet<String> = BTreeSet::new();
+    let mut queue: VecDeque<String> = roots.into();
+
+    while let Some(hash) = queue.pop_front() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        queue.extend(children(&hash).into_iter().filter(|h| !seen.contains(h)));
+    }
+
+    seen
+}
+
+/// `(name, hash)` of every detached HEAD (main and linked worktrees), every
+/// bookmark and every tag. Empty bookmark files are skipped, unreadable
+/// HEADs, bookmarks and tags are pushed to `broken` instead.
+pub(crate) fn ref_roots(
+    repository: &NssRepository,
+    broken: &mut Vec<FsckIssue>,
+) -> Result<Vec<(String, String)>, Error> {
+    let mut roots = vec![];
+
+    let mut heads = vec![(
+        HEAD_NAME.to_string(),
+        repository.common_dir().join(HEAD_NAME),
+    )];
+    for worktree in repository.worktrees()? {
+        heads.push((
+            format!("worktrees/{}/{}", worktree.name, HEAD_NAME),
+            worktree.nss_dir.join(HEAD_NAME),
+        ));
+    }
+    for (name, path) in heads {
+        if !path.is_file() {
+            continue;
+        }
+        match HeadRepository::from(path).read() {
+            Ok(Head::ObjectHash(hash)) => roots.push((name, hash.to_hex())),
+            Ok(Head::Bookmarker(_)) => {}
+            Err(e) => broken.push(FsckIssue::BrokenReference {
+                name,
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    let bookmarks_dir = repository.common_dir().join(BOOKMARK_NAME);
+    let mut names = vec![];
+    bookmark_names(&bookmarks_dir, &bookmarks_dir, &mut names)?;
+    for name in names {
+        let bookmark = repository.bookmark().read(&name);
+        let name = format!("{}/{}", BOOKMARK_NAME, name);
+        match bookmark {
+            Ok(bookmark) => roots.push((name, bookmark.hash.to_hex())),
+            Err(Error::UnbornBookmark(_)) => {}
+            Err(e) => broken.push(FsckIssue::BrokenReference {
+                name,
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    for name in repository.tags().names()? {
+        let tag = repository.tags().read(&name);
+        let name = format!("{}/{}", TAG_NAME, name);
+        match tag {
+            Ok(hash) => roots.push((name, hash.to_hex())),
+            Err(e) => broken.push(FsckIssue::BrokenReference {
+                name,
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    Ok(roots)
+}
+
+pub(crate) fn bookmark_names(
+    base: &Path,
+    dir: &Path,
+    names: &mut Vec<String>,
+) -> Result<(), Error> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for dir_entry in dir.read_dir()? {
+        let path = dir_entry?.path();
+        if path.is_dir() {
+            bookmark_names(base, &path, names)?;
+            continue;
+        }
+        if is_lock_path(&path) {
+            continue;
+        }
+        let name = path
+            .strip_prefix(base)
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        names.push(name);
+    }
+    names.sort();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::config::Config;
+    use crate::struct_set::{Blob, BookMark, Commit, Entry, ObjectId, Tree};
+
+    use anyhow::Result;
+    use std::ffi::OsString;
+    use std::fs;
+    use testdir::testdir;
+
+    fn blob(content: &str) -> Object {
+        Object::Blob(Blob {
+            content: content.as_bytes().to_vec(),
+        })
+    }
+
+    fn tree(name: &str, object: &Object) -> Object {
+        Object::Tree(Tree::from_entries(vec![Entry {
+            mode: 0o100644,
+            name: OsString::from(name),
+            hash: object.object_id(),
+        }]))
+    }
+
+    fn commit(tree: &Object, parents: Vec<ObjectId>) -> Object {
+        let commit =
+            Commit::new(tree.object_id(), parents, "noshishi", "noshishi", "message").unwrap();
+        Object::Commit(commit)
+    }
+
+    fn write_bookmark(repository: &NssRepository, name: &str, object: &Object) -> Result<()> {
+        fs::File::create(repository.common_dir().join("bookmarks").join(name))?;
+        repository
+            .bookmark()
+            .write(BookMark::new(PathBuf::from(name), object.object_id()))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fsck_healthy_repository() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let blob1 = blob("first");
+        let tree1 = tree("first.rs", &blob1);
+        let commit1 = commit(&tree1, vec![]);
+        let blob2 = blob("second");
+        let tree2 = tree("first.rs", &blob2);
+        let commit2 = commit(&tree2, vec![commit1.object_id()]);
+        for object in [&blob1, &tree1, &commit1, &blob2, &tree2, &commit2] {
+            repository.objects().write(object.clone())?;
+        }
+        write_bookmark(&repository, "main", &commit2)?;
+
+        // Tag objects are only reachable from their tag
+        repository.create_annotated_tag("v1", commit1.object_id(), "first")?;
+
+        let report = fsck(&repository)?;
+        assert!(report.is_ok());
+        assert_eq!(report.checked, 7);
+        assert!(report.unreachable.is_empty());
+        assert!(report.dangling.is_empty());
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fsck_broken_repository() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let blob1 = blob("first");
+        let tree1 = tree("first.rs", &blob1);
+        let commit1 = commit(&tree1, vec![]);
+        let lost_blob = blob("lost");
+        let lost_tree = tree("lost.rs", &lost_blob);
+        for object in [&tree1, &commit1, &lost_blob, &lost_tree] {
+            repository.objects().write(object.clone())?;
+        }
+        write_bookmark(&repository, "main", &commit1)?;
+        write_bookmark(&repository, "gone", &blob("never written"))?;
+
+        // Corrupt content under a valid name, and garbage
+        let corrupt = blob("corrupt");
+        let corrupt_hash = hex::encode(corrupt.to_hash());
+        let objects_dir = repository.common_dir().join("objects");
+        fs::create_dir_all(objects_dir.join(&corrupt_hash[..2]))?;
+        fs::write(
+            objects_dir
+                .join(&corrupt_hash[..2])
+                .join(&corrupt_hash[2..]),
+            b"blob 7\0corrupX",
+        )?;
+        fs::write(objects_dir.join(&corrupt_hash[..2]).join("junk"), b"junk")?;
+
+        let report = fsck(&repository)?;
+        assert!(!report.is_ok());
+        assert_eq!(report.checked, 5);
+
+        let blob1_hash = hex::encode(blob1.to_hash());
+        assert!(report.issues.contains(&FsckIssue::MissingObject {
+            from: hex::encode(tree1.to_hash()),
+            to: blob1_hash,
+        }));
+        assert!(report.issues.contains(&FsckIssue::MissingObject {
+            from: "bookmarks/gone".to_string(),
+            to: hex::encode(blob("never written").to_hash()),
+        }));
+        assert!(report
+            .issues
+            .iter()
+            .any(|i| matches!(i, FsckIssue::HashMismatch { hash, .. } if hash == &corrupt_hash)));
+        assert!(report
+            .issues
+            .iter()
+            .any(|i| matches!(i, FsckIssue::StrayFile(p) if p.ends_with("junk"))));
+
+        // lost tree is unreachable and dangling, its blob only unreachable
+        let lost_tree_hash = hex::encode(lost_tree.to_hash());
+        let lost_blob_hash = hex::encode(lost_blob.to_hash());
+        assert!(report.unreachable.contains(&lost_tree_hash));
+        assert!(report.unreachable.contains(&lost_blob_hash));
+        assert!(report.dangling.contains(&lost_tree_hash));
+        assert!(!report.dangling.contains(&lost_blob_hash));
+
+        // Corrupt reference files are reported, the others still checked
+        fs::write(repository.common_dir().join("tags").join("broken"), "junk")?;
+        fs::write(
+            repository.common_dir().join("bookmarks").join("broken"),
+            "junk",
+        )?;
+        fs::write(repository.common_dir().join("HEAD"), "junk")?;
+        let report = fsck(&repository)?;
+        for broken in ["tags/broken", "bookmarks/broken", "HEAD"] {
+            assert!(report
+                .issues
+                .iter()
+                .any(|i| matches!(i, FsckIssue::BrokenReference { name, .. } if name == broken)));
+        }
+        assert!(report.issues.contains(&FsckIssue::MissingObject {
+            from: "bookmarks/gone".to_string(),
+            to: hex::encode(blob("never written").to_hash()),
+        }));
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fsck_detached_head() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let blob1 = blob("first");
+        let tree1 = tree("first.rs", &blob1);
+        let commit1 = commit(&tree1, vec![]);
+        for object in [&blob1, &tree1, &commit1] {
+            repository.objects().write(object.clone())?;
+        }
+        repository
+            .head()
+            .write(Head::ObjectHash(commit1.object_id()))?;
+
+        let report = fsck(&repository)?;
+        assert!(report.is_ok());
+        assert!(report.unreachable.is_empty());
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fsck_large_objects() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let mut config = Config::default();
+        config.add_large_pattern("*.bin");
+        let repository = NssRepository::init(temp_dir.clone(), config, false)?;
+        fs::write(temp_dir.join("model.bin"), vec![1u8; 10_000])?;
+        let hash = repository.write_file(temp_dir.join("model.bin"))?;
+
+        let report = fsck(&repository)?;
+        assert!(report.is_ok());
+
+        let pointer = match repository.objects().read(&hash)? {
+            Object::Blob(blob) => LargePointer::from_blob(&blob).unwrap(),
+            object => panic!("unexpected object: {:?}", object),
+        };
+        let key = pointer.key.clone();
+        let stored = repository.large_objects().path(&pointer);
+
+        fs::write(&stored, b"changed")?;
+        let report = fsck(&repository)?;
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue::CorruptLargeObject {
+                hash: hash.clone(),
+                key: key.clone()
+            }]
+        );
+
+        fs::remove_file(&stored)?;
+        let report = fsck(&repository)?;
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue::MissingLargeObject { hash, key }]
+        );
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,428 @@
+//! Garbage collection of unreachable objects
+//!
+//! Objects reachable from HEAD, any bookmark, any tag, any reflog entry or
+//! any INDEX are kept.
+//! Unreachable loose objects older than the grace period are removed,
+//! younger ones are kept since a running command may still refer to them.
+//! Packed objects are followed but never removed.
+//!
+
+// Std
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+// Internal
+use super::error::Error;
+use super::fsck::{reachable_from, ref_roots, references, FsckIssue};
+use super::reflog::is_zero_hash;
+use super::repository::{IndexRepository, NssRepository, Repository, INDEX_NAME};
+use crate::nss_io::file_system::remove_file;
+use crate::struct_set::Object;
+
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+/// **GcOptions Struct**
+///
+/// `dry_run` only lists what would be deleted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GcOptions {
+    pub grace_period: Duration,
+    pub dry_run: bool,
+}
+
+impl Default for GcOptions {
+    /// Two weeks of grace, like git's `gc.pruneExpire`.
+    fn default() -> Self {
+        Self {
+            grace_period: DEFAULT_GRACE_PERIOD,
+            dry_run: false,
+        }
+    }
+}
+
+/// **GcReport Struct**
+///
+/// Hashes are sorted. `pruned` lists what was (or in a dry run, would be)
+/// deleted, `recent` the unreachable objects kept by the grace period.
+/// `broken` lists the references that could not be read, nothing is
+/// pruned while there are any.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GcReport {
+    pub reachable: usize,
+    pub pruned: Vec<String>,
+    pub recent: Vec<String>,
+    pub broken: Vec<FsckIssue>,
+}
+
+/// Prune unreachable loose objects of `repository`.
+///
+/// Fails without deleting anything when a reachable object cannot be
+/// read, since its children would otherwise look unreachable. For the same
+/// reason nothing is deleted while a reference cannot be read.
+pub fn gc(repository: &NssRepository, options: &GcOptions) -> Result<GcReport, Error> {
+    let mut broken = vec![];
+    let mut roots: Vec<String> = ref_roots(repository, &mut broken)?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect();
+    // Staged and tree entries known to be blobs are never read, they
+    // refer to nothing
+    let mut blobs: HashSet<String> = index_roots(repository)?.into_iter().collect();
+    roots.extend(blobs.iter().cloned());
+    roots.extend(reflog_roots(repository)?);
+
+    let loose_objects = repository.objects().loose_objects()?;
+    let mut stored: BTreeSet<String> = repository.objects().packed_objects()?.into_iter().collect();
+    stored.extend(loose_objects.iter().map(|(h, _)| h.clone()));
+
+    let mut failure: Option<Error> = None;
+    let reachable = reachable_from(roots, |hash| {
+        if failure.is_some() || !stored.contains(hash) || blobs.contains(hash) {
+            return vec![];
+        }
+        // Uncached, each object is visited once
+        match repository.objects().read_uncached(hash) {
+            Ok(object) => {
+                if let Object::Tree(tree) = &object {
+                    blobs.extend(
+                        tree.entries
+                            .iter()
+                            .filter(|e| e.as_type() == "blob")
+                            .map(|e| e.hash.to_hex()),
+                    );
+                }
+                references(&object)
+            }
+            Err(e) => {
+                failure = Some(e);
+                vec![]
+            }
+        }
+    });
+    if let Some(e) = failure {
+        return Err(e);
+    }
+
+    let mut report = GcReport {
+        reachable: reachable.len(),
+        broken,
+        ..Default::default()
+    };
+    if !report.broken.is_empty() {
+        return Ok(report);
+    }
+
+    let now = SystemTime::now();
+    for (hash, path) in loose_objects {
+        if !repository.hash_algorithm().is_hex_hash(&hash) || reachable.contains(&hash) {
+            continue;
+        }
+
+        let modified = path.metadata()?.modified()?;
+        let age = now.duration_since(modified).unwrap_or_default();
+        if age < options.grace_period {
+            report.recent.push(hash);
+            continue;
+        }
+
+        if !options.dry_run {
+            remove_file(&path)?;
+            let dir = path.parent().unwrap();
+            if dir.read_dir()?.next().is_none() {
+                fs::remove_dir(dir)?;
+            }
+        }
+        report.pruned.push(hash);
+    }
+    if !options.dry_run && !report.pruned.is_empty() {
+        repository.objects().clear_cache();
+    }
+
+    Ok(report)
+}
+
+/// Old and new values in the reflogs of the main and every linked worktree.
+fn reflog_roots(repository: &NssRepository) -> Result<Vec<String>, Error> {
+    let mut reflogs = vec![repository.reflog()];
+    for worktree in repository.worktrees()? {
+        reflogs.push(worktree.open().reflog());
+    }
+
+    let mut roots = vec![];
+    for reflog in reflogs {
+        for name in reflog.names()? {
+            for entry in reflog.read(&name)? {
+                roots.extend(
+                    [entry.old, entry.new]
+                        .into_iter()
+                        .filter(|h| !is_zero_hash(h)),
+                );
+            }
+        }
+    }
+
+    Ok(roots)
+}
+
+/// Blob hashes staged in the INDEX of the main and every linked worktree.
+fn index_roots(repository: &NssRepository) -> Result<Vec<String>, Error> {
+    let mut paths = vec![repository.common_dir().join(INDEX_NAME)];
+    for worktree in repository.worktrees()? {
+        paths.push(worktree.nss_dir.join(INDEX_NAME));
+    }
+
+    let mut roots = vec![];
+    for path in paths {
+        if !path.is_file() {
+            continue;
+        }
+        let index = IndexRepository::from(path)
+            .with_hash_algorithm(repository.hash_algorithm())
+            .read()?;
+        roots.extend(index.filemetas.iter().map(|f| f.hash.to_hex()));
+    }
+
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::config::Config;
+    use crate::repo::pack::{repack, RepackOptions};
+    use crate::repo::repository::PathRepository;
+    use crate::struct_set::{Blob, BookMark, Commit, Entry, Hashable, Head, Index, Tree};
+
+    use anyhow::Result;
+    use std::ffi::OsString;
+    use std::fs::File;
+    use std::path::PathBuf;
+    use testdir::testdir;
+
+    fn blob(content: &str) -> Object {
+        Object::Blob(Blob {
+            content: content.as_bytes().to_vec(),
+        })
+    }
+
+    fn object_path(repository: &NssRepository, object: &Object) -> PathBuf {
+        let hash = hex::encode(object.to_hash());
+        repository
+            .common_dir()
+            .join("objects")
+            .join(&hash[..2])
+            .join(&hash[2..])
+    }
+
+    fn age(repository: &NssRepository, object: &Object, secs: u64) -> Result<()> {
+        let file = File::options()
+            .write(true)
+            .open(object_path(repository, object))?;
+        file.set_modified(SystemTime::now() - Duration::from_secs(secs))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+
+        // Reachable from a bookmark
+        let kept_blob = blob("kept");
+        let tree = Object::Tree(Tree::from_entries(vec![Entry {
+            mode: 0o100644,
+            name: OsString::from("kept.rs"),
+            hash: kept_blob.object_id(),
+        }]));
+        let commit = Object::Commit(Commit::new(
+            tree.object_id(),
+            vec![],
+            "noshishi",
+            "noshishi",
+            "initial",
+        )?);
+        File::create(repository.common_dir().join("bookmarks").join("main"))?;
+        repository
+            .bookmark()
+            .write(BookMark::new(PathBuf::from("main"), commit.object_id()))?;
+
+        // Reachable from the index only
+        fs::write(temp_dir.join("staged.rs"), "staged")?;
+        let staged_blob = blob("staged");
+        let mut index = Index::empty();
+        index.add(&repository, temp_dir.join("staged.rs"), None)?;
+        repository.index().write(index)?;
+
+        // Unreachable, old and fresh
+        let old_blob = blob("old");
+        let fresh_blob = blob("fresh");
+
+        for object in [
+            &kept_blob,
+            &tree,
+            &commit,
+            &staged_blob,
+            &old_blob,
+            &fresh_blob,
+        ] {
+            repository.objects().write(object.clone())?;
+            age(&repository, object, 30 * 24 * 60 * 60)?;
+        }
+        age(&repository, &fresh_blob, 60)?;
+
+        // Dry run deletes nothing
+        let options = GcOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let report = gc(&repository, &options)?;
+        assert_eq!(report.reachable, 4);
+        assert_eq!(report.pruned, vec![hex::encode(old_blob.to_hash())]);
+        assert_eq!(report.recent, vec![hex::encode(fresh_blob.to_hash())]);
+        assert!(object_path(&repository, &old_blob).exists());
+
+        let report = gc(&repository, &GcOptions::default())?;
+        assert_eq!(report.pruned, vec![hex::encode(old_blob.to_hash())]);
+        assert!(!object_path(&repository, &old_blob).exists());
+        assert_eq!(repository.objects().cache_stats().entries, 0);
+        assert!(!object_path(&repository, &old_blob)
+            .parent()
+            .unwrap()
+            .exists());
+        for object in [&kept_blob, &tree, &commit, &staged_blob, &fresh_blob] {
+            assert!(object_path(&repository, object).exists());
+        }
+
+        // No grace period
+        let options = GcOptions {
+            grace_period: Duration::ZERO,
+            ..Default::default()
+        };
+        let report = gc(&repository, &options)?;
+        assert_eq!(report.pruned, vec![hex::encode(fresh_blob.to_hash())]);
+
+        // Blobs are known by their tree entry and never read
+        fs::write(object_path(&repository, &kept_blob), b"broken")?;
+        assert!(gc(&repository, &GcOptions::default())?.pruned.is_empty());
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_keeps_reflog_objects() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let old_blob = blob("overwritten");
+        let new_blob = blob("current");
+        for object in [&old_blob, &new_blob] {
+            repository.objects().write(object.clone())?;
+            age(&repository, object, 30 * 24 * 60 * 60)?;
+        }
+
+        // Only the reflog still knows the first value
+        for object in [&old_blob, &new_blob] {
+            repository
+                .bookmark()
+                .write(BookMark::new(PathBuf::from("main"), object.object_id()))?;
+        }
+
+        let report = gc(&repository, &GcOptions::default())?;
+        assert!(report.pruned.is_empty());
+        assert!(object_path(&repository, &old_blob).exists());
+
+        // Broken references are reported and block pruning
+        let unreachable = blob("unreachable");
+        repository.objects().write(unreachable.clone())?;
+        age(&repository, &unreachable, 30 * 24 * 60 * 60)?;
+        let tag = repository.common_dir().join("tags").join("broken");
+        fs::write(&tag, "junk")?;
+        let bookmark = repository.common_dir().join("bookmarks").join("broken");
+        fs::write(&bookmark, "junk")?;
+        let report = gc(&repository, &GcOptions::default())?;
+        assert_eq!(report.broken.len(), 2);
+        assert!(report.pruned.is_empty());
+        assert!(object_path(&repository, &unreachable).exists());
+
+        fs::remove_file(tag)?;
+        fs::remove_file(bookmark)?;
+        let report = gc(&repository, &GcOptions::default())?;
+        assert_eq!(report.pruned, vec![hex::encode(unreachable.to_hash())]);
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_follows_packed_objects() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let loose_blob = blob("loose");
+        let tree = Object::Tree(Tree::from_entries(vec![Entry {
+            mode: 0o100644,
+            name: OsString::from("loose.rs"),
+            hash: loose_blob.object_id(),
+        }]));
+        repository
+            .head()
+            .write(Head::ObjectHash(tree.object_id()))?;
+
+        // Tree is packed, its blob stays loose
+        repository.objects().write(tree.clone())?;
+        repack(&repository, &RepackOptions::default())?;
+        repository.objects().write(loose_blob.clone())?;
+        age(&repository, &loose_blob, 30 * 24 * 60 * 60)?;
+
+        let report = gc(&repository, &GcOptions::default())?;
+        assert!(report.pruned.is_empty());
+        assert_eq!(report.reachable, 2);
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_unreadable_reachable_object() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let commit = Object::Commit(Commit::new(
+            "c192349d0ee530038e5d925fdd701652ca755ba8".parse()?,
+            vec![],
+            "noshishi",
+            "noshishi",
+            "initial",
+        )?);
+        repository.objects().write(commit.clone())?;
+        fs::write(object_path(&repository, &commit), b"broken")?;
+        repository
+            .head()
+            .write(Head::ObjectHash(commit.object_id()))?;
+
+        assert!(gc(&repository, &GcOptions::default()).is_err());
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+}
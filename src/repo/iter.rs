@@ -0,0 +1,252 @@
+//! Object enumeration
+//!
+//! [`ObjectIds`] walks every object of an [`ObjectRepository`] without
+//! collecting them first.
+//!     - loose objects, one `objects/xx/` directory at a time
+//!     - packed objects, one pack at a time, skipping those also stored
+//!       loose or in an earlier pack
+//!     Files that are not object names, e.g. temp files, are skipped.
+//!     Ids come sorted within a directory or pack, not overall.
+//!
+
+// Std
+use std::path::PathBuf;
+use std::vec::IntoIter;
+
+// Internal
+use super::database::ObjectDatabase;
+use super::error::Error;
+use super::pack::Pack;
+use super::repository::ObjectRepository;
+use crate::struct_set::ObjectId;
+
+/// **ObjectIds Struct**
+///
+/// Lazy iterator over the ids of every stored object, see
+/// [`ObjectRepository::object_ids`]. Stops after the first error.
+#[derive(Debug)]
+pub struct ObjectIds {
+    objects: ObjectRepository,
+    object_type: Option<String>,
+    // Loose directories not listed yet, `None` before the first one
+    dirs: Option<IntoIter<PathBuf>>,
+    // Packs not listed yet, `None` until the loose objects are done
+    packs: Option<IntoIter<Pack>>,
+    listed_packs: Vec<Pack>,
+    pending: IntoIter<String>,
+    finished: bool,
+}
+
+impl ObjectIds {
+    pub(crate) fn new(objects: ObjectRepository) -> Self {
+        Self {
+            objects,
+            object_type: None,
+            dirs: None,
+            packs: None,
+            listed_packs: vec![],
+            pending: Vec::new().into_iter(),
+            finished: false,
+        }
+    }
+
+    /// Only objects of `object_type`, e.g. `"commit"`.
+    ///
+    /// The type is read from the header of each loose object; packed
+    /// objects are read whole.
+    pub fn with_type<S: Into<String>>(mut self, object_type: S) -> Self {
+        self.object_type = Some(object_type.into());
+        self
+    }
+
+    /// Queue the next directory or pack, `false` once everything is listed.
+    fn list_next(&mut self) -> Result<bool, Error> {
+        let dirs = match &mut self.dirs {
+            Some(dirs) => dirs,
+            None => self.dirs.insert(self.objects.loose_dirs()?.into_iter()),
+        };
+        if let Some(dir) = dirs.next() {
+            self.pending = self.objects.loose_hashes(&dir)?.into_iter();
+            return Ok(true);
+        }
+
+        let packs = match &mut self.packs {
+            Some(packs) => packs,
+            None => self
+                .packs
+                .insert(self.objects.packs()?.to_vec().into_iter()),
+        };
+        let Some(pack) = packs.next() else {
+            return Ok(false);
+        };
+
+        let hashes: Vec<String> = pack
+            .hashes()
+            .into_iter()
+            .filter(|h| !self.objects.is_loose(h))
+            .filter(|h| !self.listed_packs.iter().any(|p| p.contains(h)))
+            .collect();
+        self.pending = hashes.into_iter();
+        self.listed_packs.push(pack);
+
+        Ok(true)
+    }
+
+    fn next_id(&mut self) -> Result<Option<ObjectId>, Error> {
+        loop {
+            let Some(hash) = self.pending.next() else {
+                match self.list_next()? {
+                    true => continue,
+                    false => return Ok(None),
+                }
+            };
+
+            if let Some(object_type) = &self.object_type {
+                if &self.objects.object_info(&hash)?.object_type != object_type {
+                    continue;
+                }
+            }
+
+            return Ok(Some(ObjectId::from_hex(&hash)?));
+        }
+    }
+}
+
+impl Iterator for ObjectIds {
+    type Item = Result<ObjectId, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let next = self.next_id();
+        if !matches!(next, Ok(Some(_))) {
+            self.finished = true;
+        }
+
+        next.transpose()
+    }
+}
+
+impl ObjectRepository {
+    /// Every stored object id, loose and packed, see [`crate::repo::iter`].
+    pub fn object_ids(&self) -> ObjectIds {
+        ObjectIds::new(self.clone())
+    }
+}
+
+/// Object ids of `database` of `object_type`, or of any type on `None`,
+/// for backends without a lazy walk.
+pub(crate) fn collected_ids<'a, D: ObjectDatabase + ?Sized>(
+    database: &'a D,
+    object_type: Option<&'a str>,
+) -> Box<dyn Iterator<Item = Result<ObjectId, Error>> + 'a> {
+    let hashes = match database.hashes() {
+        Ok(hashes) => hashes,
+        Err(e) => return Box::new(std::iter::once(Err(e))),
+    };
+
+    Box::new(hashes.into_iter().filter_map(move |hash| {
+        if let Some(object_type) = object_type {
+            match database.object_info(&hash) {
+                Ok(info) if info.object_type != object_type => return None,
+                Err(e) => return Some(Err(e)),
+                Ok(_) => {}
+            }
+        }
+
+        Some(ObjectId::from_hex(&hash).map_err(Error::from))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::config::Config;
+    use crate::repo::pack::{repack, RepackOptions};
+    use crate::repo::repository::{NssRepository, PathRepository};
+    use crate::struct_set::{Blob, Commit, Entry, Hashable, Object, Tree};
+
+    use anyhow::Result;
+    use std::collections::BTreeSet;
+    use std::ffi::OsString;
+    use std::fs;
+    use testdir::testdir;
+
+    fn blob(content: &str) -> Object {
+        Object::Blob(Blob {
+            content: content.as_bytes().to_vec(),
+        })
+    }
+
+    #[test]
+    fn test_object_ids() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        assert_eq!(repository.objects().object_ids().count(), 0);
+
+        let first = blob("first");
+        let tree = Object::Tree(Tree::from_entries(vec![Entry {
+            mode: 0o100644,
+            name: OsString::from("first.rs"),
+            hash: first.object_id(),
+        }]));
+        let commit = Object::Commit(Commit::new(
+            tree.object_id(),
+            vec![],
+            "nopeNoshihsi",
+            "nopeNoshihsi",
+            "initial",
+        )?);
+        for object in [&first, &tree, &commit] {
+            repository.objects().write(object.clone())?;
+        }
+        repack(&repository, &RepackOptions::default())?;
+
+        // Loose, and both loose and packed
+        let second = blob("second");
+        repository.objects().write(second.clone())?;
+        let first_hash = first.object_id().to_hex();
+        let (d, f) = first_hash.split_at(2);
+        let objects_dir = repository.common_dir().join("objects");
+        fs::create_dir_all(objects_dir.join(d))?;
+        fs::write(objects_dir.join(d).join(f), first.as_bytes())?;
+        fs::write(objects_dir.join(d).join("tmp_junk"), b"junk")?;
+
+        let ids = repository
+            .objects()
+            .object_ids()
+            .collect::<Result<Vec<_>, _>>()?;
+        let expected: BTreeSet<ObjectId> = [&first, &tree, &commit, &second]
+            .iter()
+            .map(|o| o.object_id())
+            .collect();
+        assert_eq!(ids.len(), 4);
+        assert_eq!(ids.into_iter().collect::<BTreeSet<_>>(), expected);
+
+        let blobs = repository
+            .objects()
+            .object_ids()
+            .with_type("blob")
+            .collect::<Result<BTreeSet<_>, _>>()?;
+        assert_eq!(
+            blobs,
+            BTreeSet::from([first.object_id(), second.object_id()])
+        );
+        let commits = repository
+            .objects()
+            .object_ids()
+            .with_type("commit")
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(commits, vec![commit.object_id()]);
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,393 @@
+//! Large objects
+//!
+//! Files matching `large.patterns` of the config keep their content out of
+//! the object database. Their blob is a small pointer instead.
+//!     Pointer blob:
+//!         - nss-large 1
+//!         - hash <algorithm>:<hex>  ... hash of the raw content
+//!         - size <bytes>
+//!         - key <key>               ... where the content is in the store
+//!     Store:
+//!         - .nss/large/<key>        ... key is `xx/yyyy..` of the hash
+//!     Patterns are globs where `*` and `?` do not cross `/`. A pattern
+//!     without `/` matches the file name in any directory.
+//!
+
+// Std
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+// Internal
+use super::error::Error;
+use super::repository::split_hash;
+use super::stream::HashingWriter;
+use crate::nss_io::file_system::{create_dir, rename_new, sync_dir, temp_path};
+use crate::struct_set::{Blob, HashAlgorithm};
+
+pub(crate) const LARGE_NAME: &str = "large";
+const POINTER_HEADER: &str = "nss-large 1";
+
+// Pointers are a few lines, anything bigger is ordinary content
+const MAX_POINTER_SIZE: usize = 256;
+
+/// **LargePointer Struct**
+///
+/// Content of the blob standing in for a large file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LargePointer {
+    pub algorithm: HashAlgorithm,
+    pub hash: String,
+    pub size: u64,
+    pub key: String,
+}
+
+impl LargePointer {
+    /// Pointer for content hashing to `hash`.
+    pub fn new(algorithm: HashAlgorithm, hash: String, size: u64) -> Self {
+        let (dir, file) = split_hash(&hash);
+        let key = format!("{}/{}", dir, file);
+
+        Self {
+            algorithm,
+            hash,
+            size,
+            key,
+        }
+    }
+
+    /// Pointer for the next `len` bytes of `reader`, without storing them.
+    pub fn from_reader<R: Read>(reader: R, len: u64, algorithm: HashAlgorithm) -> io::Result<Self> {
+        let mut hasher = algorithm.hasher();
+        copy_exact(reader, &mut hasher, len)?;
+
+        Ok(Self::new(algorithm, hex::encode(hasher.finalize()), len))
+    }
+
+    /// The pointer held by `blob`, `None` for ordinary content.
+    pub fn from_blob(blob: &Blob) -> Option<Self> {
+        if blob.content.len() > MAX_POINTER_SIZE {
+            return None;
+        }
+        let content = std::str::from_utf8(&blob.content).ok()?;
+        let mut lines = content.lines();
+        if lines.next()? != POINTER_HEADER {
+            return None;
+        }
+
+        let (algorithm, hash) = lines.next()?.strip_prefix("hash ")?.split_once(':')?;
+        let algorithm = [HashAlgorithm::Sha1, HashAlgorithm::Sha256]
+            .into_iter()
+            .find(|a| a.as_str() == algorithm)?;
+        let size = lines.next()?.strip_prefix("size ")?.parse().ok()?;
+        let key = lines.next()?.strip_prefix("key ")?;
+        if !algorithm.is_hex_hash(hash) || lines.next().is_some() {
+            return None;
+        }
+
+        let pointer = Self::new(algorithm, hash.to_string(), size);
+        match pointer.key == key {
+            true => Some(pointer),
+            false => None,
+        }
+    }
+
+    pub fn to_blob(&self) -> Blob {
+        let content = format!(
+            "{}\nhash {}:{}\nsize {}\nkey {}\n",
+            POINTER_HEADER, self.algorithm, self.hash, self.size, self.key
+        );
+
+        Blob {
+            content: content.into_bytes(),
+        }
+    }
+}
+
+/// **LargeObjectStore Struct**
+///
+/// Content of large files, by key, under `.nss/large`.
+#[derive(Debug, Clone)]
+pub struct LargeObjectStore {
+    root: PathBuf,
+    patterns: Vec<String>,
+    hash_algorithm: HashAlgorithm,
+}
+
+impl LargeObjectStore {
+    pub(crate) fn new(root: PathBuf, patterns: Vec<String>, algorithm: HashAlgorithm) -> Self {
+        Self {
+            root,
+            patterns,
+            hash_algorithm: algorithm,
+        }
+    }
+
+    pub fn path(&self, pointer: &LargePointer) -> PathBuf {
+        self.root.join(&pointer.key)
+    }
+
+    /// Whether the file at `relative` (from the work tree root) is stored
+    /// as a pointer.
+    pub fn is_large<P: AsRef<Path>>(&self, relative: P) -> bool {
+        let relative = relative.as_ref().to_string_lossy().replace('\\', "/");
+        let name = relative.rsplit('/').next().unwrap_or_default();
+
+        self.patterns
+            .iter()
+            .any(|pattern| match pattern.contains('/') {
+                true => glob_match(
+                    pattern.trim_start_matches('/').as_bytes(),
+                    relative.as_bytes(),
+                ),
+                false => glob_match(pattern.as_bytes(), name.as_bytes()),
+            })
+    }
+
+    /// Store the next `len` bytes of `reader` and return their pointer.
+    ///
+    /// Content already in the store is kept as is.
+    pub fn store<R: Read>(&self, reader: R, len: u64) -> Result<LargePointer, Error> {
+        create_dir(&self.root)?;
+        let temp = temp_path(&self.root.join("large"));
+
+        let result = self.store_temp(&temp, reader, len).and_then(|pointer| {
+            let path = self.path(&pointer);
+            let dir = path.parent().unwrap();
+            create_dir(dir)?;
+            // Same content under the same key, keep what is there
+            match rename_new(&temp, &path) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                result => result?,
+            }
+            sync_dir(dir)?;
+
+            Ok(pointer)
+        });
+        let _ = fs::remove_file(&temp);
+
+        result
+    }
+
+    fn store_temp<R: Read>(&self, temp: &Path, reader: R, len: u64) -> Result<LargePointer, Error> {
+        let file = OpenOptions::new().write(true).create_new(true).open(temp)?;
+        let mut writer = HashingWriter {
+            inner: file,
+            hasher: self.hash_algorithm.hasher(),
+        };
+        copy_exact(reader, &mut writer, len)?;
+        writer.inner.sync_all()?;
+
+        let hash = hex::encode(writer.hasher.finalize());
+
+        Ok(LargePointer::new(self.hash_algorithm, hash, len))
+    }
+
+    /// Stored content of `pointer`.
+    pub fn open(&self, pointer: &LargePointer) -> Result<File, Error> {
+        let path = self.path(pointer);
+        if !path.is_file() {
+            return Err(Error::NotFoundLargeObject(pointer.key.clone()));
+        }
+
+        Ok(File::open(path)?)
+    }
+
+    /// Copy the content of `pointer` to `target`, e.g. on checkout.
+    ///
+    /// The content is written to a temp file, checked against the pointer
+    /// and only then moved onto `target`, so a crash or a corrupt store
+    /// never leaves a wrong file behind.
+    pub fn checkout<P: AsRef<Path>>(&self, pointer: &LargePointer, target: P) -> Result<(), Error> {
+        let target = target.as_ref();
+        let temp = temp_path(target);
+
+        let result = self.checkout_temp(pointer, &temp).and_then(|()| {
+            fs::rename(&temp, target)?;
+            Ok(sync_dir(target.parent().unwrap_or(Path::new(".")))?)
+        });
+        let _ = fs::remove_file(&temp);
+
+        result
+    }
+
+    fn checkout_temp(&self, pointer: &LargePointer, temp: &Path) -> Result<(), Error> {
+        let content = self.open(pointer)?;
+        if content.metadata()?.len() != pointer.size {
+            return Err(Error::CorruptLargeObject(pointer.key.clone()));
+        }
+
+        let file = OpenOptions::new().write(true).create_new(true).open(temp)?;
+        let mut writer = HashingWriter {
+            inner: file,
+            hasher: pointer.algorithm.hasher(),
+        };
+        copy_exact(content, &mut writer, pointer.size)?;
+        if hex::encode(writer.hasher.finalize()) != pointer.hash {
+            return Err(Error::CorruptLargeObject(pointer.key.clone()));
+        }
+        writer.inner.sync_all()?;
+
+        Ok(())
+    }
+
+    /// Whether the stored content still has the size and hash of `pointer`.
+    pub fn verify(&self, pointer: &LargePointer) -> Result<bool, Error> {
+        let file = self.open(pointer)?;
+        if file.metadata()?.len() != pointer.size {
+            return Ok(false);
+        }
+
+        let actual = LargePointer::from_reader(file, pointer.size, pointer.algorithm)?;
+
+        Ok(actual.hash == pointer.hash)
+    }
+}
+
+fn copy_exact<R: Read, W: Write>(reader: R, writer: &mut W, len: u64) -> io::Result<()> {
+    let copied = io::copy(&mut reader.take(len), writer)?;
+    if copied != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("content has {} bytes, expected {}", copied, len),
+        ));
+    }
+
+    Ok(())
+}
+
+/// `*` matches any run and `?` any one byte, neither matching `/`.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text it has swallowed up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if (c == b'?' && text[t] != b'/') || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) if text[star_t] != b'/' => {
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p;
+                    t = star_t + 1;
+                }
+                _ => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::Result;
+    use testdir::testdir;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*.psd", b"image.psd"));
+        assert!(glob_match(b"assets/*.bin", b"assets/model.bin"));
+        assert!(glob_match(b"data?.csv", b"data1.csv"));
+        assert!(glob_match(b"*", b""));
+        assert!(!glob_match(b"*.psd", b"image.png"));
+        assert!(!glob_match(b"assets/*.bin", b"assets/sub/model.bin"));
+        assert!(!glob_match(b"a?b", b"a/b"));
+    }
+
+    #[test]
+    fn test_large_pointer() {
+        let pointer =
+            LargePointer::from_reader(&b"large content"[..], 13, HashAlgorithm::Sha256).unwrap();
+        assert_eq!(pointer.hash.len(), 64);
+        assert_eq!(
+            pointer.key,
+            format!("{}/{}", &pointer.hash[..2], &pointer.hash[2..])
+        );
+
+        let blob = pointer.to_blob();
+        assert_eq!(LargePointer::from_blob(&blob), Some(pointer.clone()));
+
+        // Ordinary and tampered content is not a pointer
+        let blob = Blob {
+            content: b"nss-large 1\nordinary file".to_vec(),
+        };
+        assert_eq!(LargePointer::from_blob(&blob), None);
+        let mut blob = pointer.to_blob();
+        blob.content = String::from_utf8(blob.content)
+            .unwrap()
+            .replace("key ", "key 00")
+            .into_bytes();
+        assert_eq!(LargePointer::from_blob(&blob), None);
+    }
+
+    #[test]
+    fn test_large_object_store() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let store = LargeObjectStore::new(
+            temp_dir.join(LARGE_NAME),
+            vec!["*.bin".to_string(), "/assets/*.psd".to_string()],
+            HashAlgorithm::Sha1,
+        );
+        assert!(store.is_large("model.bin"));
+        assert!(store.is_large("deep/dir/model.bin"));
+        assert!(store.is_large("assets/cover.psd"));
+        assert!(!store.is_large("other/assets/cover.psd"));
+        assert!(!store.is_large("README.md"));
+
+        let content = vec![7u8; 100_000];
+        let pointer = store.store(&content[..], content.len() as u64)?;
+        assert_eq!(pointer.size, 100_000);
+        assert_eq!(fs::read(store.path(&pointer))?, content);
+        assert!(store.verify(&pointer)?);
+
+        // Storing again is a no-op
+        assert_eq!(store.store(&content[..], content.len() as u64)?, pointer);
+
+        let target = temp_dir.join("checkout.bin");
+        store.checkout(&pointer, &target)?;
+        assert_eq!(fs::read(&target)?, content);
+
+        // Corrupt and missing content, the checked out file is kept
+        fs::write(store.path(&pointer), b"changed")?;
+        assert!(!store.verify(&pointer)?);
+        assert!(matches!(
+            store.checkout(&pointer, &target),
+            Err(Error::CorruptLargeObject(_))
+        ));
+        let mut same_size = content.clone();
+        same_size[0] = 8;
+        fs::write(store.path(&pointer), &same_size)?;
+        assert!(matches!(
+            store.checkout(&pointer, &target),
+            Err(Error::CorruptLargeObject(_))
+        ));
+        assert_eq!(fs::read(&target)?, content);
+        assert_eq!(fs::read_dir(&temp_dir)?.count(), 2);
+        fs::remove_file(store.path(&pointer))?;
+        assert!(matches!(
+            store.verify(&pointer),
+            Err(Error::NotFoundLargeObject(_))
+        ));
+
+        // Short reader
+        assert!(store.store(&b"short"[..], 10).is_err());
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+//! Repository format version and migrations
+//!
+//! `core.repositoryformatversion` in the config marks the on-disk format.
+//!     Versions:
+//!         - 0 ... legacy repository without a marker
+//!         - 1 ... format version recorded in the config
+//!         - 2 ... loose objects may be zlib-compressed
+//!         - 3 ... objects may be stored in pack files
+//!         - 4 ... packed objects may be deltas
+//!         - 5 ... object ids may be SHA-256 (`core.objectformat`)
+//!         - 6 ... annotated tag objects and the `tags` directory
+//!         - 7 ... trees, commits and tags may be git-encoded
+//!           (`core.objectencoding`)
+//!     A repository newer than [`CURRENT_FORMAT_VERSION`] is refused on open.
+//!     Older ones are upgraded one step at a time with [`migrate`].
+//!
+
+// Internal
+use super::config::Config;
+use super::error::Error;
+use super::repository::{NssRepository, Repository, CONFIG_NAME};
+use crate::nss_io::file_system::create_dir;
+use crate::struct_set::ObjectEncoding;
+
+/// Newest format this crate reads and writes.
+pub const CURRENT_FORMAT_VERSION: u32 = 7;
+
+// First format allowing each feature
+const ZLIB_FORMAT_VERSION: u32 = 2;
+pub(crate) const PACK_FORMAT_VERSION: u32 = 3;
+pub(crate) const DELTA_FORMAT_VERSION: u32 = 4;
+pub(crate) const TAG_OBJECT_FORMAT_VERSION: u32 = 6;
+pub(crate) const GIT_ENCODING_FORMAT_VERSION: u32 = 7;
+
+/// **Migration Struct**
+///
+/// One upgrade step from format `from` to format `to`.
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub from: u32,
+    pub to: u32,
+    pub description: &'static str,
+    apply: fn(&NssRepository) -> Result<(), Error>,
+}
+
+impl Migration {
+    pub fn apply(&self, repository: &NssRepository) -> Result<(), Error> {
+        (self.apply)(repository)
+    }
+}
+
+/// Every known upgrade step, oldest first.
+pub fn migrations() -> Vec<Migration> {
+    vec![
+        Migration {
+            from: 0,
+            to: 1,
+            description: "record the repository format version",
+            apply: |_| Ok(()),
+        },
+        // Raw objects stay readable, only older readers must be kept out
+        Migration {
+            from: 1,
+            to: 2,
+            description: "compress new loose objects with zlib",
+            apply: |_| Ok(()),
+        },
+        Migration {
+            from: 2,
+            to: 3,
+            description: "allow objects in pack files",
+            apply: |_| Ok(()),
+        },
+        Migration {
+            from: 3,
+            to: 4,
+            description: "allow deltas in pack files",
+            apply: |_| Ok(()),
+        },
+        // Existing repositories stay SHA-1, the setting only applies on init
+        Migration {
+            from: 4,
+            to: 5,
+            description: "allow SHA-256 object ids",
+            apply: |_| Ok(()),
+        },
+        // Older readers fail on the first tag object
+        Migration {
+            from: 5,
+            to: 6,
+            description: "allow annotated tag objects",
+            apply: |repository| Ok(create_dir(&repository.tags().root)?),
+        },
+        // Existing repositories stay nss-encoded, the setting only applies
+        // on init
+        Migration {
+            from: 6,
+            to: 7,
+            description: "allow git-encoded trees, commits and tags",
+            apply: |_| Ok(()),
+        },
+    ]
+}
+
+/// Format version of the repository, `0` when there is no config yet.
+pub fn format_version(repository: &NssRepository) -> Result<u32, Error> {
+    if !repository.common_dir().join(CONFIG_NAME).exists() {
+        return Ok(0);
+    }
+
+    Ok(repository.config().read()?.repository_format_version())
+}
+
+/// Zlib level of new loose objects, `None` when the format predates
+/// compression and objects must stay raw until [`migrate`]d.
+pub(crate) fn loose_compression(config: &Config) -> Option<u32> {
+    match config.repository_format_version() >= ZLIB_FORMAT_VERSION {
+        true => Some(config.compression()),
+        false => None,
+    }
+}
+
+/// Refuse repositories written by a newer format than this crate knows,
+/// or git-encoded ones whose format predates the encoding.
+pub fn check_format_version(repository: &NssRepository) -> Result<u32, Error> {
+    let version = format_version(repository)?;
+    if version > CURRENT_FORMAT_VERSION {
+        return Err(Error::UnsupportedFormatVersion(version));
+    }
+    if version < GIT_ENCODING_FORMAT_VERSION && repository.object_encoding() == ObjectEncoding::Git
+    {
+        return Err(Error::FormatVersionTooOld(
+            version,
+            "git-encoded objects".to_string(),
+        ));
+    }
+
+    Ok(version)
+}
+
+/// Refuse writing `feature`, first allowed by format `required`, so older
+/// readers are not handed data they cannot parse.
+pub(crate) fn require_format_version(
+    repository: &NssRepository,
+    required: u32,
+    feature: &str,
+) -> Result<(), Error> {
+    match format_version(repository)? {
+        version if version < required => {
+            Err(Error::FormatVersionTooOld(version, feature.to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Upgrade the repository step by step up to `target`.
+///
+/// The config is rewritten after every step, so an interrupted migration
+/// resumes from the last finished step. Returns the applied steps.
+pub fn migrate(repository: &NssRepository, target: u32) -> Result<Vec<Migration>, Error> {
+    // Not `check_format_version`, a too old git-encoded repository must
+    // still be migratable
+    let mut version = format_version(repository)?;
+    if version > CURRENT_FORMAT_VERSION {
+        return Err(Error::UnsupportedFormatVersion(version));
+    }
+    if target > CURRENT_FORMAT_VERSION || target < version {
+        return Err(Error::UnsupportedFormatVersion(target));
+    }
+
+    let all = migrations();
+    let mut applied = vec![];
+    while version < target {
+        let migration = all
+            .iter()
+            .find(|m| m.from == version)
+            .ok_or(Error::NotFoundMigration(version))?;
+
+        migration.apply(repository)?;
+
+        let mut config = repository.config().read()?;
+        config.set_repository_format_version(migration.to);
+        repository.config().write(config)?;
+
+        version = migration.to;
+        applied.push(*migration);
+    }
+
+    Ok(applied)
+}
+
+/// Upgrade the repository to [`CURRENT_FORMAT_VERSION`].
+pub fn migrate_to_current(repository: &NssRepository) -> Result<Vec<Migration>, Error> {
+    migrate(repository, CURRENT_FORMAT_VERSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::config::Config;
+
+    use anyhow::Result;
+    use std::fs;
+    use testdir::testdir;
+
+    #[test]
+    fn test_migrations_chain() {
+        let all = migrations();
+        assert_eq!(all[0].from, 0);
+        assert_eq!(all.last().unwrap().to, CURRENT_FORMAT_VERSION);
+        for pair in all.windows(2) {
+            assert_eq!(pair[0].to, pair[1].from);
+        }
+    }
+
+    #[test]
+    fn test_check_format_version() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        assert_eq!(check_format_version(&repository)?, CURRENT_FORMAT_VERSION);
+
+        let mut config = repository.config().read()?;
+        config.set_repository_format_version(CURRENT_FORMAT_VERSION + 1);
+        repository.config().write(config)?;
+
+        let result = check_format_version(&repository);
+        assert!(matches!(result, Err(Error::UnsupportedFormatVersion(_))));
+
+        let result = NssRepository::open(temp_dir.clone());
+        assert!(matches!(result, Err(Error::UnsupportedFormatVersion(_))));
+
+        // Git-encoded objects need format 7, migrating still works
+        let mut config = repository.config().read()?;
+        config.set_repository_format_version(6);
+        config.set_object_encoding(ObjectEncoding::Git);
+        repository.config().write(config)?;
+        let result = NssRepository::open(temp_dir.clone());
+        assert!(matches!(result, Err(Error::FormatVersionTooOld(6, _))));
+        migrate_to_current(&repository)?;
+        NssRepository::open(temp_dir.clone())?;
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_legacy_repository() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        // Legacy config has no format marker
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        fs::write(
+            repository.common_dir().join("config"),
+            "[user]\nname = \"noshishi\"\n",
+        )?;
+        fs::remove_dir(repository.common_dir().join("tags"))?;
+        assert_eq!(format_version(&repository)?, 0);
+
+        let applied = migrate_to_current(&repository)?;
+        assert_eq!(applied.len(), CURRENT_FORMAT_VERSION as usize);
+        assert_eq!(format_version(&repository)?, CURRENT_FORMAT_VERSION);
+        assert_eq!(repository.config().read()?.username(), "noshishi");
+        assert!(repository.common_dir().join("tags").is_dir());
+
+        // Nothing left to do
+        assert!(migrate_to_current(&repository)?.is_empty());
+
+        // No downgrade
+        let result = migrate(&repository, 0);
+        assert!(matches!(result, Err(Error::UnsupportedFormatVersion(0))));
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+}
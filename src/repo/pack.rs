@@ -0,0 +1,846 @@
+//! Pack files
+//!
+//! Bundles many objects into `objects/pack/pack-<id>.pack` with a sorted
+//! hash index `pack-<id>.idx` next to it.
+//!     Pack:
+//!         - header ... `NPCK`, version (u32 BE), object count (u32 BE)
+//!         - entry  ... kind (u8), size (u32 BE), zlib compressed data
+//!     Index:
+//!         - header ... `NIDX`, version (u32 BE), object count (u32 BE)
+//!         - entry  ... hash, pack offset (u64 BE), sorted by hash
+//!     Entry kinds:
+//!         - 1 ... whole object, `<type> <size>\0<content>`
+//!         - 2 ... delta, base hash and a [`delta`] against the whole
+//!                 object of that base in the same pack
+//!     Hashes are 20 or 32 bytes, after the repository's hash algorithm.
+//!     The size and the compression cover everything after the size.
+//!     Indexes are loaded once per object store and read again after a
+//!     repack or a lookup they cannot answer.
+//!     Packs are written one entry at a time, objects over the big file
+//!     threshold stay loose.
+//!
+
+mod delta;
+
+// Std
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{BufWriter, SeekFrom};
+use std::path::{Path, PathBuf};
+
+// External
+use sha1::{Digest, Sha1};
+
+// Internal
+use super::config::DEFAULT_COMPRESSION;
+use super::database::ObjectInfo;
+use super::error::Error;
+use super::migration::{require_format_version, DELTA_FORMAT_VERSION, PACK_FORMAT_VERSION};
+use super::repository::{NssRepository, OBJECT_NAME};
+use crate::nss_io::file_system::{
+    create_dir, read_content, temp_path, write_content, ReadMode, WriteMode,
+};
+use crate::nss_io::zlib::{compress, decompress};
+use crate::struct_set::{HashAlgorithm, Hashable, Object, ObjectEncoding};
+
+pub(crate) const PACK_DIR_NAME: &str = "pack";
+const PACK_SIGNATURE: &[u8; 4] = b"NPCK";
+const INDEX_SIGNATURE: &[u8; 4] = b"NIDX";
+const PACK_VERSION: u32 = 1;
+const HEADER_SIZE: usize = 12;
+const OFFSET_SIZE: usize = 8;
+
+const KIND_OBJECT: u8 = 1;
+const KIND_DELTA: u8 = 2;
+
+// Guards against reference cycles in a broken pack
+const MAX_READ_DEPTH: usize = 4096;
+
+/// **RepackOptions Struct**
+///
+/// Blobs and trees are stored as deltas against one of the `window`
+/// previous objects of the same type, with chains at most `depth` long.
+/// `depth` 0 disables deltas. Objects larger than `big_file_threshold`
+/// bytes stay loose.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepackOptions {
+    pub depth: usize,
+    pub window: usize,
+    pub big_file_threshold: u64,
+}
+
+impl Default for RepackOptions {
+    /// Same as git's `pack.depth`, `pack.window` and `core.bigFileThreshold`.
+    fn default() -> Self {
+        Self {
+            depth: 50,
+            window: 10,
+            big_file_threshold: 512 * 1024 * 1024,
+        }
+    }
+}
+
+/// **Pack Struct**
+///
+/// One pack file with its index loaded in memory.
+#[derive(Debug, Clone)]
+pub struct Pack {
+    pack_path: PathBuf,
+    hash_algorithm: HashAlgorithm,
+    encoding: ObjectEncoding,
+    entries: Vec<(Vec<u8>, u64)>,
+}
+
+impl Pack {
+    /// Load the index at `idx_path`, the pack is opened on each read.
+    ///
+    /// Like git's, the index does not record its hash algorithm, so it
+    /// must be the repository's.
+    pub fn open<P: AsRef<Path>>(idx_path: P, algorithm: HashAlgorithm) -> Result<Self, Error> {
+        let idx_path = idx_path.as_ref();
+        let broken =
+            |reason: &str| Error::InvalidPack(format!("{}: {}", idx_path.display(), reason));
+
+        let bytes = read_content(idx_path, ReadMode::default())?;
+        let count = read_header(&bytes, INDEX_SIGNATURE).ok_or_else(|| broken("bad header"))?;
+        let body = &bytes[HEADER_SIZE..];
+        let entry_size = algorithm.size() + OFFSET_SIZE;
+        if body.len() != count * entry_size {
+            return Err(broken("truncated index"));
+        }
+
+        let entries: Vec<(Vec<u8>, u64)> = body
+            .chunks_exact(entry_size)
+            .map(|chunk| {
+                let (hash, offset) = chunk.split_at(algorithm.size());
+                (
+                    hash.to_vec(),
+                    u64::from_be_bytes(offset.try_into().unwrap()),
+                )
+            })
+            .collect();
+        if entries.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(broken("index is not sorted"));
+        }
+
+        Ok(Self {
+            pack_path: idx_path.with_extension("pack"),
+            hash_algorithm: algorithm,
+            encoding: ObjectEncoding::default(),
+            entries,
+        })
+    }
+
+    /// Parse trees, commits and tags laid out in `encoding`.
+    pub(crate) fn with_encoding(mut self, encoding: ObjectEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.pack_path
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Every hash in the pack, sorted.
+    pub fn hashes(&self) -> Vec<String> {
+        self.entries.iter().map(|(h, _)| hex::encode(h)).collect()
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.offset(hash).is_some()
+    }
+
+    /// Hashes starting with the hex `prefix`, found by binary search.
+    pub fn hashes_with_prefix(&self, prefix: &str) -> Vec<String> {
+        let prefix = prefix.to_ascii_lowercase();
+        let start = self
+            .entries
+            .partition_point(|(h, _)| hex::encode(h).as_str() < prefix.as_str());
+
+        self.entries[start..]
+            .iter()
+            .map(|(h, _)| hex::encode(h))
+            .take_while(|h| h.starts_with(&prefix))
+            .collect()
+    }
+
+    /// Read the object `hash`, `None` when it is not in this pack.
+    ///
+    /// The content is checked against `hash`.
+    pub fn read(&self, hash: &str) -> Result<Option<Object>, Error> {
+        let offset = match self.offset(hash) {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+
+        let raw = self.read_raw(offset, MAX_READ_DEPTH)?;
+        let object = Object::from_content_with_encoding(raw, self.hash_algorithm, self.encoding)?;
+        if hex::encode(object.to_hash_with(self.hash_algorithm)) != hash {
+            return Err(self.broken(&format!("object {} does not match its hash", hash)));
+        }
+
+        Ok(Some(object))
+    }
+
+    fn offset(&self, hash: &str) -> Option<u64> {
+        let hash = hex::decode(hash).ok()?;
+        if hash.len() != self.hash_algorithm.size() {
+            return None;
+        }
+        let index = self.entries.binary_search_by(|(h, _)| h.cmp(&hash)).ok()?;
+
+        Some(self.entries[index].1)
+    }
+
+    /// Whole object bytes at `offset`, resolving delta chains.
+    fn read_raw(&self, offset: u64, depth: usize) -> Result<Vec<u8>, Error> {
+        let (kind, data) = self.read_entry(offset)?;
+
+        match kind {
+            KIND_OBJECT => Ok(data),
+            KIND_DELTA => {
+                if depth == 0 || data.len() < self.hash_algorithm.size() {
+                    return Err(self.broken("broken delta chain"));
+                }
+                let (base_hash, delta) = data.split_at(self.hash_algorithm.size());
+                let base_offset = self
+                    .offset(&hex::encode(base_hash))
+                    .ok_or_else(|| self.broken("delta base is not in the pack"))?;
+                let base = self.read_raw(base_offset, depth - 1)?;
+
+                delta::apply(&base, delta)
+            }
+            kind => Err(self.broken(&format!("unknown entry kind {}", kind))),
+        }
+    }
+
+    fn read_entry(&self, offset: u64) -> Result<(u8, Vec<u8>), Error> {
+        let mut file = File::open(&self.pack_path)?;
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut head = [0u8; 5];
+        file.read_exact(&mut head)?;
+        let size = u32::from_be_bytes(head[1..].try_into().unwrap()) as usize;
+        let mut data = vec![0u8; size];
+        file.read_exact(&mut data)?;
+
+        Ok((head[0], decompress(&data)?))
+    }
+
+    fn broken(&self, reason: &str) -> Error {
+        Error::InvalidPack(format!("{}: {}", self.pack_path.display(), reason))
+    }
+}
+
+fn read_header(bytes: &[u8], signature: &[u8; 4]) -> Option<usize> {
+    if bytes.len() < HEADER_SIZE || &bytes[..4] != signature {
+        return None;
+    }
+    let version = u32::from_be_bytes(bytes[4..8].try_into().unwrap());
+    if version != PACK_VERSION {
+        return None;
+    }
+
+    Some(u32::from_be_bytes(bytes[8..12].try_into().unwrap()) as usize)
+}
+
+/// Every pack under `objects_dir`, sorted by name.
+pub(crate) fn packs<P: AsRef<Path>>(
+    objects_dir: P,
+    algorithm: HashAlgorithm,
+) -> Result<Vec<Pack>, Error> {
+    let pack_dir = objects_dir.as_ref().join(PACK_DIR_NAME);
+    if !pack_dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut idx_paths = vec![];
+    for dir_entry in pack_dir.read_dir()? {
+        let path = dir_entry?.path();
+        // An index without its pack is an interrupted write
+        if path.extension() == Some("idx".as_ref()) && path.with_extension("pack").is_file() {
+            idx_paths.push(path);
+        }
+    }
+    idx_paths.sort();
+
+    idx_paths
+        .iter()
+        .map(|path| Pack::open(path, algorithm))
+        .collect()
+}
+
+/// Write the objects `entries` into a new pack under `objects_dir` and
+/// return its path with the hashes it holds.
+///
+/// Each object is read through `read` when its turn comes and written to a
+/// temp pack file, so only the delta window is held in memory. An object
+/// whose compressed entry does not fit in the size field is left out.
+/// The pack is renamed into place before its index is written, so readers
+/// never see an index pointing into a missing pack.
+pub(crate) fn write_pack<P, F>(
+    objects_dir: P,
+    entries: &[(String, ObjectInfo)],
+    level: u32,
+    options: &RepackOptions,
+    mut read: F,
+) -> Result<(PathBuf, Vec<String>), Error>
+where
+    P: AsRef<Path>,
+    F: FnMut(&str) -> Result<Vec<u8>, Error>,
+{
+    let pack_dir = objects_dir.as_ref().join(PACK_DIR_NAME);
+    create_dir(&pack_dir)?;
+    let temp = temp_path(&pack_dir.join("pack"));
+
+    let result = write_temp_pack(&temp, entries, level, options, &mut read).and_then(|index| {
+        let mut id = Sha1::new();
+        for (hash, _) in &index {
+            id.update(hash);
+        }
+        let name = format!("pack-{}", hex::encode(id.finalize()));
+        let pack_path = pack_dir.join(&name).with_extension("pack");
+        fs::rename(&temp, &pack_path)?;
+
+        let mut bytes = header(INDEX_SIGNATURE, index.len())?;
+        for (hash, offset) in &index {
+            bytes.extend_from_slice(hash);
+            bytes.extend_from_slice(&offset.to_be_bytes());
+        }
+        write_content(
+            pack_dir.join(&name).with_extension("idx"),
+            &bytes,
+            WriteMode::Atomic,
+        )?;
+
+        Ok((
+            pack_path,
+            index.iter().map(|(h, _)| hex::encode(h)).collect(),
+        ))
+    });
+    let _ = fs::remove_file(&temp);
+
+    result
+}
+
+/// Write the pack entries of `entries` to `temp` and return the hash and
+/// offset of each written one, sorted by hash.
+fn write_temp_pack<F>(
+    temp: &Path,
+    entries: &[(String, ObjectInfo)],
+    level: u32,
+    options: &RepackOptions,
+    read: &mut F,
+) -> Result<Vec<(Vec<u8>, u64)>, Error>
+where
+    F: FnMut(&str) -> Result<Vec<u8>, Error>,
+{
+    let mut file = BufWriter::new(OpenOptions::new().write(true).create_new(true).open(temp)?);
+    // The count is patched once known
+    file.write_all(&header(PACK_SIGNATURE, 0)?)?;
+
+    let mut index = vec![];
+    let mut offset = HEADER_SIZE as u64;
+    let mut window: VecDeque<(&str, Vec<u8>, Vec<u8>, usize)> = VecDeque::new();
+    for (hash, info) in pack_order(entries, options) {
+        let hash_bytes =
+            hex::decode(hash).map_err(|_| Error::InvalidPack(format!("{} is no hash", hash)))?;
+        let raw = read(hash)?;
+        let kind = info.object_type.as_str();
+
+        let base = match is_deltifiable(info, options) {
+            true => best_base(&window, kind, &raw, options),
+            false => None,
+        };
+        let (entry_kind, data, depth) = match base {
+            Some((i, delta)) => {
+                let (_, base_hash, _, base_depth) = &window[i];
+                let data = [&base_hash[..], &delta].concat();
+                (KIND_DELTA, compress(&data, level)?, base_depth + 1)
+            }
+            None => (KIND_OBJECT, compress(&raw, level)?, 0),
+        };
+        // Too large for a pack, stays loose
+        let Ok(size) = size_field(data.len(), "entry") else {
+            continue;
+        };
+
+        file.write_all(&[entry_kind])?;
+        file.write_all(&size.to_be_bytes())?;
+        file.write_all(&data)?;
+        index.push((hash_bytes.clone(), offset));
+        offset += (1 + 4 + data.len()) as u64;
+
+        if is_deltifiable(info, options) {
+            if window.len() == options.window {
+                window.pop_front();
+            }
+            window.push_back((kind, hash_bytes, raw, depth));
+        }
+    }
+
+    let mut file = file.into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start((HEADER_SIZE - 4) as u64))?;
+    file.write_all(&size_field(index.len(), "object count")?.to_be_bytes())?;
+    file.sync_all()?;
+
+    index.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(index)
+}
+
+/// Order in which `entries` are written, each hash once.
+///
+/// Like git, delta candidates come first, sorted by type and then by size,
+/// largest first, so that smaller versions become deltas of bigger ones.
+fn pack_order<'a>(
+    entries: &'a [(String, ObjectInfo)],
+    options: &RepackOptions,
+) -> Vec<(&'a str, &'a ObjectInfo)> {
+    let mut order: Vec<(&str, &ObjectInfo)> =
+        entries.iter().map(|(h, i)| (h.as_str(), i)).collect();
+    order.sort_by(|a, b| a.0.cmp(b.0));
+    order.dedup_by(|a, b| a.0 == b.0);
+    order.sort_by(|a, b| {
+        is_deltifiable(b.1, options)
+            .cmp(&is_deltifiable(a.1, options))
+            .then(a.1.object_type.cmp(&b.1.object_type))
+            .then(b.1.size.cmp(&a.1.size))
+            .then(a.0.cmp(b.0))
+    });
+
+    order
+}
+
+fn is_deltifiable(info: &ObjectInfo, options: &RepackOptions) -> bool {
+    options.depth > 0 && options.window > 0 && matches!(info.object_type.as_str(), "blob" | "tree")
+}
+
+/// Position in `window` and delta of the base worth storing `raw`
+/// against, if any.
+fn best_base(
+    window: &VecDeque<(&str, Vec<u8>, Vec<u8>, usize)>,
+    kind: &str,
+    raw: &[u8],
+    options: &RepackOptions,
+) -> Option<(usize, Vec<u8>)> {
+    let mut best: Option<(usize, Vec<u8>)> = None;
+    for (i, (base_kind, _, base_raw, depth)) in window.iter().enumerate() {
+        if *base_kind != kind || *depth >= options.depth {
+            continue;
+        }
+
+        let delta = delta::encode(base_raw, raw);
+        // Only worth it when clearly smaller than the whole object
+        if delta.len() < raw.len() / 2 && best.as_ref().map_or(true, |(_, d)| delta.len() < d.len())
+        {
+            best = Some((i, delta));
+        }
+    }
+
+    best
+}
+
+fn header(signature: &[u8; 4], count: usize) -> Result<Vec<u8>, Error> {
+    let mut bytes = signature.to_vec();
+    bytes.extend_from_slice(&PACK_VERSION.to_be_bytes());
+    bytes.extend_from_slice(&size_field(count, "object count")?.to_be_bytes());
+
+    Ok(bytes)
+}
+
+/// `len` as a u32 field, refusing what would be silently truncated.
+fn size_field(len: usize, what: &str) -> Result<u32, Error> {
+    u32::try_from(len)
+        .map_err(|_| Error::InvalidPack(format!("{} of {} does not fit in a pack", what, len)))
+}
+
+/// **RepackReport Struct**
+///
+/// `pack` is `None` when there were no loose objects to pack. `kept` lists
+/// the loose objects too large for a pack, sorted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RepackReport {
+    pub packed: usize,
+    pub pack: Option<PathBuf>,
+    pub kept: Vec<String>,
+}
+
+/// Move every loose object of `repository` into one new pack.
+///
+/// Objects are read one at a time, bypassing the cache, and those larger
+/// than `options.big_file_threshold` stay loose. Loose files are removed
+/// only after the pack and its index are on disk.
+/// Fails without touching anything when a loose object cannot be read,
+/// or when the repository format predates packs, or deltas unless
+/// `options` turn them off.
+pub fn repack(repository: &NssRepository, options: &RepackOptions) -> Result<RepackReport, Error> {
+    require_format_version(repository, PACK_FORMAT_VERSION, "pack files")?;
+    if options.depth > 0 && options.window > 0 {
+        require_format_version(repository, DELTA_FORMAT_VERSION, "deltas in pack files")?;
+    }
+
+    let objects = repository.objects();
+    let mut loose_objects: HashMap<String, PathBuf> = objects
+        .loose_objects()?
+        .into_iter()
+        .filter(|(hash, _)| repository.hash_algorithm().is_hex_hash(hash))
+        .collect();
+
+    let mut report = RepackReport::default();
+    let mut entries = vec![];
+    for hash in loose_objects.keys() {
+        let info = objects.object_info(hash)?;
+        match info.size > options.big_file_threshold {
+            true => report.kept.push(hash.clone()),
+            false => entries.push((hash.clone(), info)),
+        }
+    }
+    report.kept.sort();
+    if entries.is_empty() {
+        return Ok(report);
+    }
+
+    let objects_dir = repository.common_dir().join(OBJECT_NAME);
+    let level = objects.compression().unwrap_or(DEFAULT_COMPRESSION);
+    let (pack, packed) = write_pack(objects_dir, &entries, level, options, |hash| {
+        Ok(objects.read_uncached(hash)?.as_bytes())
+    })?;
+    objects.refresh_packs()?;
+
+    for hash in &packed {
+        let path = loose_objects.remove(hash).unwrap();
+        fs::remove_file(&path)?;
+        let dir = path.parent().unwrap();
+        if dir.read_dir()?.next().is_none() {
+            fs::remove_dir(dir)?;
+        }
+    }
+    // Left out of the pack by their compressed size
+    report.kept.extend(
+        entries
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .filter(|h| loose_objects.contains_key(h)),
+    );
+    report.kept.sort();
+    report.packed = packed.len();
+    report.pack = Some(pack);
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::config::Config;
+    use crate::repo::repository::{try_get_objects_path, PathRepository, Repository};
+    use crate::struct_set::Blob;
+
+    use anyhow::Result;
+    use testdir::testdir;
+
+    fn blob(content: &str) -> Object {
+        Object::Blob(Blob {
+            content: content.as_bytes().to_vec(),
+        })
+    }
+
+    fn pack_objects<P: AsRef<Path>>(
+        objects_dir: P,
+        objects: &[Object],
+        algorithm: HashAlgorithm,
+        options: &RepackOptions,
+    ) -> Result<PathBuf, Error> {
+        let entries: Vec<(String, ObjectInfo)> = objects
+            .iter()
+            .map(|o| {
+                let hash = hex::encode(o.to_hash_with(algorithm));
+                (hash, ObjectInfo::from_object(o))
+            })
+            .collect();
+        let (pack_path, _) = write_pack(objects_dir, &entries, 6, options, |hash| {
+            let (i, _) = entries
+                .iter()
+                .enumerate()
+                .find(|(_, e)| e.0 == hash)
+                .unwrap();
+            Ok(objects[i].as_bytes())
+        })?;
+
+        Ok(pack_path)
+    }
+
+    #[test]
+    fn test_write_pack() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let objects: Vec<Object> = (0..50).map(|i| blob(&format!("blob {}", i))).collect();
+        let pack_path = pack_objects(
+            &temp_dir,
+            &objects,
+            HashAlgorithm::Sha1,
+            &RepackOptions::default(),
+        )?;
+        assert!(pack_path.is_file());
+        assert!(pack_path.with_extension("idx").is_file());
+
+        let packs = packs(&temp_dir, HashAlgorithm::Sha1)?;
+        assert_eq!(packs.len(), 1);
+        assert_eq!(packs[0].len(), 50);
+
+        for object in &objects {
+            let hash = hex::encode(object.to_hash());
+            let read = packs[0].read(&hash)?.unwrap();
+            assert_eq!(read.as_bytes(), object.as_bytes());
+            assert_eq!(packs[0].hashes_with_prefix(&hash[..8]), vec![hash]);
+        }
+        assert!(packs[0]
+            .read("0000000000000000000000000000000000000000")?
+            .is_none());
+
+        // Corrupt entry is detected
+        let mut bytes = fs::read(&pack_path)?;
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&pack_path, bytes)?;
+        let results: Vec<_> = objects
+            .iter()
+            .map(|o| packs[0].read(&hex::encode(o.to_hash())))
+            .collect();
+        assert!(results.iter().any(|r| r.is_err()));
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_size_field() {
+        assert_eq!(size_field(u32::MAX as usize, "entry").unwrap(), u32::MAX);
+        assert!(matches!(
+            size_field(u32::MAX as usize + 1, "entry"),
+            Err(Error::InvalidPack(_))
+        ));
+    }
+
+    #[test]
+    fn test_write_pack_deltas() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        // A file growing by one line per version
+        let mut content = String::new();
+        let mut objects = vec![];
+        for i in 0..20 {
+            content.push_str(&format!(
+                "line {} of a tracked file that changes a little\n",
+                i
+            ));
+            objects.push(blob(&content));
+        }
+
+        let whole = RepackOptions {
+            depth: 0,
+            ..Default::default()
+        };
+        let whole_path = pack_objects(
+            temp_dir.join("whole"),
+            &objects,
+            HashAlgorithm::Sha1,
+            &whole,
+        )?;
+        let delta_path = pack_objects(
+            temp_dir.join("delta"),
+            &objects,
+            HashAlgorithm::Sha1,
+            &RepackOptions::default(),
+        )?;
+        assert!(fs::metadata(&delta_path)?.len() < fs::metadata(&whole_path)?.len());
+
+        // Short chains
+        let short = RepackOptions {
+            depth: 2,
+            window: 1,
+            ..Default::default()
+        };
+        let short_path = pack_objects(
+            temp_dir.join("short"),
+            &objects,
+            HashAlgorithm::Sha1,
+            &short,
+        )?;
+
+        for dir in ["whole", "delta", "short"] {
+            let pack = &packs(temp_dir.join(dir), HashAlgorithm::Sha1)?[0];
+            for object in &objects {
+                let read = pack.read(&hex::encode(object.to_hash()))?.unwrap();
+                assert_eq!(read.as_bytes(), object.as_bytes());
+            }
+        }
+        assert!(fs::metadata(&short_path)?.len() < fs::metadata(&whole_path)?.len());
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_pack_sha256() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let objects: Vec<Object> = (0..10)
+            .map(|i| blob(&format!("a sha256 blob with some shared text {}", i)))
+            .collect();
+        pack_objects(
+            &temp_dir,
+            &objects,
+            HashAlgorithm::Sha256,
+            &RepackOptions::default(),
+        )?;
+
+        let pack = &packs(&temp_dir, HashAlgorithm::Sha256)?[0];
+        for object in &objects {
+            let hash = hex::encode(object.to_hash_with(HashAlgorithm::Sha256));
+            assert_eq!(hash.len(), 64);
+            let read = pack.read(&hash)?.unwrap();
+            assert_eq!(read.as_bytes(), object.as_bytes());
+        }
+        assert!(pack.read(&hex::encode(objects[0].to_hash()))?.is_none());
+
+        // Read as SHA-1 the index does not split into whole entries
+        assert!(packs(&temp_dir, HashAlgorithm::Sha1).is_err());
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repack() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let objects: Vec<Object> = (0..10).map(|i| blob(&format!("repack {}", i))).collect();
+        for object in &objects {
+            repository.objects().write(object.clone())?;
+        }
+
+        let report = repack(&repository, &RepackOptions::default())?;
+        assert_eq!(report.packed, 10);
+        assert!(repository.objects().loose_objects()?.is_empty());
+
+        // Reads fall back to the pack
+        for object in &objects {
+            let hash = hex::encode(object.to_hash());
+            let read = repository.objects().read(&hash)?;
+            assert_eq!(read.as_bytes(), object.as_bytes());
+
+            let path = try_get_objects_path(temp_dir.clone(), &hash[..10])?;
+            assert!(path.ends_with(format!("{}/{}", &hash[..2], &hash[2..])));
+        }
+
+        // Rewriting a packed object leaves a fresh loose copy for gc
+        repository.objects().write(objects[0].clone())?;
+        assert_eq!(repository.objects().loose_objects()?.len(), 1);
+
+        // New loose objects go to a second pack
+        repository.objects().write(blob("loose"))?;
+        assert_eq!(repack(&repository, &RepackOptions::default())?.packed, 2);
+        assert_eq!(
+            packs(
+                repository.common_dir().join(OBJECT_NAME),
+                HashAlgorithm::Sha1
+            )?
+            .len(),
+            2
+        );
+
+        // Nothing left
+        assert_eq!(
+            repack(&repository, &RepackOptions::default())?,
+            RepackReport::default()
+        );
+
+        // Indexes are loaded once, a pack written by another handle is
+        // picked up on the first miss
+        let other = NssRepository::open(temp_dir.clone())?;
+        assert_eq!(other.objects().packs()?.len(), 2);
+        repository.objects().write(blob("elsewhere"))?;
+        repack(&repository, &RepackOptions::default())?;
+        assert_eq!(repository.objects().packs()?.len(), 3);
+        assert_eq!(other.objects().packs()?.len(), 2);
+        let hash = hex::encode(blob("elsewhere").to_hash());
+        assert!(!other.objects().has_object(&hash)?);
+        assert_eq!(
+            other.objects().read(&hash)?.as_bytes(),
+            blob("elsewhere").as_bytes()
+        );
+        assert_eq!(other.objects().packs()?.len(), 3);
+
+        // Objects over the threshold stay loose
+        let big = blob("a blob too big for this pack");
+        let big_hash = hex::encode(big.to_hash());
+        repository.objects().write(big.clone())?;
+        repository.objects().write(blob("small"))?;
+        let options = RepackOptions {
+            big_file_threshold: 8,
+            ..Default::default()
+        };
+        let report = repack(&repository, &options)?;
+        assert_eq!(report.packed, 1);
+        assert_eq!(report.kept, vec![big_hash.clone()]);
+        assert!(repository.objects().is_loose(&big_hash));
+        assert_eq!(
+            repository.objects().read(&big_hash)?.as_bytes(),
+            big.as_bytes()
+        );
+        assert!(repack(&repository, &options)?.pack.is_none());
+        repack(&repository, &RepackOptions::default())?;
+
+        // Formats before packs, or before deltas, refuse them until migrated
+        let mut config = repository.config().read()?;
+        config.set_repository_format_version(2);
+        repository.config().write(config)?;
+        repository.objects().write(blob("unmigrated"))?;
+        assert!(matches!(
+            repack(&repository, &RepackOptions::default()),
+            Err(Error::FormatVersionTooOld(2, _))
+        ));
+        let mut config = repository.config().read()?;
+        config.set_repository_format_version(3);
+        repository.config().write(config)?;
+        assert!(matches!(
+            repack(&repository, &RepackOptions::default()),
+            Err(Error::FormatVersionTooOld(3, _))
+        ));
+        let no_deltas = RepackOptions {
+            depth: 0,
+            ..Default::default()
+        };
+        assert_eq!(repack(&repository, &no_deltas)?.packed, 1);
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+}
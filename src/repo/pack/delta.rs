@@ -0,0 +1,231 @@
+//! Copy/insert deltas
+//!
+//! A delta rebuilds a target from a base.
+//!     Header:
+//!         - base size, target size (varint)
+//!     Instructions:
+//!         - 0x01 offset len   ... copy `len` bytes of the base from `offset`
+//!         - 0x02 len bytes    ... insert the next `len` bytes
+//!     Numbers are little endian base 128 varints.
+//!
+
+// Std
+use std::collections::HashMap;
+
+// Internal
+use crate::repo::error::Error;
+
+const COPY: u8 = 0x01;
+const INSERT: u8 = 0x02;
+
+// Shorter matches cost more as copy than as insert
+const BLOCK_SIZE: usize = 16;
+
+/// Delta turning `base` into `target`.
+pub(crate) fn encode(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = vec![];
+    write_varint(&mut delta, base.len() as u64);
+    write_varint(&mut delta, target.len() as u64);
+
+    // First position of every block in the base
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    if base.len() >= BLOCK_SIZE {
+        for offset in (0..=base.len() - BLOCK_SIZE).rev() {
+            blocks.insert(&base[offset..offset + BLOCK_SIZE], offset);
+        }
+    }
+
+    let mut pending: Vec<u8> = vec![];
+    let mut position = 0;
+    while position < target.len() {
+        let found = target
+            .get(position..position + BLOCK_SIZE)
+            .and_then(|block| blocks.get(block));
+
+        match found {
+            Some(&offset) => {
+                let len = base[offset..]
+                    .iter()
+                    .zip(&target[position..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+                flush_insert(&mut delta, &mut pending);
+                delta.push(COPY);
+                write_varint(&mut delta, offset as u64);
+                write_varint(&mut delta, len as u64);
+                position += len;
+            }
+            None => {
+                pending.push(target[position]);
+                position += 1;
+            }
+        }
+    }
+    flush_insert(&mut delta, &mut pending);
+
+    delta
+}
+
+/// Rebuild the target of `delta` from `base`.
+pub(crate) fn apply(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, Error> {
+    let broken = |reason: &str| Error::InvalidPack(format!("broken delta: {}", reason));
+
+    let mut cursor = 0;
+    let base_size = read_varint(delta, &mut cursor).ok_or_else(|| broken("no base size"))?;
+    let target_size = read_varint(delta, &mut cursor).ok_or_else(|| broken("no target size"))?;
+    if base_size != base.len() as u64 {
+        return Err(broken("base size does not match"));
+    }
+
+    // The sizes come from the pack, so they only bound what is kept
+    let capacity = target_size.min((base.len() + delta.len()) as u64);
+    let mut target = Vec::with_capacity(capacity as usize);
+    while cursor < delta.len() {
+        let op = delta[cursor];
+        cursor += 1;
+
+        match op {
+            COPY => {
+                let offset = read_varint(delta, &mut cursor).ok_or_else(|| broken("no offset"))?;
+                let len = read_varint(delta, &mut cursor).ok_or_else(|| broken("no length"))?;
+                let end = offset
+                    .checked_add(len)
+                    .ok_or_else(|| broken("copy out of base"))?;
+                let range = offset as usize..end as usize;
+                target
+                    .extend_from_slice(base.get(range).ok_or_else(|| broken("copy out of base"))?);
+            }
+            INSERT => {
+                let len = read_varint(delta, &mut cursor).ok_or_else(|| broken("no length"))?;
+                let end = cursor
+                    .checked_add(len as usize)
+                    .ok_or_else(|| broken("insert past end"))?;
+                target.extend_from_slice(
+                    delta
+                        .get(cursor..end)
+                        .ok_or_else(|| broken("insert past end"))?,
+                );
+                cursor = end;
+            }
+            op => return Err(broken(&format!("unknown instruction {}", op))),
+        }
+
+        if target.len() as u64 > target_size {
+            return Err(broken("target size does not match"));
+        }
+    }
+
+    if target.len() as u64 != target_size {
+        return Err(broken("target size does not match"));
+    }
+
+    Ok(target)
+}
+
+fn flush_insert(delta: &mut Vec<u8>, pending: &mut Vec<u8>) {
+    if pending.is_empty() {
+        return;
+    }
+
+    delta.push(INSERT);
+    write_varint(delta, pending.len() as u64);
+    delta.append(pending);
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], cursor: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*cursor)?;
+        *cursor += 1;
+        value |= u64::from(byte & 0x7f).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+        if shift >= 64 {
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_round_trip() {
+        let base = b"fn main() {\n    println!(\"Hello, world!\");\n    let x = 1;\n}\n".repeat(10);
+        let mut target = base.clone();
+        target.splice(200..210, b"inserted lines\n".iter().copied());
+        target.extend_from_slice(b"// trailing comment\n");
+
+        let delta = encode(&base, &target);
+        assert!(delta.len() < target.len() / 4);
+        assert_eq!(apply(&base, &delta).unwrap(), target);
+
+        // Unrelated and empty inputs
+        for (base, target) in [
+            (&b"abc"[..], &b"completely different"[..]),
+            (&b""[..], &b"only inserts"[..]),
+            (&b"only base"[..], &b""[..]),
+        ] {
+            let delta = encode(base, target);
+            assert_eq!(apply(base, &delta).unwrap(), target);
+        }
+    }
+
+    #[test]
+    fn test_delta_broken() {
+        let base = b"0123456789abcdef0123456789abcdef".to_vec();
+        let target = b"0123456789abcdef0123456789abcdef!".to_vec();
+        let delta = encode(&base, &target);
+
+        // Wrong base
+        assert!(apply(b"other base", &delta).is_err());
+
+        // Truncated
+        assert!(apply(&base, &delta[..delta.len() - 1]).is_err());
+
+        // Copy past the base
+        let mut delta = vec![];
+        write_varint(&mut delta, 4);
+        write_varint(&mut delta, 8);
+        delta.extend_from_slice(&[COPY, 2, 8]);
+        assert!(apply(b"abcd", &delta).is_err());
+
+        // Huge target size is an error, not an allocation failure
+        let mut delta = vec![];
+        write_varint(&mut delta, 4);
+        write_varint(&mut delta, u64::MAX);
+        delta.extend_from_slice(&[COPY, 0, 4]);
+        assert!(matches!(apply(b"abcd", &delta), Err(Error::InvalidPack(_))));
+
+        // Growing past the target size stops early
+        let mut delta = vec![];
+        write_varint(&mut delta, 4);
+        write_varint(&mut delta, 4);
+        delta.extend_from_slice(&[COPY, 0, 4, COPY, 0, 4]);
+        assert!(matches!(apply(b"abcd", &delta), Err(Error::InvalidPack(_))));
+    }
+
+    #[test]
+    fn test_varint() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut bytes = vec![];
+            write_varint(&mut bytes, value);
+            let mut cursor = 0;
+            assert_eq!(read_varint(&bytes, &mut cursor), Some(value));
+            assert_eq!(cursor, bytes.len());
+        }
+    }
+}
@@ -0,0 +1,573 @@
+//! Reference logs
+//!
+//! Every change of HEAD or a bookmark appends one line to its log.
+//!     Paths:
+//!         - HEAD        ... `<nss_dir>/logs/HEAD`, per worktree
+//!         - bookmarks   ... `<common_dir>/logs/bookmarks/<name>`
+//!     Format:
+//!         `<old> <new> <name> <<email>> <unix time> +0000\t<reason>`
+//!     A missing value is written as [`zero_hash`], sized by the hash algorithm.
+//!
+
+// Std
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+// External
+use chrono::prelude::{DateTime, Utc};
+use chrono::TimeZone;
+
+// Internal
+use super::config::Config;
+use super::error::Error;
+use super::fsck::bookmark_names;
+use super::repository::{
+    lock_error, ConfigRepository, HeadRepository, NssRepository, Repository, BOOKMARK_NAME,
+    CONFIG_NAME, HEAD_NAME, TAG_NAME,
+};
+use crate::nss_io::file_system::{create_dir, read_content, write_content, ReadMode, WriteMode};
+use crate::nss_io::lock::LockFile;
+use crate::struct_set::{HashAlgorithm, Head, ObjectId};
+
+pub(crate) const LOGS_NAME: &str = "logs";
+
+/// Old value of a newly created reference, new value of a deleted one.
+pub fn zero_hash(algorithm: HashAlgorithm) -> String {
+    ObjectId::zero(algorithm).to_hex()
+}
+
+/// Whether `hash` is a [`zero_hash`], of any algorithm.
+pub(crate) fn is_zero_hash(hash: &str) -> bool {
+    !hash.is_empty() && hash.bytes().all(|b| b == b'0')
+}
+
+/// **ReflogEntry Struct**
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    pub identity: String,
+    pub date: DateTime<Utc>,
+    pub reason: String,
+}
+
+impl ReflogEntry {
+    fn to_line(&self) -> String {
+        format!(
+            "{} {} {} {} +0000\t{}\n",
+            self.old,
+            self.new,
+            self.identity,
+            self.date.timestamp(),
+            self.reason.replace('\n', " ")
+        )
+    }
+
+    fn from_line(line: &str) -> Result<Self, Error> {
+        let broken = || Error::InvalidReflog(line.to_string());
+
+        let (fields, reason) = line.split_once('\t').ok_or_else(broken)?;
+        let (old, rest) = fields.split_once(' ').ok_or_else(broken)?;
+        let (new, rest) = rest.split_once(' ').ok_or_else(broken)?;
+        let (rest, _timezone) = rest.rsplit_once(' ').ok_or_else(broken)?;
+        let (identity, timestamp) = rest.rsplit_once(' ').ok_or_else(broken)?;
+
+        let timestamp = timestamp.parse::<i64>().map_err(|_| broken())?;
+        let date = Utc
+            .timestamp_opt(timestamp, 0)
+            .single()
+            .ok_or_else(broken)?;
+
+        Ok(Self {
+            old: old.to_string(),
+            new: new.to_string(),
+            identity: identity.to_string(),
+            date,
+            reason: reason.to_string(),
+        })
+    }
+}
+
+/// **Reflog Struct**
+///
+/// Reads and writes the logs of one worktree. References are named
+/// `HEAD` or by the bookmark name, e.g. `main`.
+#[derive(Debug, Clone)]
+pub struct Reflog {
+    nss_dir: PathBuf,
+    common_dir: PathBuf,
+    hash_algorithm: HashAlgorithm,
+}
+
+impl Reflog {
+    pub(crate) fn new(nss_dir: PathBuf, common_dir: PathBuf) -> Self {
+        Self {
+            nss_dir,
+            common_dir,
+            hash_algorithm: HashAlgorithm::default(),
+        }
+    }
+
+    /// Size missing values for `algorithm`, see [`zero_hash`].
+    pub(crate) fn with_hash_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = algorithm;
+        self
+    }
+
+    pub fn log_path(&self, name: &str) -> PathBuf {
+        match name {
+            HEAD_NAME => self.nss_dir.join(LOGS_NAME).join(HEAD_NAME),
+            _ => self
+                .common_dir
+                .join(LOGS_NAME)
+                .join(BOOKMARK_NAME)
+                .join(name),
+        }
+    }
+
+    /// Reference file whose changes are logged as `name`.
+    fn ref_path(&self, name: &str) -> PathBuf {
+        match name {
+            HEAD_NAME => self.nss_dir.join(HEAD_NAME),
+            _ => self.common_dir.join(BOOKMARK_NAME).join(name),
+        }
+    }
+
+    /// Entries of `name`, oldest first. Empty when nothing was logged.
+    pub fn read(&self, name: &str) -> Result<Vec<ReflogEntry>, Error> {
+        let path = self.log_path(name);
+        if !path.is_file() {
+            return Ok(vec![]);
+        }
+
+        let bytes = read_content(&path, ReadMode::default())?;
+        let content = String::from_utf8(bytes)?;
+
+        content.lines().map(ReflogEntry::from_line).collect()
+    }
+
+    /// Value of `name` before its last `n` updates, like `main@{n}`.
+    pub fn nth(&self, name: &str, n: usize) -> Result<String, Error> {
+        let entries = self.read(name)?;
+        let not_found = || Error::NotFoundRevision(format!("{}@{{{}}}", name, n));
+
+        // The newest entry holds the current value
+        let index = entries.len().checked_sub(n + 1).ok_or_else(not_found)?;
+        let hash = &entries[index].new;
+        if is_zero_hash(hash) {
+            return Err(not_found());
+        }
+
+        Ok(hash.to_string())
+    }
+
+    /// HEAD and every bookmark with a log.
+    pub fn names(&self) -> Result<Vec<String>, Error> {
+        let mut names = vec![];
+        if self.log_path(HEAD_NAME).is_file() {
+            names.push(HEAD_NAME.to_string());
+        }
+
+        let bookmark_logs = self.common_dir.join(LOGS_NAME).join(BOOKMARK_NAME);
+        bookmark_names(&bookmark_logs, &bookmark_logs, &mut names)?;
+
+        Ok(names)
+    }
+
+    /// Drop entries of `name` older than `before`. Returns the count dropped.
+    ///
+    /// Writers log under the reference's lock, so it is held here too and
+    /// no entry appended meanwhile is lost. Fails with
+    /// [`Error::RepositoryLocked`] while the reference is being written.
+    pub fn expire(&self, name: &str, before: DateTime<Utc>) -> Result<usize, Error> {
+        let ref_path = self.ref_path(name);
+        create_dir(ref_path.parent().unwrap())?;
+        let _lock = LockFile::acquire(ref_path).map_err(lock_error)?;
+
+        let entries = self.read(name)?;
+        let kept: Vec<&ReflogEntry> = entries.iter().filter(|e| e.date >= before).collect();
+
+        let expired = entries.len() - kept.len();
+        if expired > 0 {
+            let content: String = kept.iter().map(|e| e.to_line()).collect();
+            write_content(self.log_path(name), content.as_bytes(), WriteMode::Atomic)?;
+        }
+
+        Ok(expired)
+    }
+
+    /// [`Reflog::expire`] for every log.
+    pub fn expire_all(&self, before: DateTime<Utc>) -> Result<usize, Error> {
+        let mut expired = 0;
+        for name in self.names()? {
+            expired += self.expire(&name, before)?;
+        }
+
+        Ok(expired)
+    }
+
+    pub(crate) fn append(
+        &self,
+        name: &str,
+        old: &str,
+        new: &str,
+        reason: &str,
+    ) -> Result<(), Error> {
+        // Nothing happened, e.g. HEAD set to an unborn bookmark on init
+        if is_zero_hash(old) && is_zero_hash(new) {
+            return Ok(());
+        }
+
+        let entry = ReflogEntry {
+            old: old.to_string(),
+            new: new.to_string(),
+            identity: self.identity()?,
+            date: Utc::now(),
+            reason: reason.to_string(),
+        };
+
+        let path = self.log_path(name);
+        create_dir(path.parent().unwrap())?;
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(entry.to_line().as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Value HEAD resolves to, [`zero_hash`] on an unborn bookmark.
+    pub(crate) fn head_hash(&self) -> Result<String, Error> {
+        let head_path = self.nss_dir.join(HEAD_NAME);
+        if !head_path.is_file() {
+            return Ok(zero_hash(self.hash_algorithm));
+        }
+
+        match HeadRepository::from(head_path).read()? {
+            Head::ObjectHash(hash) => Ok(hash.to_hex()),
+            Head::Bookmarker(bookmark) => self.bookmark_hash(&bookmark.display().to_string()),
+        }
+    }
+
+    /// Bookmark HEAD of this worktree points at, if any.
+    pub(crate) fn head_bookmark(&self) -> Result<Option<String>, Error> {
+        let head_path = self.nss_dir.join(HEAD_NAME);
+        if !head_path.is_file() {
+            return Ok(None);
+        }
+
+        match HeadRepository::from(head_path).read()? {
+            Head::Bookmarker(bookmark) => Ok(Some(bookmark.display().to_string())),
+            Head::ObjectHash(_) => Ok(None),
+        }
+    }
+
+    pub(crate) fn bookmark_hash(&self, name: &str) -> Result<String, Error> {
+        let path = self.common_dir.join(BOOKMARK_NAME).join(name);
+        if !path.is_file() {
+            return Ok(zero_hash(self.hash_algorithm));
+        }
+
+        let bytes = read_content(&path, ReadMode::default())?;
+        let hash = String::from_utf8(bytes)?.trim().to_string();
+
+        match hash.is_empty() {
+            true => Ok(zero_hash(self.hash_algorithm)),
+            false => Ok(hash),
+        }
+    }
+
+    fn identity(&self) -> Result<String, Error> {
+        let config_path = self.common_dir.join(CONFIG_NAME);
+        let config = match config_path.is_file() {
+            true => ConfigRepository::from(config_path).read()?,
+            false => Config::default(),
+        };
+
+        Ok(format!(
+            "{} <{}>",
+            config.username(),
+            config.useremail().unwrap_or_default()
+        ))
+    }
+}
+
+impl NssRepository {
+    pub fn reflog(&self) -> Reflog {
+        Reflog::new(self.nss_dir(), self.common_dir()).with_hash_algorithm(self.hash_algorithm())
+    }
+
+    /// Hash named by `revision`.
+    ///
+    /// Accepts `HEAD`, a bookmark name, a tag name, a full hash or
+    /// `<name>@{n}`, the value of `HEAD` or a bookmark before its last `n`
+    /// updates. An annotated tag names its tag object.
+    pub fn resolve_revision(&self, revision: &str) -> Result<String, Error> {
+        let not_found = || Error::NotFoundRevision(revision.to_string());
+        let reflog = self.reflog();
+
+        if let Some((name, rest)) = revision.split_once("@{") {
+            let n = rest
+                .strip_suffix('}')
+                .and_then(|n| n.parse::<usize>().ok())
+                .ok_or_else(not_found)?;
+            return reflog.nth(name, n);
+        }
+
+        let hash = match revision {
+            HEAD_NAME => reflog.head_hash()?,
+            _ if self
+                .common_dir()
+                .join(BOOKMARK_NAME)
+                .join(revision)
+                .is_file() =>
+            {
+                reflog.bookmark_hash(revision)?
+            }
+            _ if self.common_dir().join(TAG_NAME).join(revision).is_file() => {
+                self.tags().read(revision)?.to_hex()
+            }
+            _ if self.hash_algorithm().is_hex_hash(revision) => revision.to_string(),
+            _ => return Err(not_found()),
+        };
+
+        match is_zero_hash(&hash) {
+            true => Err(not_found()),
+            false => Ok(hash),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::config::User;
+    use crate::repo::repository::PathRepository;
+    use crate::struct_set::BookMark;
+
+    use anyhow::Result;
+    use chrono::Duration;
+    use std::fs;
+    use testdir::testdir;
+
+    const HASH_A: &str = "a0a4e2f9bb9b4bb1b6fcbce0b4d1ac53ad5e9f70";
+    const HASH_B: &str = "b1b4e2f9bb9b4bb1b6fcbce0b4d1ac53ad5e9f70";
+    const HASH_C: &str = "c2c4e2f9bb9b4bb1b6fcbce0b4d1ac53ad5e9f70";
+
+    fn write_bookmark(repository: &NssRepository, name: &str, hash: &str) -> Result<()> {
+        repository
+            .bookmark()
+            .write(BookMark::new(PathBuf::from(name), hash.parse()?))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reflog_entry_line() -> Result<()> {
+        let entry = ReflogEntry {
+            old: zero_hash(HashAlgorithm::Sha1),
+            new: HASH_A.to_string(),
+            identity: "noshishi <nss@example.com>".to_string(),
+            date: Utc.timestamp_opt(1700000000, 0).unwrap(),
+            reason: "commit: initial".to_string(),
+        };
+        let line = entry.to_line();
+        assert_eq!(
+            line,
+            format!(
+                "{} {} noshishi <nss@example.com> 1700000000 +0000\tcommit: initial\n",
+                zero_hash(HashAlgorithm::Sha1),
+                HASH_A
+            )
+        );
+        assert_eq!(ReflogEntry::from_line(line.trim_end())?, entry);
+
+        assert!(ReflogEntry::from_line("broken").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reflog_records_updates() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let config = Config::new(User::new(
+            "noshishi".to_string(),
+            Some("nss@example.com".to_string()),
+        ));
+        let repository = NssRepository::init(temp_dir.clone(), config, false)?;
+
+        write_bookmark(&repository, "main", HASH_A)?;
+        write_bookmark(&repository, "main", HASH_B)?;
+        repository.bookmark().write_with_reason(
+            BookMark::new(PathBuf::from("main"), HASH_C.parse()?),
+            "amend",
+        )?;
+        write_bookmark(&repository, "topic", HASH_A)?;
+
+        let entries = repository.reflog().read("main")?;
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            (entries[0].old.as_str(), entries[0].new.as_str()),
+            (zero_hash(HashAlgorithm::Sha1).as_str(), HASH_A)
+        );
+        assert_eq!(
+            (entries[1].old.as_str(), entries[1].new.as_str()),
+            (HASH_A, HASH_B)
+        );
+        assert_eq!(entries[2].reason, "amend");
+        assert_eq!(entries[0].identity, "noshishi <nss@example.com>");
+
+        // HEAD follows main
+        let head_entries = repository.reflog().read("HEAD")?;
+        assert_eq!(head_entries.len(), 3);
+        assert_eq!(head_entries.last().unwrap().new, HASH_C);
+
+        // Detach
+        repository.head().write(Head::ObjectHash(HASH_A.parse()?))?;
+        let head_entries = repository.reflog().read("HEAD")?;
+        assert_eq!(head_entries.last().unwrap().old, HASH_C);
+        assert_eq!(
+            head_entries.last().unwrap().reason,
+            format!("checkout: moving to {}", HASH_A)
+        );
+
+        // Transactions are logged too
+        repository
+            .bookmark()
+            .transaction()
+            .update("topic", Some(HASH_A.parse()?), HASH_B.parse()?)
+            .reason("rebase")
+            .commit()?;
+        let entries = repository.reflog().read("topic")?;
+        assert_eq!(entries.last().unwrap().new, HASH_B);
+        assert_eq!(entries.last().unwrap().reason, "rebase");
+
+        let mut names = repository.reflog().names()?;
+        names.sort();
+        assert_eq!(names, vec!["HEAD", "main", "topic"]);
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reflog_chains_concurrent_updates() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let config = Config::new(User::new(
+            "noshishi".to_string(),
+            Some("nss@example.com".to_string()),
+        ));
+        let repository = NssRepository::init(temp_dir.clone(), config, false)?;
+
+        std::thread::scope(|s| {
+            for hash in [HASH_A, HASH_B, HASH_C] {
+                let repository = &repository;
+                s.spawn(move || {
+                    for _ in 0..10 {
+                        loop {
+                            let bookmark =
+                                BookMark::new(PathBuf::from("main"), hash.parse().unwrap());
+                            match repository.bookmark().write(bookmark) {
+                                Err(Error::RepositoryLocked(_)) => std::thread::yield_now(),
+                                result => break result.unwrap(),
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        // Every entry starts where the previous one ended
+        let entries = repository.reflog().read("main")?;
+        assert_eq!(entries.len(), 30);
+        assert_eq!(entries[0].old, zero_hash(HashAlgorithm::Sha1));
+        for pair in entries.windows(2) {
+            assert_eq!(pair[1].old, pair[0].new);
+        }
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_revision() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let result = repository.resolve_revision("HEAD");
+        assert!(matches!(result, Err(Error::NotFoundRevision(_))));
+
+        write_bookmark(&repository, "main", HASH_A)?;
+        write_bookmark(&repository, "main", HASH_B)?;
+        write_bookmark(&repository, "main", HASH_C)?;
+
+        assert_eq!(repository.resolve_revision("main")?, HASH_C);
+        assert_eq!(repository.resolve_revision("HEAD")?, HASH_C);
+        assert_eq!(repository.resolve_revision("main@{0}")?, HASH_C);
+        assert_eq!(repository.resolve_revision("main@{2}")?, HASH_A);
+        assert_eq!(repository.resolve_revision("HEAD@{1}")?, HASH_B);
+        assert_eq!(repository.resolve_revision(HASH_A)?, HASH_A);
+
+        for revision in ["main@{3}", "main@{x}", "missing", "missing@{0}"] {
+            let result = repository.resolve_revision(revision);
+            assert!(matches!(result, Err(Error::NotFoundRevision(_))));
+        }
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reflog_expire() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        write_bookmark(&repository, "main", HASH_A)?;
+        write_bookmark(&repository, "main", HASH_B)?;
+
+        // Backdate the first entry
+        let reflog = repository.reflog();
+        let mut entries = reflog.read("main")?;
+        entries[0].date = Utc::now() - Duration::days(100);
+        let content: String = entries.iter().map(|e| e.to_line()).collect();
+        fs::write(reflog.log_path("main"), content)?;
+
+        let before = Utc::now() - Duration::days(90);
+
+        // Not while the bookmark is being written
+        let lock = LockFile::acquire(repository.common_dir().join("bookmarks").join("main"))?;
+        assert!(matches!(
+            reflog.expire("main", before),
+            Err(Error::RepositoryLocked(_))
+        ));
+        drop(lock);
+
+        assert_eq!(reflog.expire("main", before)?, 1);
+        assert_eq!(reflog.read("main")?.len(), 1);
+        assert_eq!(reflog.read("main")?[0].new, HASH_B);
+
+        assert_eq!(reflog.expire_all(before)?, 0);
+        assert_eq!(reflog.expire_all(Utc::now() + Duration::days(1))?, 3);
+        assert!(reflog.read("HEAD")?.is_empty());
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+}
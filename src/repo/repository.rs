@@ -2,17 +2,36 @@
 
 // Std
 use std::fs;
-use std::path::{Path, PathBuf};
-
-// External
-use dirs::home_dir;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 // Internal
+use super::cache::{CacheStats, ObjectCache, DEFAULT_CACHE_SIZE};
 use super::config::Config;
+use super::database::{ObjectDatabase, ObjectInfo};
+use super::discovery::Discovery;
 use super::error::Error;
-use crate::nss_io::file_system::{create_dir, read_content, write_content, ReadMode, WriteMode};
+use super::large::{LargeObjectStore, LARGE_NAME};
+use super::migration::{
+    check_format_version, loose_compression, CURRENT_FORMAT_VERSION, GIT_ENCODING_FORMAT_VERSION,
+};
+use super::pack::{packs, Pack};
+use super::reflog::Reflog;
+use super::stream::{read_header, write_blob, BlobReader};
+use super::tags::TagRepository;
+use super::transaction::BookMarkTransaction;
+use crate::nss_io::error::Error as NssIoError;
+use crate::nss_io::file_system::{
+    create_dir, open_content, read_content, write_content, ReadMode, WriteMode,
+};
+use crate::nss_io::lock::{is_lock_path, is_stale_lock, lock_path, write_locked, LockFile};
 use crate::struct_set::error::Error as StructError;
-use crate::struct_set::{BookMark, Commit, Hashable, Head, Index, IndexVesion1, Object};
+use crate::struct_set::{
+    Blob, BookMark, Commit, HashAlgorithm, Hashable, Head, Index, IndexVesion1, Object,
+    ObjectEncoding, ObjectId,
+};
 
 pub trait Repository<T> {
     fn write(&self, item: T) -> Result<(), Error>;
@@ -26,26 +45,70 @@ pub trait PathRepository<T> {
     fn read<P: Into<String>>(&self, p: P) -> Result<T, Error>;
 }
 
-const REPO_NAME: &str = ".nss";
-const OBJECT_NAME: &str = "objects";
-const BOOKMARK_NAME: &str = "bookmarks";
+pub(crate) const REPO_NAME: &str = ".nss";
+pub(crate) const OBJECT_NAME: &str = "objects";
+pub(crate) const BOOKMARK_NAME: &str = "bookmarks";
+pub(crate) const TAG_NAME: &str = "tags";
 // const LOCAL_NAME: &str = "local";
-const CONFIG_NAME: &str = "config";
-const HEAD_NAME: &str = "HEAD";
-const INDEX_NAME: &str = "INDEX";
+pub(crate) const CONFIG_NAME: &str = "config";
+pub(crate) const HEAD_NAME: &str = "HEAD";
+pub(crate) const INDEX_NAME: &str = "INDEX";
+pub(crate) const COMMON_DIR_NAME: &str = "commondir";
+const DEFAULT_BOOKMARK: &str = "main";
 
 #[derive(Debug, Clone)]
 pub struct HeadRepository {
     root: PathBuf,
+    reflog: Option<Reflog>,
 }
 
-impl Repository<Head> for HeadRepository {
-    fn write(&self, item: Head) -> Result<(), Error> {
+impl HeadRepository {
+    /// Log every write of HEAD in `reflog`.
+    pub(crate) fn with_reflog(mut self, reflog: Reflog) -> Self {
+        self.reflog = Some(reflog);
+        self
+    }
+
+    /// Write HEAD and log the change with `reason`.
+    ///
+    /// The old value is read and logged under the lock, so concurrent
+    /// writers chain in the reflog.
+    pub fn write_with_reason(&self, item: Head, reason: &str) -> Result<(), Error> {
         let s = toml::to_string(&item)?;
-        write_content(&self.root, s.as_bytes(), WriteMode::default())?;
+        let lock = LockFile::acquire(&self.root).map_err(lock_error)?;
+
+        let update = match &self.reflog {
+            Some(reflog) => {
+                let old = reflog.head_hash()?;
+                let new = match &item {
+                    Head::ObjectHash(hash) => hash.to_hex(),
+                    Head::Bookmarker(bookmark) => {
+                        reflog.bookmark_hash(&bookmark.display().to_string())?
+                    }
+                };
+                Some((reflog, old, new))
+            }
+            None => None,
+        };
+        // Logged once the change has landed, still under the lock
+        lock.replace(s.as_bytes())?;
+        if let Some((reflog, old, new)) = update {
+            reflog.append(HEAD_NAME, &old, &new, reason)?;
+        }
 
         Ok(())
     }
+}
+
+impl Repository<Head> for HeadRepository {
+    fn write(&self, item: Head) -> Result<(), Error> {
+        let reason = match &item {
+            Head::Bookmarker(bookmark) => format!("checkout: moving to {}", bookmark.display()),
+            Head::ObjectHash(hash) => format!("checkout: moving to {}", hash),
+        };
+
+        self.write_with_reason(item, &reason)
+    }
 
     fn read(&self) -> Result<Head, Error> {
         let bytes = read_content(&self.root, ReadMode::default())?;
@@ -57,7 +120,7 @@ impl Repository<Head> for HeadRepository {
 
 impl From<PathBuf> for HeadRepository {
     fn from(root: PathBuf) -> Self {
-        Self { root }
+        Self { root, reflog: None }
     }
 }
 
@@ -91,11 +154,20 @@ impl From<PathBuf> for ConfigRepository {
 #[derive(Debug, Clone)]
 pub struct IndexRepository {
     root: PathBuf,
+    hash_algorithm: HashAlgorithm,
+}
+
+impl IndexRepository {
+    /// Read entries whose hashes are of `algorithm`.
+    pub(crate) fn with_hash_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = algorithm;
+        self
+    }
 }
 
 impl Repository<Index> for IndexRepository {
     fn write(&self, index: Index) -> Result<(), Error> {
-        write_content(&self.root, &index.as_bytes(), WriteMode::default())?;
+        write_with_lock(&self.root, &index.as_bytes())?;
 
         Ok(())
     }
@@ -103,93 +175,457 @@ impl Repository<Index> for IndexRepository {
     fn read(&self) -> Result<Index, Error> {
         let bytes = read_content(&self.root, ReadMode::default())?;
 
-        Ok(Index::from_rawindex(bytes)?)
+        Ok(Index::from_rawindex_with(bytes, self.hash_algorithm)?)
     }
 }
 
 impl From<PathBuf> for IndexRepository {
     fn from(root: PathBuf) -> Self {
-        Self { root }
+        Self {
+            root,
+            hash_algorithm: HashAlgorithm::default(),
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct ObjectRepository {
     root: PathBuf,
+    compression: Option<u32>,
+    hash_algorithm: HashAlgorithm,
+    encoding: ObjectEncoding,
+    cache: Arc<ObjectCache>,
+    // Pack indexes, loaded on first use and shared by clones
+    packs: Arc<RwLock<Option<Arc<Vec<Pack>>>>>,
 }
 
 impl ObjectRepository {
+    /// Compress new loose objects with zlib `level` (0-9), or write them
+    /// raw on `None`.
+    pub(crate) fn with_compression(mut self, level: Option<u32>) -> Self {
+        self.compression = level;
+        self
+    }
+
+    pub(crate) fn compression(&self) -> Option<u32> {
+        self.compression
+    }
+
+    /// Name and parse objects with `algorithm`.
+    pub(crate) fn with_hash_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = algorithm;
+        self.packs = Arc::default();
+        self
+    }
+
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+
+    /// Lay out and parse trees, commits and tags in `encoding`.
+    pub(crate) fn with_encoding(mut self, encoding: ObjectEncoding) -> Self {
+        self.encoding = encoding;
+        self.packs = Arc::default();
+        self
+    }
+
+    pub fn object_encoding(&self) -> ObjectEncoding {
+        self.encoding
+    }
+
+    /// Keep up to `budget` bytes of parsed objects in memory, see
+    /// [`crate::repo::cache`]. Clones made before share the old cache.
+    pub(crate) fn with_cache_size(mut self, budget: usize) -> Self {
+        self.cache = Arc::new(ObjectCache::new(budget));
+        self
+    }
+
+    /// Hit and miss counters of the object cache.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Forget cached objects, e.g. after objects were deleted.
+    pub fn clear_cache(&self) {
+        self.cache.clear()
+    }
+
+    /// Read `hash` from disk, bypassing the cache.
+    ///
+    /// Packs are scanned again when `hash` is in none of the loaded ones,
+    /// another process may have repacked it meanwhile.
+    pub(crate) fn read_uncached(&self, hash: &str) -> Result<Object, Error> {
+        self.check_hash(hash)?;
+        let (d, f) = split_hash(hash);
+        let path = self.root.join(d).join(f);
+        if !path.exists() {
+            let mut packs = self.packs()?;
+            if !packs.iter().any(|p| p.contains(hash)) {
+                packs = self.refresh_packs()?;
+            }
+            for pack in packs.iter() {
+                if let Some(object) = pack.read(hash)? {
+                    return Ok(object);
+                }
+            }
+        }
+
+        // Objects written before compression are raw
+        let content = read_content(path, ReadMode::DetectZlib)?;
+
+        Ok(Object::from_content_with_encoding(
+            content,
+            self.hash_algorithm,
+            self.encoding,
+        )?)
+    }
+
+    /// Whether `hash` is stored, loose or in a loaded pack.
+    pub fn has_object(&self, hash: &str) -> Result<bool, Error> {
+        if self.check_hash(hash).is_err() {
+            return Ok(false);
+        }
+        if self.is_loose(hash) {
+            return Ok(true);
+        }
+
+        Ok(self.packs()?.iter().any(|p| p.contains(hash)))
+    }
+
+    /// Type and content size of `hash`.
+    ///
+    /// Only the header of a loose object is inflated. Packed objects are
+    /// read whole, packs hold small objects.
+    pub fn object_info(&self, hash: &str) -> Result<ObjectInfo, Error> {
+        self.check_hash(hash)?;
+        let (d, f) = split_hash(hash);
+        let path = self.root.join(d).join(f);
+        if !path.is_file() {
+            return Ok(ObjectInfo::from_object(&self.read(hash)?));
+        }
+
+        let mut reader = open_content(path, ReadMode::DetectZlib)?;
+        let (object_type, size) = read_header(&mut reader, hash)?;
+
+        Ok(ObjectInfo { object_type, size })
+    }
+
+    /// Whether `hash` is stored as a loose file.
+    pub(crate) fn is_loose(&self, hash: &str) -> bool {
+        if self.check_hash(hash).is_err() {
+            return false;
+        }
+        let (d, f) = split_hash(hash);
+        self.root.join(d).join(f).is_file()
+    }
+
+    /// Reject anything but a full hex hash of the repository's algorithm,
+    /// before it is split into an object path.
+    fn check_hash(&self, hash: &str) -> Result<(), Error> {
+        match self.hash_algorithm.is_hex_hash(hash) {
+            true => Ok(()),
+            false => Err(Error::NssStruct(StructError::InvalidObjectId(
+                hash.to_string(),
+            ))),
+        }
+    }
+
+    /// Packs of this object store, see [`crate::repo::pack`].
+    ///
+    /// The indexes are read once, later calls reuse them until
+    /// [`ObjectRepository::refresh_packs`].
+    pub(crate) fn packs(&self) -> Result<Arc<Vec<Pack>>, Error> {
+        if let Some(packs) = self.packs.read().unwrap().as_ref() {
+            return Ok(packs.clone());
+        }
+
+        self.refresh_packs()
+    }
+
+    /// Read the pack indexes again, e.g. after a repack.
+    pub(crate) fn refresh_packs(&self) -> Result<Arc<Vec<Pack>>, Error> {
+        let packs: Vec<Pack> = packs(&self.root, self.hash_algorithm)?
+            .into_iter()
+            .map(|p| p.with_encoding(self.encoding))
+            .collect();
+        let packs = Arc::new(packs);
+        *self.packs.write().unwrap() = Some(packs.clone());
+
+        Ok(packs)
+    }
+
+    /// Hash of every packed object, sorted and deduplicated.
+    pub(crate) fn packed_objects(&self) -> Result<Vec<String>, Error> {
+        let mut hashes: Vec<String> = self.packs()?.iter().flat_map(|p| p.hashes()).collect();
+        hashes.sort();
+        hashes.dedup();
+
+        Ok(hashes)
+    }
+
+    /// Hashes starting with the hex `prefix`, loose or packed, sorted.
+    ///
+    /// Only the loose directory named by the first two digits is listed.
+    pub fn hashes_with_prefix(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let prefix = prefix.to_ascii_lowercase();
+        if prefix.len() < 2 {
+            return Ok(ObjectDatabase::hashes(self)?
+                .into_iter()
+                .filter(|h| h.starts_with(&prefix))
+                .collect());
+        }
+
+        let (d, f) = split_hash(&prefix);
+        let dir = self.root.join(d);
+        let mut hashes = vec![];
+        if dir.is_dir() {
+            for dir_entry in dir.read_dir()? {
+                let name = dir_entry?.file_name().to_string_lossy().to_string();
+                if name.starts_with(f) && name.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    hashes.push(format!("{}{}", d, name));
+                }
+            }
+        }
+        for pack in self.packs()?.iter() {
+            hashes.extend(pack.hashes_with_prefix(&prefix));
+        }
+        hashes.sort();
+        hashes.dedup();
+
+        Ok(hashes)
+    }
+
+    /// Store the next `len` bytes of `reader` as a blob with bounded
+    /// memory, see [`crate::repo::stream`]. Returns the hash.
+    pub fn write_blob_stream<R: Read>(&self, reader: R, len: u64) -> Result<String, Error> {
+        write_blob(
+            &self.root,
+            reader,
+            len,
+            self.hash_algorithm,
+            self.compression,
+        )
+    }
+
+    /// Content of the blob `hash` as a stream.
+    ///
+    /// Unlike [`PathRepository::read`] the content is not checked against
+    /// `hash`, as it is never held whole.
+    pub fn read_blob_stream(&self, hash: &str) -> Result<BlobReader, Error> {
+        self.check_hash(hash)?;
+        let (d, f) = split_hash(hash);
+        let path = self.root.join(d).join(f);
+        if path.exists() {
+            return BlobReader::open_loose(path, hash);
+        }
+
+        match self.read(hash)? {
+            Object::Blob(blob) => Ok(BlobReader::from_blob(blob)),
+            o => Err(Error::NssStruct(StructError::DontMatchType(
+                o.as_str().to_string(),
+                hash.to_string(),
+            ))),
+        }
+    }
+
     pub fn read_commit<P: Into<String>>(&self, p: P) -> Result<Commit, Error> {
         match self.read(p) {
             Ok(Object::Commit(c)) => Ok(c),
             _ => Err(Error::NssStruct(StructError::CannotSpecifyHash)),
         }
     }
+
+    /// Hash and path of every loose object file, sorted by hash.
+    ///
+    /// Files under `objects/xx/` whose name is not hex are returned as well,
+    /// with the joined name as hash, so callers can report them.
+    pub(crate) fn loose_objects(&self) -> Result<Vec<(String, PathBuf)>, Error> {
+        let mut objects = vec![];
+        for dir in self.loose_dirs()? {
+            let dir_name = dir.file_name().unwrap().to_string_lossy().to_string();
+            for file_entry in dir.read_dir()? {
+                let path = file_entry?.path();
+                let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+                objects.push((format!("{}{}", dir_name, file_name), path));
+            }
+        }
+        objects.sort();
+
+        Ok(objects)
+    }
+
+    /// Every `objects/xx/` directory, sorted.
+    pub(crate) fn loose_dirs(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut dirs = vec![];
+        if !self.root.is_dir() {
+            return Ok(dirs);
+        }
+
+        for dir_entry in self.root.read_dir()? {
+            let dir = dir_entry?.path();
+            let dir_name = dir.file_name().unwrap().to_string_lossy().to_string();
+            if dir.is_dir() && dir_name.len() == 2 && hex::decode(&dir_name).is_ok() {
+                dirs.push(dir);
+            }
+        }
+        dirs.sort();
+
+        Ok(dirs)
+    }
+
+    /// Hash of every object file in the loose directory `dir`, sorted.
+    /// Other files are skipped.
+    pub(crate) fn loose_hashes(&self, dir: &Path) -> Result<Vec<String>, Error> {
+        let dir_name = dir.file_name().unwrap().to_string_lossy().to_string();
+        let mut hashes = vec![];
+        for file_entry in dir.read_dir()? {
+            let file_name = file_entry?.file_name().to_string_lossy().to_string();
+            let hash = format!("{}{}", dir_name, file_name);
+            if self.hash_algorithm.is_hex_hash(&hash) {
+                hashes.push(hash.to_ascii_lowercase());
+            }
+        }
+        hashes.sort();
+
+        Ok(hashes)
+    }
 }
 
 impl PathRepository<Object> for ObjectRepository {
+    /// Store `item` under its hash. Objects are immutable, so one already
+    /// stored loose is only freshened: its mtime is bumped, so gc's grace
+    /// period covers it until the reference about to use it lands. A packed
+    /// one gets a fresh loose copy.
+    ///
+    /// Trees, commits and tags must be laid out in the repository's
+    /// encoding and refer to ids of its algorithm, otherwise they could not
+    /// be read back.
     fn write(&self, item: Object) -> Result<(), Error> {
-        let hash = hex::encode(item.to_hash());
+        if let Some(encoding) = item.encoding().filter(|e| e != &self.encoding) {
+            return Err(Error::MismatchedObjectEncoding(
+                encoding.to_string(),
+                self.encoding.to_string(),
+            ));
+        }
+        if let Some(id) = item
+            .object_ids()
+            .into_iter()
+            .find(|id| id.algorithm() != self.hash_algorithm)
+        {
+            return Err(Error::MismatchedHashAlgorithm(
+                id.algorithm().to_string(),
+                self.hash_algorithm.to_string(),
+            ));
+        }
+
+        let hash = hex::encode(item.to_hash_with(self.hash_algorithm));
         let (d, f) = split_hash(&hash);
         let p = self.root.join(d).join(f);
         create_dir(self.root.join(d))?;
-        write_content(p, &item.as_bytes(), WriteMode::CreateNewTrucate)?;
-
-        Ok(())
+        let mode = match self.compression {
+            Some(level) => WriteMode::AtomicCreateNewWithZlibLevel(level),
+            None => WriteMode::AtomicCreateNew,
+        };
+        let result = write_content(&p, &item.as_bytes(), mode);
+        match result {
+            // Written before or meanwhile by another process
+            Err(NssIoError::IOError(e)) if e.kind() == io::ErrorKind::AlreadyExists => freshen(&p),
+            result => Ok(result?),
+        }
     }
 
     fn read<P: Into<String>>(&self, p: P) -> Result<Object, Error> {
         let p = p.into();
-        let (d, f) = split_hash(&p);
-        let p = self.root.join(d).join(f);
-        let content = read_content(p, ReadMode::default())?;
+        if let Some(object) = self.cache.get(&p) {
+            return Ok(object);
+        }
+
+        let object = self.read_uncached(&p)?;
+        self.cache.insert(&p, &object);
 
-        Ok(Object::from_content(content)?)
+        Ok(object)
     }
 }
 
 impl From<PathBuf> for ObjectRepository {
     fn from(root: PathBuf) -> Self {
-        Self { root }
+        Self {
+            root,
+            compression: None,
+            hash_algorithm: HashAlgorithm::default(),
+            encoding: ObjectEncoding::default(),
+            cache: Arc::new(ObjectCache::new(DEFAULT_CACHE_SIZE)),
+            packs: Arc::default(),
+        }
     }
 }
 
-fn object_path<T: Into<String>>(root: PathBuf, hash: T) -> (PathBuf, PathBuf) {
-    let hash = hash.into();
-    let (dir, file) = hash.split_at(2);
-
-    let obj_dir = root.join(REPO_NAME).join(OBJECT_NAME).join(dir);
-
-    let obj_file = obj_dir.join(file);
-
-    (obj_dir, obj_file)
-}
-
 #[derive(Debug, Clone)]
 pub struct LocalBookMarkRepository {
     root: PathBuf,
+    reflog: Option<Reflog>,
 }
 
 impl PathRepository<BookMark> for LocalBookMarkRepository {
     fn write(&self, item: BookMark) -> Result<(), Error> {
-        let p = self.root.join(item.name);
-        write_content(p, item.hash.as_bytes(), WriteMode::default())?;
-
-        Ok(())
+        self.write_with_reason(item, "update")
     }
 
+    /// Fails with [`Error::UnbornBookmark`] on an empty bookmark file.
     fn read<P: Into<String>>(&self, bookmarker: P) -> Result<BookMark, Error> {
-        let p = self.root.join(bookmarker.into());
+        let name = bookmarker.into();
+        let p = self.root.join(&name);
         let bytes = read_content(&p, ReadMode::default())?;
         let content = String::from_utf8(bytes)?;
+        let content = content.trim();
+        if content.is_empty() {
+            return Err(Error::UnbornBookmark(name));
+        }
+
+        Ok(BookMark::new(p, ObjectId::from_hex(content)?))
+    }
+}
+
+impl LocalBookMarkRepository {
+    /// Log every bookmark write in `reflog`.
+    pub(crate) fn with_reflog(mut self, reflog: Reflog) -> Self {
+        self.reflog = Some(reflog);
+        self
+    }
+
+    /// Write the bookmark and log the change with `reason`.
+    ///
+    /// HEAD is logged as well when it points at this bookmark. Like HEAD,
+    /// the old value is read and logged under the lock.
+    pub fn write_with_reason(&self, item: BookMark, reason: &str) -> Result<(), Error> {
+        let name = item.name.display().to_string();
+        let p = self.root.join(item.name);
+        create_dir(p.parent().unwrap())?;
+        let hash = item.hash.to_hex();
+        let lock = LockFile::acquire(&p).map_err(lock_error)?;
+
+        let update = match &self.reflog {
+            Some(reflog) => Some((reflog, reflog.bookmark_hash(&name)?)),
+            None => None,
+        };
+        // Logged once the change has landed, still under the lock
+        lock.replace(hash.as_bytes())?;
+        if let Some((reflog, old)) = update {
+            log_bookmark_update(reflog, &name, &old, &hash, reason)?;
+        }
 
-        Ok(BookMark::new(p, content))
+        Ok(())
+    }
+
+    /// Start a set of bookmark updates applied all together or not at all.
+    pub fn transaction(&self) -> BookMarkTransaction {
+        BookMarkTransaction::new(self.root.clone(), self.reflog.clone())
     }
 }
 
 impl From<PathBuf> for LocalBookMarkRepository {
     fn from(root: PathBuf) -> Self {
-        Self { root }
+        Self { root, reflog: None }
     }
 }
 
@@ -202,6 +638,11 @@ pub struct NssRepository {
     pub objects: ObjectRepository,
     pub head: HeadRepository,
     pub bookmark: LocalBookMarkRepository,
+    pub tags: TagRepository,
+    large_objects: LargeObjectStore,
+    nss_dir: PathBuf,
+    common_dir: PathBuf,
+    bare: bool,
 }
 
 impl NssRepository {
@@ -214,11 +655,75 @@ impl NssRepository {
     }
 
     pub fn new(root: PathBuf) -> Self {
-        let config = root.join(REPO_NAME).join(CONFIG_NAME).into();
-        let index = root.join(REPO_NAME).join(INDEX_NAME).into();
-        let objects = root.join(REPO_NAME).join(OBJECT_NAME).into();
-        let head = root.join(REPO_NAME).join(HEAD_NAME).into();
-        let bookmark = root.join(REPO_NAME).join(BOOKMARK_NAME).into();
+        let nss_dir = root.join(REPO_NAME);
+
+        Self::with_layout(root, nss_dir, false)
+    }
+
+    /// Addresser for an existing repository at `root`.
+    ///
+    /// Unlike [`NssRepository::new`] the format version is checked, so
+    /// repositories written by a newer format are refused.
+    pub fn open(root: PathBuf) -> Result<Self, Error> {
+        let repository = Self::new(root);
+        check_format_version(&repository)?;
+        repository.check_hash_algorithm()?;
+
+        Ok(repository)
+    }
+
+    /// Addresser for a bare repository.
+    ///
+    /// `root` is the object and bookmark store itself, there is no working
+    /// tree and no `.nss` directory.
+    pub fn new_bare(root: PathBuf) -> Self {
+        let nss_dir = root.clone();
+
+        Self::with_layout(root, nss_dir, true)
+    }
+
+    /// Addresser for a working tree whose repository directory lives
+    /// elsewhere, e.g. given by `NSS_DIR` or a `.nss` file.
+    ///
+    /// When `nss_dir` is a linked worktree directory (it has a `commondir`
+    /// file), config, objects and bookmarks come from the shared directory.
+    pub fn with_nss_dir(root: PathBuf, nss_dir: PathBuf) -> Self {
+        Self::with_layout(root, nss_dir, false)
+    }
+
+    fn with_layout(root: PathBuf, nss_dir: PathBuf, bare: bool) -> Self {
+        let common_dir = match fs::read_to_string(nss_dir.join(COMMON_DIR_NAME)) {
+            Ok(content) => normalize_path(nss_dir.join(content.trim())),
+            Err(_) => nss_dir.clone(),
+        };
+
+        let config = ConfigRepository::from(common_dir.join(CONFIG_NAME));
+        let settings = config.read().ok();
+        let settings = settings.as_ref();
+        let compression = settings.and_then(loose_compression);
+        let hash_algorithm = settings.map_or(HashAlgorithm::default(), |c| c.hash_algorithm());
+        let encoding = settings.map_or(ObjectEncoding::default(), |c| c.object_encoding());
+        let cache_size = settings.map_or(DEFAULT_CACHE_SIZE, |c| c.cache_size());
+        let large_patterns = settings.map_or(vec![], |c| c.large_patterns().to_vec());
+        let reflog =
+            Reflog::new(nss_dir.clone(), common_dir.clone()).with_hash_algorithm(hash_algorithm);
+
+        // Per worktree
+        let index =
+            IndexRepository::from(nss_dir.join(INDEX_NAME)).with_hash_algorithm(hash_algorithm);
+        let head = HeadRepository::from(nss_dir.join(HEAD_NAME)).with_reflog(reflog.clone());
+
+        // Shared by every worktree
+        let objects = ObjectRepository::from(common_dir.join(OBJECT_NAME))
+            .with_compression(compression)
+            .with_hash_algorithm(hash_algorithm)
+            .with_encoding(encoding)
+            .with_cache_size(cache_size);
+        let bookmark =
+            LocalBookMarkRepository::from(common_dir.join(BOOKMARK_NAME)).with_reflog(reflog);
+        let tags = TagRepository::from(common_dir.join(TAG_NAME));
+        let large_objects =
+            LargeObjectStore::new(common_dir.join(LARGE_NAME), large_patterns, hash_algorithm);
 
         Self {
             root,
@@ -227,6 +732,39 @@ impl NssRepository {
             objects,
             head,
             bookmark,
+            tags,
+            large_objects,
+            nss_dir,
+            common_dir,
+            bare,
+        }
+    }
+
+    /// Directory holding this worktree's HEAD and INDEX.
+    pub fn nss_dir(&self) -> PathBuf {
+        self.nss_dir.clone()
+    }
+
+    /// Directory holding config, objects and bookmarks shared by every
+    /// worktree. Same as [`NssRepository::nss_dir`] outside linked worktrees.
+    pub fn common_dir(&self) -> PathBuf {
+        self.common_dir.clone()
+    }
+
+    /// Whether this is a linked worktree rather than the main one.
+    pub fn is_linked_worktree(&self) -> bool {
+        self.nss_dir != self.common_dir
+    }
+
+    pub fn is_bare(&self) -> bool {
+        self.bare
+    }
+
+    /// Working tree root, or `None` for a bare repository.
+    pub fn work_tree(&self) -> Option<PathBuf> {
+        match self.bare {
+            true => None,
+            false => Some(self.root.clone()),
         }
     }
 
@@ -250,44 +788,285 @@ impl NssRepository {
         &self.bookmark
     }
 
-    // pub fn create(root: PathBuf) -> Self {
+    /// Lightweight and annotated tags, see [`crate::repo::tags`].
+    pub fn tags(&self) -> &TagRepository {
+        &self.tags
+    }
+
+    /// Store of files matching `large.patterns`, shared by every worktree.
+    pub fn large_objects(&self) -> &LargeObjectStore {
+        &self.large_objects
+    }
+
+    /// Store the work tree file at `path` and return its blob hash.
+    ///
+    /// Files matching `large.patterns` go to the large-object store behind a
+    /// pointer blob, others are streamed into the object database. Content
+    /// already stored is not written again.
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> Result<String, Error> {
+        let path = path.as_ref();
+        let len = path.metadata()?.len();
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+
+        if self.large_objects.is_large(relative) {
+            let pointer = self.large_objects.store(fs::File::open(path)?, len)?;
+            let blob = Object::Blob(pointer.to_blob());
+            let hash = hex::encode(blob.to_hash_with(self.hash_algorithm()));
+            self.objects.write(blob)?;
+
+            return Ok(hash);
+        }
+
+        let hash = hex::encode(Blob::hash_reader(
+            fs::File::open(path)?,
+            len,
+            self.hash_algorithm(),
+        )?);
+        if !self.objects.has_object(&hash)? {
+            self.objects.write_blob_stream(fs::File::open(path)?, len)?;
+        }
+
+        Ok(hash)
+    }
+
+    /// Hash function of object ids, set by `core.objectformat`.
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.objects.hash_algorithm()
+    }
+
+    /// Layout of trees, commits and tags, set by `core.objectencoding`.
+    pub fn object_encoding(&self) -> ObjectEncoding {
+        self.objects.object_encoding()
+    }
+
+    /// Refuse a repository whose HEAD hash is not of the configured
+    /// algorithm, e.g. a SHA-1 store whose config was edited to SHA-256.
+    pub fn check_hash_algorithm(&self) -> Result<(), Error> {
+        let algorithm = self.hash_algorithm();
+        let hash = self.reflog().head_hash()?;
+        let hash = hash.trim();
+        if algorithm.is_hex_hash(hash) {
+            return Ok(());
+        }
+
+        let found = [HashAlgorithm::Sha1, HashAlgorithm::Sha256]
+            .into_iter()
+            .find(|a| a.is_hex_hash(hash))
+            .map(|a| a.to_string())
+            .unwrap_or_else(|| format!("{}-digit hash", hash.len()));
+
+        Err(Error::MismatchedHashAlgorithm(algorithm.to_string(), found))
+    }
+
+    /// Create a fresh repository layout under `root`.
+    ///
+    /// Builds `.nss/objects`, `.nss/bookmarks` and `.nss/tags`, writes
+    /// `config`, an empty `INDEX` and a `HEAD` pointing at the initial
+    /// bookmark. An existing repository is refused unless `reinitialize` is
+    /// set; reinitializing recreates missing pieces and rewrites the config,
+    /// but keeps HEAD, INDEX, objects, bookmarks and tags as they are.
+    pub fn init(root: PathBuf, config: Config, reinitialize: bool) -> Result<Self, Error> {
+        Self::new(root).create_layout(config, reinitialize)
+    }
+
+    /// Create a fresh bare repository directly under `root`.
+    ///
+    /// Same layout as [`NssRepository::init`] without `.nss` and `INDEX`,
+    /// and the config is marked as bare.
+    pub fn init_bare(root: PathBuf, mut config: Config, reinitialize: bool) -> Result<Self, Error> {
+        config.set_bare(true);
+
+        Self::new_bare(root).create_layout(config, reinitialize)
+    }
+
+    fn create_layout(mut self, mut config: Config, reinitialize: bool) -> Result<Self, Error> {
+        let exists = match self.bare {
+            true => self.config.root.exists() || self.head.root.exists(),
+            false => self.nss_dir.exists(),
+        };
+        if exists && !reinitialize {
+            return Err(Error::AlreadyExistsRepository(self.nss_dir));
+        }
+
+        create_dir(&self.objects.root)?;
+        create_dir(&self.bookmark.root)?;
+        create_dir(&self.tags.root)?;
+
+        let version = match exists {
+            true => check_format_version(&self)?,
+            false => CURRENT_FORMAT_VERSION,
+        };
+        config.set_repository_format_version(version);
+
+        // Existing objects cannot be renamed to another algorithm
+        if let Ok(existing) = self.config().read() {
+            if !config.is_hash_algorithm_set() {
+                config.set_hash_algorithm(existing.hash_algorithm());
+            } else if existing.hash_algorithm() != config.hash_algorithm() {
+                return Err(Error::MismatchedHashAlgorithm(
+                    config.hash_algorithm().to_string(),
+                    existing.hash_algorithm().to_string(),
+                ));
+            }
+
+            if !config.is_object_encoding_set() {
+                config.set_object_encoding(existing.object_encoding());
+            } else if existing.object_encoding() != config.object_encoding() {
+                return Err(Error::MismatchedObjectEncoding(
+                    config.object_encoding().to_string(),
+                    existing.object_encoding().to_string(),
+                ));
+            }
+        }
+        if version < GIT_ENCODING_FORMAT_VERSION && config.object_encoding() == ObjectEncoding::Git
+        {
+            return Err(Error::FormatVersionTooOld(
+                version,
+                "git-encoded objects".to_string(),
+            ));
+        }
+
+        let hash_algorithm = config.hash_algorithm();
+        self.objects = self
+            .objects
+            .with_compression(loose_compression(&config))
+            .with_hash_algorithm(hash_algorithm)
+            .with_encoding(config.object_encoding())
+            .with_cache_size(config.cache_size());
+        self.index = self.index.with_hash_algorithm(hash_algorithm);
+        let reflog = self.reflog();
+        self.head = self.head.with_reflog(reflog.clone());
+        self.bookmark = self.bookmark.with_reflog(reflog);
+        self.large_objects = LargeObjectStore::new(
+            self.common_dir.join(LARGE_NAME),
+            config.large_patterns().to_vec(),
+            hash_algorithm,
+        );
+        self.config().write(config)?;
+
+        if !self.head.root.exists() {
+            self.head()
+                .write(Head::Bookmarker(PathBuf::from(DEFAULT_BOOKMARK)))?;
+        }
+
+        if !self.bare && !self.index.root.exists() {
+            self.index().write(Index::empty())?;
+        }
+
+        Ok(self)
+    }
+
+    /// Remove HEAD, INDEX, bookmark and tag lock files older than `max_age`.
+    ///
+    /// A writer that crashed between taking and releasing a lock leaves
+    /// `<file>.lock` behind, and every later write then fails with
+    /// [`Error::RepositoryLocked`]. Returns the removed lock files.
+    pub fn remove_stale_locks(&self, max_age: Duration) -> Result<Vec<PathBuf>, Error> {
+        let mut locks = vec![lock_path(&self.head.root), lock_path(&self.index.root)];
+        lock_paths(&self.bookmark.root, &mut locks)?;
+        lock_paths(&self.tags.root, &mut locks)?;
+
+        let mut removed = vec![];
+        for lock in locks {
+            if lock.is_file() && is_stale_lock(&lock, max_age)? {
+                fs::remove_file(&lock)?;
+                removed.push(lock);
+            }
+        }
 
-    //     Self { root, config, index, objects, head, bookmark }
-    // }
+        Ok(removed)
+    }
 }
 
 // utility
-pub fn split_hash(hash: &str) -> (&str, &str) {
-    hash.split_at(2)
+fn write_with_lock<P: AsRef<Path>>(p: P, content: &[u8]) -> Result<(), Error> {
+    write_locked(p, content).map_err(lock_error)
 }
 
-pub fn try_get_objects_path<T: Into<String>>(root: PathBuf, hash: T) -> Result<PathBuf, Error> {
-    let hash = hash.into();
-
-    if hash.len() < 6 {
-        return Err(Error::NssStruct(StructError::LessObjectHash));
+/// Log a bookmark change, and the same change of HEAD when it follows
+/// that bookmark.
+pub(crate) fn log_bookmark_update(
+    reflog: &Reflog,
+    name: &str,
+    old: &str,
+    new: &str,
+    reason: &str,
+) -> Result<(), Error> {
+    reflog.append(name, old, new, reason)?;
+    if reflog.head_bookmark()?.as_deref() == Some(name) {
+        reflog.append(HEAD_NAME, old, new, reason)?;
     }
 
-    let (_, file) = hash.split_at(2);
-    let (object_dir, _) = object_path(root, &hash);
+    Ok(())
+}
+
+/// A held lock means another writer, everything else is a plain IO failure.
+pub(crate) fn lock_error(e: NssIoError) -> Error {
+    match e {
+        NssIoError::Locked(lock) => Error::RepositoryLocked(lock),
+        e => e.into(),
+    }
+}
 
-    let mut paths: Vec<PathBuf> = vec![];
-    ext_paths(&object_dir, &mut paths)?;
+fn lock_paths(dir: &Path, locks: &mut Vec<PathBuf>) -> Result<(), Error> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
 
-    let mut target_files: Vec<PathBuf> = vec![];
-    for path in paths {
-        if path.as_os_str().to_string_lossy().contains(file) {
-            target_files.push(path)
+    for dir_entry in dir.read_dir()? {
+        let path = dir_entry?.path();
+        if path.is_dir() {
+            lock_paths(&path, locks)?;
+        } else if is_lock_path(&path) {
+            locks.push(path);
         }
     }
 
-    if target_files.len() > 2 {
-        return Err(Error::NssStruct(StructError::CannotSpecifyHash));
-    } else if target_files.is_empty() {
-        return Err(Error::NssStruct(StructError::NotFoundObject));
+    Ok(())
+}
+
+fn normalize_path(path: PathBuf) -> PathBuf {
+    // Drop `.` and `..` lexically, without touching the filesystem
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => continue,
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            c => normalized.push(c),
+        }
     }
 
-    Ok(object_dir.join(&target_files[0]))
+    normalized
+}
+
+/// Bump the mtime of the loose object at `p`.
+pub(crate) fn freshen(p: &Path) -> Result<(), Error> {
+    fs::File::options()
+        .write(true)
+        .open(p)?
+        .set_modified(std::time::SystemTime::now())?;
+
+    Ok(())
+}
+
+pub fn split_hash(hash: &str) -> (&str, &str) {
+    hash.split_at(2)
+}
+
+/// Path of the object whose hash starts with `hash` (at least 6 digits),
+/// see [`ObjectDatabase::resolve_prefix`].
+///
+/// Packed objects are resolved too. Their path is where the loose file
+/// would be, so the full hash can still be read from it.
+pub fn try_get_objects_path<T: Into<String>>(root: PathBuf, hash: T) -> Result<PathBuf, Error> {
+    let repository = NssRepository::new(root);
+    let objects = repository.objects();
+    let hash = objects.resolve_prefix(&hash.into())?;
+    let (d, f) = split_hash(&hash);
+
+    Ok(objects.root.join(d).join(f))
 }
 
 fn ext_paths<P: AsRef<Path>>(target: P, paths: &mut Vec<PathBuf>) -> Result<(), Error> {
@@ -361,38 +1140,29 @@ pub fn get_all_paths_ignore<P: AsRef<Path>>(root: PathBuf, target: P) -> Vec<Pat
     paths
 }
 
+/// Find the repository for `repo_dir` (or the current directory).
+///
+/// Honors `NSS_DIR`, `NSS_WORK_TREE` and `NSS_CEILING_DIRECTORIES`,
+/// see [`Discovery`].
 pub fn exists_repo<P: AsRef<Path>>(repo_dir: Option<P>) -> Result<NssRepository, Error> {
-    let current_dir = match repo_dir {
-        Some(p) => {
-            if p.as_ref().to_path_buf() == home_dir().unwrap() {
-                return Err(Error::NotFoundRepository);
-            } else {
-                p.as_ref().to_path_buf()
-            }
-        }
+    let start = match repo_dir {
+        Some(p) => p.as_ref().to_path_buf(),
         _ => std::env::current_dir()?,
     };
 
-    let repo_path = current_dir.join(PathBuf::from(".nss"));
-    let read_dir = fs::read_dir(&current_dir)?;
-
-    for entry in read_dir {
-        match entry?.path() == repo_path {
-            true => return Ok(NssRepository::new(current_dir)),
-            false => continue,
-        }
-    }
-
-    return exists_repo(current_dir.parent());
+    Discovery::from_env().discover(start)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::repo::config::*;
+    use crate::repo::database::PrefixCandidate;
+    use crate::struct_set::Blob;
 
     use anyhow::Result;
     use std::env;
+    use std::ffi::OsString;
     use std::fs;
     use std::fs::File;
     use std::io::Read;
@@ -460,6 +1230,164 @@ email = \"nopenoshishi@gmail.com\"
         assert_eq!(result.unwrap(), test_config);
     }
 
+    #[test]
+    fn test_init_repository() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let user = User::new("noshishi".to_string(), None);
+        let repository = NssRepository::init(temp_dir.clone(), Config::new(user), false)?;
+
+        let repo_dir = temp_dir.join(".nss");
+        assert!(repo_dir.join("objects").is_dir());
+        assert!(repo_dir.join("bookmarks").is_dir());
+        assert!(repo_dir.join("INDEX").is_file());
+
+        let test_user = User::new("noshishi".to_string(), None);
+        let mut test_config = Config::new(test_user);
+        test_config.set_repository_format_version(CURRENT_FORMAT_VERSION);
+        assert_eq!(repository.config().read()?, test_config);
+
+        match repository.head().read()? {
+            Head::Bookmarker(p) => assert_eq!(p, PathBuf::from("main")),
+            head => panic!("unexpected head: {:?}", head),
+        }
+
+        assert_eq!(repository.index().read()?, Index::empty());
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_existed_repository() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        repository.head().write(Head::ObjectHash(
+            "a02b83cb54ba139e5c9d623a2fcf5424552946e0".parse()?,
+        ))?;
+
+        // Refuse to init twice
+        let result = NssRepository::init(temp_dir.clone(), Config::default(), false);
+        assert!(matches!(result, Err(Error::AlreadyExistsRepository(_))));
+
+        // Reinitialize keeps head and recreates missing layout
+        fs::remove_dir_all(temp_dir.join(".nss").join("bookmarks"))?;
+        let user = User::new("noshishi".to_string(), None);
+        let repository = NssRepository::init(temp_dir.clone(), Config::new(user), true)?;
+        assert!(temp_dir.join(".nss").join("bookmarks").is_dir());
+        assert_eq!(repository.config().read()?.username(), "noshishi");
+        match repository.head().read()? {
+            Head::ObjectHash(h) => {
+                assert_eq!(h.to_hex(), "a02b83cb54ba139e5c9d623a2fcf5424552946e0")
+            }
+            head => panic!("unexpected head: {:?}", head),
+        }
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_bare_repository() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init_bare(temp_dir.clone(), Config::default(), false)?;
+        assert!(repository.is_bare());
+        assert_eq!(repository.work_tree(), None);
+        assert_eq!(repository.nss_dir(), temp_dir);
+
+        assert!(temp_dir.join("objects").is_dir());
+        assert!(temp_dir.join("bookmarks").is_dir());
+        assert!(temp_dir.join("HEAD").is_file());
+        assert!(!temp_dir.join("INDEX").exists());
+        assert!(!temp_dir.join(".nss").exists());
+        assert!(repository.config().read()?.is_bare());
+
+        let result = NssRepository::init_bare(temp_dir.clone(), Config::default(), false);
+        assert!(matches!(result, Err(Error::AlreadyExistsRepository(_))));
+
+        // Operations on the working tree are refused
+        let result = Index::new_all(&repository);
+        assert!(matches!(result, Err(StructError::BareRepository)));
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_locked() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let nss_dir = repository.nss_dir();
+
+        // Someone else holds the locks
+        fs::write(nss_dir.join("HEAD.lock"), b"")?;
+        fs::write(nss_dir.join("INDEX.lock"), b"")?;
+        fs::write(nss_dir.join("bookmarks").join("main.lock"), b"")?;
+
+        let hash: ObjectId = "a02b83cb54ba139e5c9d623a2fcf5424552946e0".parse()?;
+        let result = repository.head().write(Head::ObjectHash(hash));
+        assert!(
+            matches!(result, Err(Error::RepositoryLocked(p)) if p == nss_dir.join("HEAD.lock"))
+        );
+        let result = repository.index().write(Index::empty());
+        assert!(matches!(result, Err(Error::RepositoryLocked(_))));
+        let result = repository
+            .bookmark()
+            .write(BookMark::new(PathBuf::from("main"), hash));
+        assert!(matches!(result, Err(Error::RepositoryLocked(_))));
+
+        // Untouched targets
+        assert!(matches!(repository.head().read()?, Head::Bookmarker(_)));
+        assert!(!nss_dir.join("bookmarks").join("main").exists());
+
+        // Fresh locks are kept, stale ones removed
+        assert!(repository
+            .remove_stale_locks(Duration::from_secs(60))?
+            .is_empty());
+        let removed = repository.remove_stale_locks(Duration::ZERO)?;
+        assert_eq!(removed.len(), 3);
+
+        repository
+            .bookmark()
+            .write(BookMark::new(PathBuf::from("feature/topic"), hash))?;
+        assert_eq!(repository.bookmark().read("feature/topic")?.hash, hash);
+
+        // Malformed and empty bookmarks are refused on read
+        let bookmarks_dir = nss_dir.join("bookmarks");
+        fs::write(bookmarks_dir.join("broken"), b"abc")?;
+        assert!(matches!(
+            repository.bookmark().read("broken"),
+            Err(Error::NssStruct(StructError::InvalidObjectId(_)))
+        ));
+        fs::write(bookmarks_dir.join("unborn"), b"")?;
+        assert!(matches!(
+            repository.bookmark().read("unborn"),
+            Err(Error::UnbornBookmark(_))
+        ));
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
     #[test]
     fn test_write_head() {}
 
@@ -473,13 +1401,449 @@ email = \"nopenoshishi@gmail.com\"
     fn test_read_index() {}
 
     #[test]
-    fn test_write_object() {}
+    fn test_write_object() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let object = Object::Blob(Blob {
+            content: b"atomic".to_vec(),
+        });
+        let hash = hex::encode(object.to_hash());
+        repository.objects().write(object.clone())?;
+
+        let object_dir = repository.common_dir().join("objects").join(&hash[..2]);
+        let names: Vec<_> = fs::read_dir(&object_dir)?
+            .map(|e| e.map(|e| e.file_name()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(names, vec![OsString::from(&hash[2..])]);
+        let stored = fs::read(object_dir.join(&hash[2..]))?;
+        assert_ne!(stored, object.as_bytes());
+        assert_eq!(
+            repository.objects().read(&hash)?.as_bytes(),
+            object.as_bytes()
+        );
+
+        // Writing an existing object succeeds, leaves it untouched and
+        // freshens it
+        let old = std::time::SystemTime::now() - Duration::from_secs(30 * 24 * 60 * 60);
+        fs::File::options()
+            .write(true)
+            .open(object_dir.join(&hash[2..]))?
+            .set_modified(old)?;
+        repository.objects().write(object.clone())?;
+        assert_eq!(fs::read(object_dir.join(&hash[2..]))?, stored);
+        let mtime = fs::metadata(object_dir.join(&hash[2..]))?.modified()?;
+        assert!(mtime > old + Duration::from_secs(60));
+        assert!(repository.objects().has_object(&hash)?);
+        let info = repository.objects().object_info(&hash)?;
+        assert_eq!(info.object_type, "blob");
+        assert_eq!(info.size, 6);
+
+        // Legacy raw object
+        let legacy = Object::Blob(Blob {
+            content: b"legacy".to_vec(),
+        });
+        let legacy_hash = hex::encode(legacy.to_hash());
+        let legacy_dir = repository
+            .common_dir()
+            .join("objects")
+            .join(&legacy_hash[..2]);
+        fs::create_dir_all(&legacy_dir)?;
+        fs::write(legacy_dir.join(&legacy_hash[2..]), legacy.as_bytes())?;
+        assert_eq!(
+            repository.objects().read(&legacy_hash)?.as_bytes(),
+            legacy.as_bytes()
+        );
+
+        // Malformed hashes are rejected, not split
+        let objects = repository.objects();
+        let upper = hash.to_uppercase();
+        for bad in ["", "a", &hash[..8], "zz", "é", &upper] {
+            assert!(matches!(
+                objects.read(bad),
+                Err(Error::NssStruct(StructError::InvalidObjectId(_)))
+            ));
+            assert!(objects.object_info(bad).is_err());
+            assert!(objects.read_blob_stream(bad).is_err());
+            assert!(!objects.has_object(bad)?);
+            assert!(repository.read_object(bad).is_err());
+        }
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_object_unmigrated() -> Result<()> {
+        use crate::repo::migration::migrate_to_current;
+
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let mut config = repository.config().read()?;
+        config.set_repository_format_version(1);
+        repository.config().write(config)?;
+
+        // Format 1 has no compressed objects, whole or streamed
+        let repository = NssRepository::open(temp_dir.clone())?;
+        let object = Object::Blob(Blob {
+            content: b"raw".to_vec(),
+        });
+        let hash = hex::encode(object.to_hash());
+        repository.objects().write(object.clone())?;
+        let streamed = repository
+            .objects()
+            .write_blob_stream(&b"streamed"[..], 8)?;
+        let objects_dir = repository.common_dir().join("objects");
+        let (d, f) = split_hash(&hash);
+        assert_eq!(fs::read(objects_dir.join(d).join(f))?, object.as_bytes());
+        let (d, f) = split_hash(&streamed);
+        assert_eq!(fs::read(objects_dir.join(d).join(f))?, b"blob 8\0streamed");
+
+        // Compressed once migrated
+        migrate_to_current(&repository)?;
+        let repository = NssRepository::open(temp_dir.clone())?;
+        let object = Object::Blob(Blob {
+            content: b"zlib".to_vec(),
+        });
+        let hash = hex::encode(object.to_hash());
+        repository.objects().write(object.clone())?;
+        let (d, f) = split_hash(&hash);
+        assert_ne!(fs::read(objects_dir.join(d).join(f))?, object.as_bytes());
+        assert_eq!(
+            repository.objects().read(&hash)?.as_bytes(),
+            object.as_bytes()
+        );
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sha256_repository() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let mut config = Config::default();
+        config.set_hash_algorithm(HashAlgorithm::Sha256);
+        let repository = NssRepository::init(temp_dir.clone(), config, false)?;
+        assert_eq!(repository.hash_algorithm(), HashAlgorithm::Sha256);
+
+        let blob = Blob {
+            content: b"sha256".to_vec(),
+        };
+        repository.objects().write(Object::Blob(blob.clone()))?;
+        let hash = hex::encode(blob.to_hash_with(HashAlgorithm::Sha256));
+        assert_eq!(hash.len(), 64);
+        assert_eq!(
+            repository.objects().read(&hash)?.as_bytes(),
+            Object::Blob(blob.clone()).as_bytes()
+        );
+
+        // Trees built from the work tree name entries with SHA-256
+        let src = temp_dir.join("src");
+        fs::create_dir_all(src.join("sub"))?;
+        fs::write(src.join("sub").join("file.rs"), "sha256")?;
+        let tree = Object::new_with(&src, HashAlgorithm::Sha256, repository.object_encoding())?;
+        repository.objects().write(tree.clone())?;
+        let tree_hash = hex::encode(tree.to_hash_with(HashAlgorithm::Sha256));
+        let Object::Tree(read) = repository.objects().read(&tree_hash)? else {
+            panic!("not a tree");
+        };
+        assert_eq!(read.entries[0].hash.algorithm(), HashAlgorithm::Sha256);
+
+        // SHA-1 ids are refused
+        assert!(matches!(
+            repository.objects().write(Object::new(&src)?),
+            Err(Error::MismatchedHashAlgorithm(_, _))
+        ));
+        fs::remove_dir_all(&src)?;
+
+        // Index entries keep 32-byte hashes
+        let mut index = Index::empty();
+        index.filemetas.push(crate::struct_set::FileMeta::from_blob(
+            "file",
+            0o100644,
+            &blob,
+            HashAlgorithm::Sha256,
+        ));
+        repository.index().write(index)?;
+        let reopened = NssRepository::open(temp_dir.clone())?;
+        assert_eq!(reopened.hash_algorithm(), HashAlgorithm::Sha256);
+        assert_eq!(reopened.index().read()?.filemetas[0].hash.to_hex(), hash);
+
+        // Reinitializing keeps the algorithm, switching it is refused
+        NssRepository::init(temp_dir.clone(), Config::default(), true)?;
+        assert_eq!(
+            NssRepository::new(temp_dir.clone()).hash_algorithm(),
+            HashAlgorithm::Sha256
+        );
+        let mut sha1 = Config::default();
+        sha1.set_hash_algorithm(HashAlgorithm::Sha1);
+        assert!(matches!(
+            NssRepository::init(temp_dir.clone(), sha1, true),
+            Err(Error::MismatchedHashAlgorithm(_, _))
+        ));
+
+        // Missing values in the reflog are SHA-256 sized
+        assert_eq!(repository.reflog().head_hash()?, "0".repeat(64));
+        repository
+            .bookmark()
+            .write(BookMark::new(PathBuf::from("main"), hash.parse()?))?;
+        let entries = repository.reflog().read("main")?;
+        assert_eq!(entries[0].old, "0".repeat(64));
+        assert_eq!(repository.reflog().read(HEAD_NAME)?[0].old, "0".repeat(64));
+        repository
+            .bookmark()
+            .transaction()
+            .update("topic", None, hash.parse()?)
+            .commit()?;
+        assert_eq!(repository.reflog().read("topic")?[0].old, "0".repeat(64));
+
+        // SHA-1 HEAD in a SHA-256 repository is refused on open
+        repository
+            .head()
+            .write(Head::ObjectHash("a".repeat(40).parse()?))?;
+        assert!(matches!(
+            NssRepository::open(temp_dir.clone()),
+            Err(Error::MismatchedHashAlgorithm(_, _))
+        ));
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_encoded_repository() -> Result<()> {
+        use crate::repo::pack::{repack, RepackOptions};
+        use crate::struct_set::{Entry, Tree};
+
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let mut config = Config::default();
+        config.set_object_encoding(ObjectEncoding::Git);
+        let repository = NssRepository::init(temp_dir.clone(), config, false)?;
+        assert_eq!(repository.object_encoding(), ObjectEncoding::Git);
+
+        let blob = Object::Blob(Blob {
+            content: b"first".to_vec(),
+        });
+        let tree = Tree::from_entries(vec![Entry {
+            mode: 0o100644,
+            name: OsString::from("first.rs"),
+            hash: blob.object_id(),
+        }]);
+        let commit = Commit::new(
+            tree.clone().with_encoding(ObjectEncoding::Git).object_id(),
+            vec![],
+            "nope <nope@nope.com>",
+            "nope <nope@nope.com>",
+            "initial",
+        )?;
+
+        // Objects of the other encoding could not be read back
+        assert!(matches!(
+            repository.objects().write(Object::Tree(tree.clone())),
+            Err(Error::MismatchedObjectEncoding(_, _))
+        ));
+        assert!(matches!(
+            repository.objects().write(Object::Commit(commit.clone())),
+            Err(Error::MismatchedObjectEncoding(_, _))
+        ));
+
+        // Work tree directories are read in the repository's encoding
+        let src = temp_dir.join("src");
+        fs::create_dir_all(src.join("nested"))?;
+        fs::write(src.join("nested").join("first.rs"), b"first")?;
+        assert!(matches!(
+            repository.objects().write(Object::new(&src)?),
+            Err(Error::MismatchedObjectEncoding(_, _))
+        ));
+        let work_tree = Object::new_with(
+            &src,
+            repository.hash_algorithm(),
+            repository.object_encoding(),
+        )?;
+        repository.objects().write(work_tree.clone())?;
+        assert_eq!(work_tree.encoding(), Some(ObjectEncoding::Git));
+
+        let tree = Object::Tree(tree.with_encoding(ObjectEncoding::Git));
+        let commit = Object::Commit(commit.with_encoding(ObjectEncoding::Git));
+        for object in [&blob, &tree, &commit] {
+            repository.objects().write(object.clone())?;
+        }
+
+        // Loose and packed objects are read in the git encoding
+        let reopened = NssRepository::open(temp_dir.clone())?;
+        assert_eq!(reopened.object_encoding(), ObjectEncoding::Git);
+        for object in [&tree, &commit] {
+            let read = reopened.objects().read(object.object_id().to_hex())?;
+            assert_eq!(read.encoding(), Some(ObjectEncoding::Git));
+            assert_eq!(read.as_bytes(), object.as_bytes());
+        }
+        repack(&reopened, &RepackOptions::default())?;
+        let reopened = NssRepository::open(temp_dir.clone())?;
+        let read = reopened.objects().read(commit.object_id().to_hex())?;
+        assert_eq!(read.as_bytes(), commit.as_bytes());
+
+        // Reinitializing keeps the encoding, switching it is refused
+        NssRepository::init(temp_dir.clone(), Config::default(), true)?;
+        assert_eq!(
+            NssRepository::open(temp_dir.clone())?.object_encoding(),
+            ObjectEncoding::Git
+        );
+        let mut nss = Config::default();
+        nss.set_object_encoding(ObjectEncoding::Nss);
+        assert!(matches!(
+            NssRepository::init(temp_dir.clone(), nss, true),
+            Err(Error::MismatchedObjectEncoding(_, _))
+        ));
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_object_cache() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let object = Object::Blob(Blob {
+            content: b"cached".to_vec(),
+        });
+        let hash = hex::encode(object.to_hash());
+        repository.objects().write(object.clone())?;
+
+        repository.objects().read(&hash)?;
+        let objects = repository.objects().clone();
+        assert_eq!(objects.read(&hash)?.as_bytes(), object.as_bytes());
+
+        // Clones share the cache
+        let stats = repository.objects().cache_stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.budget, DEFAULT_CACHE_SIZE);
+
+        repository.objects().clear_cache();
+        assert_eq!(repository.objects().cache_stats().entries, 0);
+
+        // A budget of 0 reads from disk every time
+        let mut config = Config::default();
+        config.set_cache_size(0);
+        let repository = NssRepository::init(temp_dir.clone(), config, true)?;
+        repository.objects().read(&hash)?;
+        repository.objects().read(&hash)?;
+        assert_eq!(repository.objects().cache_stats().hits, 0);
+        assert_eq!(
+            NssRepository::new(temp_dir.clone())
+                .objects()
+                .cache_stats()
+                .budget,
+            0
+        );
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
 
     #[test]
     fn test_read_object() {}
 
     #[test]
-    fn test_try_get_objects_path() {}
+    fn test_try_get_objects_path() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let object = Object::Blob(Blob {
+            content: b"prefix".to_vec(),
+        });
+        let hash = hex::encode(object.to_hash());
+        repository.objects().write(object)?;
+
+        let path = try_get_objects_path(temp_dir.clone(), &hash[..8])?;
+        assert!(path.ends_with(format!("{}/{}", &hash[..2], &hash[2..])));
+
+        // A commit sharing the first 10 digits
+        let commit = Object::Commit(Commit::new(
+            ObjectId::zero(HashAlgorithm::Sha1),
+            vec![],
+            "nopeNoshihsi",
+            "nopeNoshihsi",
+            "initial",
+        )?);
+        let commit_hash = hex::encode(commit.to_hash());
+        repository.objects().write(commit)?;
+        let digit = if &hash[10..11] == "0" { "1" } else { "0" };
+        let fake = format!("{}{}{}", &hash[..10], digit, &commit_hash[11..]);
+        let (d, f) = split_hash(&commit_hash);
+        let objects_dir = repository.common_dir().join(OBJECT_NAME);
+        fs::rename(
+            objects_dir.join(d).join(f),
+            objects_dir.join(&fake[..2]).join(&fake[2..]),
+        )?;
+
+        match try_get_objects_path(temp_dir.clone(), &hash[..8]) {
+            Err(Error::AmbiguousPrefix(prefix, candidates)) => {
+                assert_eq!(prefix, &hash[..8]);
+                let mut expected = vec![
+                    PrefixCandidate {
+                        hash: hash.clone(),
+                        object_type: "blob".to_string(),
+                    },
+                    PrefixCandidate {
+                        hash: fake.clone(),
+                        object_type: "commit".to_string(),
+                    },
+                ];
+                expected.sort_by(|a, b| a.hash.cmp(&b.hash));
+                assert_eq!(candidates, expected);
+            }
+            result => panic!("expected an ambiguous prefix, got {:?}", result),
+        }
+
+        // Narrowed by type or by length
+        let objects = repository.objects();
+        assert_eq!(objects.resolve_prefix_as(&hash[..8], Some("blob"))?, hash);
+        assert_eq!(objects.resolve_prefix_as(&hash[..8], Some("commit"))?, fake);
+        assert!(matches!(
+            objects.resolve_prefix_as(&hash[..8], Some("tree")),
+            Err(Error::NssStruct(StructError::NotFoundObject))
+        ));
+        assert_eq!(objects.abbreviation_len(&hash)?, 11);
+        assert_eq!(objects.resolve_prefix(&objects.abbreviate(&fake)?)?, fake);
+
+        // Only the start of a hash is matched
+        assert!(matches!(
+            try_get_objects_path(temp_dir.clone(), &hash[2..12]),
+            Err(Error::NssStruct(StructError::NotFoundObject))
+        ));
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
 
     #[test]
     fn test_ext_paths() {}
@@ -0,0 +1,289 @@
+//! Streaming blobs
+//!
+//! Blobs too large to hold in memory are hashed, stored and read back in
+//! chunks.
+//!     Write:
+//!         - the header comes from the known length, `blob <len>\0`
+//!         - content is compressed into a temp file while being hashed,
+//!           or copied raw before format version 2
+//!         - the temp file is linked to its hash once complete
+//!     Read:
+//!         - [`BlobReader`] inflates a loose object as it is read
+//!         - packed blobs are inflated in memory, packs hold small objects
+//!
+
+// Std
+use std::fs::{self, OpenOptions};
+use std::io::{self, Cursor, Read, Write};
+use std::path::Path;
+
+// Internal
+use super::error::Error;
+use super::repository::{freshen, split_hash};
+use crate::nss_io::file_system::{
+    create_dir, open_content, rename_new, sync_dir, temp_path, ReadMode,
+};
+use crate::nss_io::zlib::write_encoder_with_level;
+use crate::struct_set::error::Error as StructError;
+use crate::struct_set::{Blob, HashAlgorithm, StreamHasher};
+
+// Longest header read before giving up, `blob <u64>\0` fits easily
+const MAX_HEADER_SIZE: usize = 32;
+
+/// **BlobReader Struct**
+///
+/// Content of a stored blob, read as a stream.
+pub struct BlobReader {
+    size: u64,
+    inner: Box<dyn Read>,
+}
+
+impl BlobReader {
+    /// Content size given by the object header.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Open the loose object at `path`, compressed or legacy raw.
+    pub(crate) fn open_loose<P: AsRef<Path>>(path: P, hash: &str) -> Result<Self, Error> {
+        let mut reader = open_content(path, ReadMode::DetectZlib)?;
+        let size = read_blob_header(&mut reader, hash)?;
+
+        Ok(Self {
+            size,
+            inner: Box::new(reader.take(size)),
+        })
+    }
+
+    pub(crate) fn from_blob(blob: Blob) -> Self {
+        Self {
+            size: blob.content.len() as u64,
+            inner: Box::new(Cursor::new(blob.content)),
+        }
+    }
+}
+
+impl Read for BlobReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl std::fmt::Debug for BlobReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("BlobReader")
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+/// Size from the `blob <size>\0` header at the start of `reader`.
+fn read_blob_header<R: Read>(reader: &mut R, hash: &str) -> Result<u64, Error> {
+    let (object_type, size) = read_header(reader, hash)?;
+    if object_type != "blob" {
+        return Err(Error::NssStruct(StructError::DontMatchType(
+            object_type,
+            hash.to_string(),
+        )));
+    }
+
+    Ok(size)
+}
+
+/// Type and size from the `<type> <size>\0` header at the start of
+/// `reader`, reading nothing past it.
+pub(crate) fn read_header<R: Read>(reader: &mut R, hash: &str) -> Result<(String, u64), Error> {
+    let broken = |reason: &str| {
+        Error::NssStruct(StructError::InvalidObject(format!("{}: {}", hash, reason)))
+    };
+
+    let mut header = vec![];
+    let mut byte = [0u8; 1];
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            return Err(broken("no header terminator"));
+        }
+        if byte[0] == b'\0' {
+            break;
+        }
+        if header.len() == MAX_HEADER_SIZE {
+            return Err(broken("header is too long"));
+        }
+        header.push(byte[0]);
+    }
+
+    let header = String::from_utf8(header).map_err(|_| broken("header is not utf8"))?;
+    let (object_type, size) = header
+        .split_once(' ')
+        .ok_or_else(|| broken("broken header"))?;
+    let size = size.parse::<u64>().map_err(|_| broken("broken size"))?;
+
+    Ok((object_type.to_string(), size))
+}
+
+/// Compress the next `len` bytes of `reader` as a loose blob under
+/// `objects_dir` and return its hash.
+///
+/// Memory use does not depend on `len`. Like other object writes, an
+/// existing object is kept as is, freshened, and the write succeeds.
+pub(crate) fn write_blob<R: Read>(
+    objects_dir: &Path,
+    reader: R,
+    len: u64,
+    algorithm: HashAlgorithm,
+    level: Option<u32>,
+) -> Result<String, Error> {
+    create_dir(objects_dir)?;
+    let temp = temp_path(&objects_dir.join("blob"));
+
+    let result = write_temp_blob(&temp, reader, len, algorithm, level).and_then(|hash| {
+        let (d, f) = split_hash(&hash);
+        let dir = objects_dir.join(d);
+        create_dir(&dir)?;
+        match rename_new(&temp, &dir.join(f)) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => freshen(&dir.join(f))?,
+            result => result?,
+        }
+        sync_dir(&dir)?;
+
+        Ok(hash)
+    });
+    let _ = fs::remove_file(&temp);
+
+    result
+}
+
+fn write_temp_blob<R: Read>(
+    temp: &Path,
+    reader: R,
+    len: u64,
+    algorithm: HashAlgorithm,
+    level: Option<u32>,
+) -> Result<String, Error> {
+    let file = OpenOptions::new().write(true).create_new(true).open(temp)?;
+    let hasher = match level {
+        Some(level) => {
+            let mut writer = HashingWriter {
+                inner: write_encoder_with_level(file, level),
+                hasher: algorithm.hasher(),
+            };
+            copy_blob(&mut writer, reader, len)?;
+            writer.inner.finish()?.sync_all()?;
+            writer.hasher
+        }
+        None => {
+            let mut writer = HashingWriter {
+                inner: file,
+                hasher: algorithm.hasher(),
+            };
+            copy_blob(&mut writer, reader, len)?;
+            writer.inner.sync_all()?;
+            writer.hasher
+        }
+    };
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Write the header and exactly `len` bytes of `reader` to `writer`.
+fn copy_blob<R: Read, W: Write>(writer: &mut W, reader: R, len: u64) -> Result<(), Error> {
+    writer.write_all(&Blob::header(len))?;
+    let copied = io::copy(&mut reader.take(len), writer)?;
+    if copied != len {
+        return Err(Error::NssStruct(StructError::InvalidObject(format!(
+            "blob has {} bytes, expected {}",
+            copied, len
+        ))));
+    }
+
+    Ok(())
+}
+
+/// Writes through to `inner` and hashes what was written.
+pub(crate) struct HashingWriter<W: Write> {
+    pub(crate) inner: W,
+    pub(crate) hasher: StreamHasher,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::config::Config;
+    use crate::repo::repository::{NssRepository, PathRepository};
+    use crate::struct_set::{Hashable, Object};
+
+    use anyhow::Result;
+    use testdir::testdir;
+
+    #[test]
+    fn test_write_blob_stream() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let content = b"0123456789abcdef".repeat(100_000);
+        let blob = Object::Blob(Blob {
+            content: content.clone(),
+        });
+
+        let hash = repository
+            .objects()
+            .write_blob_stream(&content[..], content.len() as u64)?;
+        assert_eq!(hash, hex::encode(blob.to_hash()));
+
+        // Same object as a whole write, and no temp file left
+        assert_eq!(
+            repository.objects().read(&hash)?.as_bytes(),
+            blob.as_bytes()
+        );
+        let objects_dir = repository.common_dir().join("objects");
+        assert!(fs::read_dir(&objects_dir)?.all(|e| e.unwrap().path().is_dir()));
+
+        let mut reader = repository.objects().read_blob_stream(&hash)?;
+        assert_eq!(reader.size(), content.len() as u64);
+        let mut read = vec![];
+        reader.read_to_end(&mut read)?;
+        assert_eq!(read, content);
+
+        // Streaming it again is a no-op
+        let again = repository
+            .objects()
+            .write_blob_stream(&content[..], content.len() as u64)?;
+        assert_eq!(again, hash);
+
+        // Short reader stores nothing
+        let result = repository.objects().write_blob_stream(&b"short"[..], 10);
+        assert!(result.is_err());
+        assert!(fs::read_dir(&objects_dir)?.all(|e| e.unwrap().path().is_dir()));
+
+        // Only blobs can be streamed
+        let tree = Object::Tree(crate::struct_set::Tree::from_entries(vec![]));
+        repository.objects().write(tree.clone())?;
+        let result = repository
+            .objects()
+            .read_blob_stream(&hex::encode(tree.to_hash()));
+        assert!(matches!(
+            result,
+            Err(Error::NssStruct(StructError::DontMatchType(_, _)))
+        ));
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+//! Tags
+//!
+//! Names for fixed objects under `<common_dir>/tags`, next to `bookmarks`.
+//! Unlike bookmarks, a tag never moves once created.
+//!     - lightweight ... `tags/<name>` holds the hash of any object
+//!     - annotated   ... `tags/<name>` holds the hash of a tag object naming
+//!       the target, its type, the tagger and a message
+//!
+
+// Std
+use std::fs;
+use std::path::PathBuf;
+
+// Internal
+use super::config::Config;
+use super::database::ObjectDatabase;
+use super::error::Error;
+use super::fsck::bookmark_names;
+use super::migration::{require_format_version, TAG_OBJECT_FORMAT_VERSION};
+use super::repository::{lock_error, NssRepository, PathRepository, Repository, CONFIG_NAME};
+use crate::nss_io::file_system::{create_dir, read_content, ReadMode};
+use crate::nss_io::lock::LockFile;
+use crate::struct_set::error::Error as StructError;
+use crate::struct_set::{Hashable, Object, ObjectId, Tag};
+
+// Annotated tags of annotated tags are followed up to this depth
+const MAX_PEEL_DEPTH: usize = 16;
+
+/// **TagRepository Struct**
+///
+/// Tag files by name, shared by every worktree.
+#[derive(Debug, Clone)]
+pub struct TagRepository {
+    pub(crate) root: PathBuf,
+}
+
+impl TagRepository {
+    /// Hash the tag `name` holds, a tag object for annotated tags.
+    pub fn read(&self, name: &str) -> Result<ObjectId, Error> {
+        let path = self.root.join(name);
+        if !is_valid_tag_name(name) || !path.is_file() {
+            return Err(Error::NotFoundTag(name.to_string()));
+        }
+
+        let content = String::from_utf8(read_content(path, ReadMode::default())?)?;
+
+        Ok(ObjectId::from_hex(content.trim())?)
+    }
+
+    /// Create the tag `name` at `target`. Existing tags are never replaced.
+    pub fn create(&self, name: &str, target: ObjectId) -> Result<(), Error> {
+        if !is_valid_tag_name(name) {
+            return Err(Error::InvalidTagName(name.to_string()));
+        }
+
+        let path = self.root.join(name);
+        create_dir(path.parent().unwrap())?;
+        let mut lock = LockFile::acquire(&path).map_err(lock_error)?;
+        if path.exists() {
+            return Err(Error::AlreadyExistsTag(name.to_string()));
+        }
+        lock.write_all(target.to_hex().as_bytes())?;
+        lock.commit()?;
+
+        Ok(())
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), Error> {
+        let path = self.root.join(name);
+        if !is_valid_tag_name(name) || !path.is_file() {
+            return Err(Error::NotFoundTag(name.to_string()));
+        }
+        fs::remove_file(path)?;
+
+        Ok(())
+    }
+
+    /// Every tag name, sorted. Nested tags are named `<dir>/<name>`.
+    pub fn names(&self) -> Result<Vec<String>, Error> {
+        let mut names = vec![];
+        bookmark_names(&self.root, &self.root, &mut names)?;
+
+        Ok(names)
+    }
+}
+
+impl From<PathBuf> for TagRepository {
+    fn from(root: PathBuf) -> Self {
+        Self { root }
+    }
+}
+
+/// Relative path of `/`-separated parts, none empty, `.`, `..` or with
+/// whitespace, and no `.lock` suffix.
+fn is_valid_tag_name(name: &str) -> bool {
+    !name.ends_with(".lock")
+        && name.split('/').all(|part| {
+            !part.is_empty() && part != "." && part != ".." && !part.contains(char::is_whitespace)
+        })
+}
+
+impl NssRepository {
+    /// Create the lightweight tag `name` at the stored object `target`.
+    pub fn create_tag(&self, name: &str, target: ObjectId) -> Result<(), Error> {
+        if !self.objects().has_object(&target.to_hex())? {
+            return Err(Error::NssStruct(StructError::NotFoundObject));
+        }
+
+        self.tags().create(name, target)
+    }
+
+    /// Store a tag object for `target`, tagged by the configured user, and
+    /// create the tag `name` at it. Returns the hash of the tag object.
+    pub fn create_annotated_tag(
+        &self,
+        name: &str,
+        target: ObjectId,
+        message: &str,
+    ) -> Result<ObjectId, Error> {
+        if !is_valid_tag_name(name) {
+            return Err(Error::InvalidTagName(name.to_string()));
+        }
+        if self.tags().root.join(name).exists() {
+            return Err(Error::AlreadyExistsTag(name.to_string()));
+        }
+        require_format_version(self, TAG_OBJECT_FORMAT_VERSION, "annotated tags")?;
+
+        let object_type = self.objects().object_info(&target.to_hex())?.object_type;
+        let config = match self.common_dir().join(CONFIG_NAME).is_file() {
+            true => self.config().read()?,
+            false => Config::default(),
+        };
+        let tagger = format!(
+            "{} <{}>",
+            config.username(),
+            config.useremail().unwrap_or_default()
+        );
+
+        let tag = Object::Tag(
+            Tag::new(
+                target,
+                object_type,
+                name.to_string(),
+                tagger,
+                message.to_string(),
+            )?
+            .with_encoding(self.object_encoding()),
+        );
+        let hash = tag.object_id_with(self.hash_algorithm());
+        self.objects().write(tag)?;
+        self.tags().create(name, hash)?;
+
+        Ok(hash)
+    }
+
+    /// Object the tag `name` finally points at, following tag objects.
+    pub fn peel_tag(&self, name: &str) -> Result<ObjectId, Error> {
+        let mut hash = self.tags().read(name)?;
+        for _ in 0..MAX_PEEL_DEPTH {
+            match self.objects().read_object(&hash.to_hex())? {
+                Object::Tag(tag) => hash = tag.object,
+                _ => return Ok(hash),
+            }
+        }
+
+        Err(Error::NotFoundTag(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::config::User;
+    use crate::repo::migration::migrate_to_current;
+    use crate::struct_set::Blob;
+
+    use anyhow::Result;
+    use testdir::testdir;
+
+    #[test]
+    fn test_tag_repository() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let tags = TagRepository::from(temp_dir.join("tags"));
+        let hash = ObjectId::from_hex("a02b83cb54ba139e5c9d623a2fcf5424552946e0")?;
+        assert!(tags.names()?.is_empty());
+
+        tags.create("v1.0.0", hash)?;
+        tags.create("release/v2", hash)?;
+        assert_eq!(tags.read("v1.0.0")?, hash);
+        assert_eq!(tags.names()?, vec!["release/v2", "v1.0.0"]);
+
+        // Tags do not move
+        assert!(matches!(
+            tags.create("v1.0.0", hash),
+            Err(Error::AlreadyExistsTag(_))
+        ));
+        for name in ["", "../escape", "a//b", "with space", "v1.lock"] {
+            assert!(matches!(
+                tags.create(name, hash),
+                Err(Error::InvalidTagName(_))
+            ));
+        }
+
+        tags.delete("v1.0.0")?;
+        assert!(matches!(tags.read("v1.0.0"), Err(Error::NotFoundTag(_))));
+        assert!(matches!(tags.delete("v1.0.0"), Err(Error::NotFoundTag(_))));
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_annotated_tag() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let user = User::new(
+            "noshishi".to_string(),
+            Some("noshishi@nope.com".to_string()),
+        );
+        let repository = NssRepository::init(temp_dir.clone(), Config::new(user), false)?;
+        assert!(temp_dir.join(".nss").join("tags").is_dir());
+
+        let blob = Object::Blob(Blob {
+            content: b"release".to_vec(),
+        });
+        let target = blob.object_id();
+        assert!(matches!(
+            repository.create_tag("light", target),
+            Err(Error::NssStruct(StructError::NotFoundObject))
+        ));
+        repository.objects().write(blob)?;
+
+        repository.create_tag("light", target)?;
+        assert_eq!(repository.peel_tag("light")?, target);
+
+        let message = "Release 1.0.0\n\nSigned-off-by: noshishi <noshishi@nope.com>";
+        let hash = repository.create_annotated_tag("v1.0.0", target, message)?;
+        assert_eq!(repository.tags().read("v1.0.0")?, hash);
+        assert_eq!(repository.peel_tag("v1.0.0")?, target);
+        assert_eq!(repository.resolve_revision("v1.0.0")?, hash.to_hex());
+
+        match repository.objects().read(hash.to_hex())? {
+            Object::Tag(tag) => {
+                assert_eq!(tag.object, target);
+                assert_eq!(tag.object_type, "blob");
+                assert_eq!(tag.name, "v1.0.0");
+                assert_eq!(tag.tagger, "noshishi <noshishi@nope.com>");
+                assert_eq!(tag.message, format!("{}\n", message));
+            }
+            o => panic!("expected a tag, got {}", o.as_str()),
+        }
+        assert!(matches!(
+            repository.create_annotated_tag("v1.0.0", target, message),
+            Err(Error::AlreadyExistsTag(_))
+        ));
+
+        // Formats before tag objects refuse them until migrated
+        let mut config = repository.config().read()?;
+        config.set_repository_format_version(5);
+        repository.config().write(config)?;
+        assert!(matches!(
+            repository.create_annotated_tag("v2.0.0", target, message),
+            Err(Error::FormatVersionTooOld(5, _))
+        ));
+        assert!(!repository.tags().root.join("v2.0.0").exists());
+        migrate_to_current(&repository)?;
+        repository.create_annotated_tag("v2.0.0", target, message)?;
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+//! Transactional bookmark updates
+//!
+//! Every bookmark of a transaction is locked first, then each current
+//! value is compared with the expected one. Only when all of them match
+//! are the new values renamed into place, otherwise nothing is touched.
+//!
+
+// Std
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Internal
+use super::error::Error;
+use super::reflog::{zero_hash, Reflog};
+use super::repository::{lock_error, log_bookmark_update};
+use crate::nss_io::file_system::{create_dir, read_content, ReadMode};
+use crate::nss_io::lock::LockFile;
+use crate::struct_set::ObjectId;
+
+const DEFAULT_REASON: &str = "transaction";
+
+/// **BookMarkUpdate Struct**
+///
+/// `old` is the hash expected before the update, `None` when the bookmark
+/// must not exist yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookMarkUpdate {
+    pub name: PathBuf,
+    pub old: Option<ObjectId>,
+    pub new: ObjectId,
+}
+
+/// **BookMarkTransaction Struct**
+///
+/// Built from [`LocalBookMarkRepository::transaction`](super::repository::LocalBookMarkRepository::transaction).
+#[derive(Debug, Clone)]
+pub struct BookMarkTransaction {
+    root: PathBuf,
+    reflog: Option<Reflog>,
+    reason: String,
+    updates: Vec<BookMarkUpdate>,
+}
+
+impl BookMarkTransaction {
+    pub(crate) fn new(root: PathBuf, reflog: Option<Reflog>) -> Self {
+        Self {
+            root,
+            reflog,
+            reason: DEFAULT_REASON.to_string(),
+            updates: vec![],
+        }
+    }
+
+    /// Reason recorded in the reflog of every updated bookmark.
+    pub fn reason(mut self, reason: &str) -> Self {
+        self.reason = reason.to_string();
+
+        self
+    }
+
+    /// Move `name` from `old` to `new`.
+    pub fn update<P: Into<PathBuf>>(
+        mut self,
+        name: P,
+        old: Option<ObjectId>,
+        new: ObjectId,
+    ) -> Self {
+        self.updates.push(BookMarkUpdate {
+            name: name.into(),
+            old,
+            new,
+        });
+
+        self
+    }
+
+    pub fn updates(&self) -> &[BookMarkUpdate] {
+        &self.updates
+    }
+
+    /// Apply every update, or none of them.
+    ///
+    /// Fails with [`Error::DontMatchHashAtBookmarker`] when a bookmark does
+    /// not hold its expected hash, and with [`Error::RepositoryLocked`]
+    /// when another writer holds one of the bookmarks.
+    pub fn commit(mut self) -> Result<(), Error> {
+        // Same lock order for every writer
+        self.updates.sort_by(|a, b| a.name.cmp(&b.name));
+        for pair in self.updates.windows(2) {
+            if pair[0].name == pair[1].name {
+                return Err(Error::DuplicateBookmarkUpdate(
+                    pair[0].name.display().to_string(),
+                ));
+            }
+        }
+
+        let mut locks = vec![];
+        for update in &self.updates {
+            let path = self.root.join(&update.name);
+            create_dir(path.parent().unwrap())?;
+            locks.push(LockFile::acquire(&path).map_err(lock_error)?);
+        }
+
+        let mut previous = vec![];
+        for update in &self.updates {
+            let current = read_hash(&self.root.join(&update.name))?;
+            if current != update.old {
+                return Err(Error::DontMatchHashAtBookmarker(
+                    update.name.display().to_string(),
+                ));
+            }
+            previous.push(current);
+        }
+
+        // Renames only fail on a broken file system; put back what moved
+        for (committed, (lock, update)) in locks.iter().zip(&self.updates).enumerate() {
+            if let Err(e) = lock.replace(update.new.to_hex().as_bytes()) {
+                self.rollback(&previous[..committed]);
+                return Err(e.into());
+            }
+        }
+
+        // Logged under the locks, so entries chain
+
+        if let Some(reflog) = &self.reflog {
+            for (update, old) in self.updates.iter().zip(&previous) {
+                let name = update.name.display().to_string();
+                let old = old.map_or(zero_hash(update.new.algorithm()), |o| o.to_hex());
+                let new = update.new.to_hex();
+                log_bookmark_update(reflog, &name, &old, &new, &self.reason)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn rollback(&self, previous: &[Option<ObjectId>]) {
+        for (update, old) in self.updates.iter().zip(previous) {
+            let path = self.root.join(&update.name);
+            let _ = match old {
+                Some(hash) => fs::write(&path, hash.to_hex()),
+                None => fs::remove_file(&path),
+            };
+        }
+    }
+}
+
+/// Hash held by the bookmark at `path`, `None` when missing or unborn.
+fn read_hash(path: &Path) -> Result<Option<ObjectId>, Error> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let bytes = read_content(path, ReadMode::default())?;
+    let hash = String::from_utf8(bytes)?;
+    match hash.trim() {
+        "" => Ok(None),
+        hash => Ok(Some(ObjectId::from_hex(hash)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::config::Config;
+    use crate::repo::repository::{NssRepository, PathRepository};
+    use crate::struct_set::BookMark;
+
+    use anyhow::Result;
+    use testdir::testdir;
+
+    const HASH_A: &str = "a0a4e2f9bb9b4bb1b6fcbce0b4d1ac53ad5e9f70";
+    const HASH_B: &str = "b1b4e2f9bb9b4bb1b6fcbce0b4d1ac53ad5e9f70";
+    const HASH_C: &str = "c2c4e2f9bb9b4bb1b6fcbce0b4d1ac53ad5e9f70";
+
+    fn id(hex: &str) -> ObjectId {
+        ObjectId::from_hex(hex).unwrap()
+    }
+
+    #[test]
+    fn test_transaction_commit() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let bookmarks = repository.bookmark();
+        bookmarks.write(BookMark::new(PathBuf::from("main"), id(HASH_A)))?;
+        bookmarks.write(BookMark::new(PathBuf::from("stack/one"), id(HASH_A)))?;
+
+        bookmarks
+            .transaction()
+            .update("stack/one", Some(id(HASH_A)), id(HASH_B))
+            .update("stack/two", None, id(HASH_C))
+            .commit()?;
+
+        assert_eq!(bookmarks.read("main")?.hash, id(HASH_A));
+        assert_eq!(bookmarks.read("stack/one")?.hash, id(HASH_B));
+        assert_eq!(bookmarks.read("stack/two")?.hash, id(HASH_C));
+        assert!(!temp_dir.join(".nss/bookmarks/stack/one.lock").exists());
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_mismatch() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let repository = NssRepository::init(temp_dir.clone(), Config::default(), false)?;
+        let bookmarks = repository.bookmark();
+        bookmarks.write(BookMark::new(PathBuf::from("one"), id(HASH_A)))?;
+        bookmarks.write(BookMark::new(PathBuf::from("two"), id(HASH_C)))?;
+
+        // Second update is stale, so the first is not applied either
+        let result = bookmarks
+            .transaction()
+            .update("one", Some(id(HASH_A)), id(HASH_B))
+            .update("two", Some(id(HASH_A)), id(HASH_B))
+            .commit();
+        assert!(matches!(result, Err(Error::DontMatchHashAtBookmarker(n)) if n == "two"));
+        assert_eq!(bookmarks.read("one")?.hash, id(HASH_A));
+        assert_eq!(bookmarks.read("two")?.hash, id(HASH_C));
+
+        // Expected to be absent
+        let result = bookmarks
+            .transaction()
+            .update("one", None, id(HASH_B))
+            .commit();
+        assert!(matches!(result, Err(Error::DontMatchHashAtBookmarker(_))));
+
+        // Same bookmark twice
+        let result = bookmarks
+            .transaction()
+            .update("one", Some(id(HASH_A)), id(HASH_B))
+            .update("one", Some(id(HASH_B)), id(HASH_C))
+            .commit();
+        assert!(matches!(result, Err(Error::DuplicateBookmarkUpdate(_))));
+
+        // Held by another writer
+        fs::write(temp_dir.join(".nss/bookmarks/two.lock"), b"")?;
+        let result = bookmarks
+            .transaction()
+            .update("one", Some(id(HASH_A)), id(HASH_B))
+            .update("two", Some(id(HASH_C)), id(HASH_B))
+            .commit();
+        assert!(matches!(result, Err(Error::RepositoryLocked(_))));
+        assert_eq!(bookmarks.read("one")?.hash, id(HASH_A));
+        assert!(!temp_dir.join(".nss/bookmarks/one.lock").exists());
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,643 @@
+//! Linked worktrees
+//!
+//! Extra working directories sharing one repository.
+//!     Layout:
+//!         - <work dir>/.nss                  ... file `nssdir: <admin dir>`
+//!         - .nss/worktrees/<name>/HEAD       ... per worktree
+//!         - .nss/worktrees/<name>/INDEX      ... per worktree
+//!         - .nss/worktrees/<name>/commondir  ... path back to the shared `.nss`
+//!         - .nss/worktrees/<name>/nssdir     ... path of the worktree's `.nss` file
+//!     config, objects and bookmarks are shared.
+//!
+
+// Std
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Internal
+use super::database::ObjectDatabase;
+use super::discovery::NSS_DIR_PREFIX;
+use super::error::Error;
+use super::large::{LargeObjectStore, LargePointer};
+use super::repository::{
+    lock_error, NssRepository, PathRepository, Repository, COMMON_DIR_NAME, REPO_NAME,
+};
+use crate::nss_io::file_system::{create_dir, remove_dir_all, temp_path, write_content, WriteMode};
+use crate::nss_io::lock::LockFile;
+use crate::struct_set::error::Error as StructError;
+use crate::struct_set::{Head, Index, Object, ObjectId, Tree};
+
+const WORKTREES_NAME: &str = "worktrees";
+const NSS_FILE_NAME: &str = "nssdir";
+
+/// **Worktree Struct**
+///
+/// A linked worktree registered under `.nss/worktrees`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Worktree {
+    pub name: String,
+    pub path: PathBuf,
+    pub nss_dir: PathBuf,
+}
+
+impl Worktree {
+    /// Repository addresser rooted at this worktree.
+    pub fn open(&self) -> NssRepository {
+        NssRepository::with_nss_dir(self.path.clone(), self.nss_dir.clone())
+    }
+
+    pub fn head(&self) -> Result<Head, Error> {
+        self.open().head().read()
+    }
+}
+
+impl NssRepository {
+    /// Linked worktrees of this repository (the main one is not included).
+    pub fn worktrees(&self) -> Result<Vec<Worktree>, Error> {
+        let worktrees_dir = self.common_dir().join(WORKTREES_NAME);
+        if !worktrees_dir.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut worktrees = vec![];
+        for dir_entry in worktrees_dir.read_dir()? {
+            let nss_dir = dir_entry?.path();
+            let name = nss_dir.file_name().unwrap().to_string_lossy().to_string();
+            // Admin directory of an add still running, or interrupted
+            if !is_valid_worktree_name(&name) {
+                continue;
+            }
+
+            let nss_file = fs::read_to_string(nss_dir.join(NSS_FILE_NAME))?;
+            let path = PathBuf::from(nss_file.trim())
+                .parent()
+                .unwrap()
+                .to_path_buf();
+
+            worktrees.push(Worktree {
+                name,
+                path,
+                nss_dir,
+            })
+        }
+        worktrees.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(worktrees)
+    }
+
+    /// Create a linked worktree `name` at `path` with `bookmark` checked out.
+    ///
+    /// The bookmark's tree is written into `path` and indexed. A bookmark
+    /// without a commit yet gives an empty worktree on that bookmark.
+    /// `path` must be missing or empty. The admin directory is built under
+    /// a temp name and renamed into place last, so a failed add leaves
+    /// nothing behind.
+    pub fn add_worktree<P: AsRef<Path>>(
+        &self,
+        name: &str,
+        path: P,
+        bookmark: &str,
+    ) -> Result<NssRepository, Error> {
+        if !is_valid_worktree_name(name) {
+            return Err(Error::InvalidWorktreeName(name.to_string()));
+        }
+        let path = std::env::current_dir()?.join(path);
+        let worktrees_dir = self.common_dir().join(WORKTREES_NAME);
+        create_dir(&worktrees_dir)?;
+        // Held for the whole add, so two adds cannot both pass the checks
+        let _lock = LockFile::acquire(&worktrees_dir).map_err(lock_error)?;
+
+        let nss_dir = worktrees_dir.join(name);
+        if nss_dir.exists() {
+            return Err(Error::AlreadyExistsWorktree(name.to_string()));
+        }
+        if path.join(REPO_NAME).exists() {
+            return Err(Error::AlreadyExistsRepository(path.join(REPO_NAME)));
+        }
+        let existed = path.exists();
+        if existed && path.read_dir()?.next().is_some() {
+            return Err(Error::NotEmptyWorktreePath(path));
+        }
+        self.ensure_not_checked_out(bookmark, None)?;
+
+        let temp = temp_path(&nss_dir);
+        let result = self
+            .write_worktree(&temp, &nss_dir, &path, bookmark)
+            .and_then(|()| Ok(fs::rename(&temp, &nss_dir)?));
+        if let Err(e) = result {
+            let _ = fs::remove_dir_all(&temp);
+            match existed {
+                true => _ = clear_dir(&path),
+                false => _ = fs::remove_dir_all(&path),
+            }
+            return Err(e);
+        }
+
+        Ok(NssRepository::with_nss_dir(path, nss_dir))
+    }
+
+    /// Fill the admin directory `temp`, to be renamed to `nss_dir`, and the
+    /// working directory `path`.
+    fn write_worktree(
+        &self,
+        temp: &Path,
+        nss_dir: &Path,
+        path: &Path,
+        bookmark: &str,
+    ) -> Result<(), Error> {
+        // Admin directory
+        create_dir(temp)?;
+        let common_dir = PathBuf::from("..").join("..");
+        let nss_file = path.join(REPO_NAME);
+        write_content(
+            temp.join(COMMON_DIR_NAME),
+            format!("{}\n", common_dir.display()).as_bytes(),
+            WriteMode::AtomicCreateNew,
+        )?;
+        write_content(
+            temp.join(NSS_FILE_NAME),
+            format!("{}\n", nss_file.display()).as_bytes(),
+            WriteMode::AtomicCreateNew,
+        )?;
+
+        // Working directory
+        create_dir(path)?;
+        write_content(
+            &nss_file,
+            format!("{} {}\n", NSS_DIR_PREFIX, nss_dir.display()).as_bytes(),
+            WriteMode::AtomicCreateNew,
+        )?;
+
+        let worktree = NssRepository::with_nss_dir(path.to_path_buf(), temp.to_path_buf());
+        worktree
+            .head()
+            .write(Head::Bookmarker(PathBuf::from(bookmark)))?;
+
+        let index = match self.bookmark().read(bookmark) {
+            Ok(b) => {
+                let commit = self.objects().read_commit(b.hash)?;
+                match self.objects().read(commit.tree_hash)? {
+                    Object::Tree(tree) => write_tree_files(self, self.large_objects(), tree, path)?,
+                    _ => {
+                        return Err(Error::NssStruct(StructError::DontMatchType(
+                            "Tree".to_string(),
+                            commit.tree_hash.to_hex(),
+                        )))
+                    }
+                }
+                Index::new_all(&worktree)?
+            }
+            Err(_) => Index::empty(),
+        };
+        worktree.index().write(index)
+    }
+
+    /// Remove linked worktree `name`, its admin directory and its working
+    /// directory.
+    ///
+    /// Refused with [`Error::DirtyWorktree`] when its files differ from its
+    /// INDEX or its INDEX from the HEAD commit, unless `force` is set.
+    pub fn remove_worktree(&self, name: &str, force: bool) -> Result<(), Error> {
+        let worktree = self
+            .worktrees()?
+            .into_iter()
+            .find(|w| w.name == name)
+            .ok_or(Error::NotFoundWorktree(name.to_string()))?;
+
+        if worktree.path.exists() {
+            if !force && !is_clean(&worktree.open())? {
+                return Err(Error::DirtyWorktree(name.to_string()));
+            }
+            remove_dir_all(&worktree.path)?;
+        }
+        remove_dir_all(&worktree.nss_dir)?;
+
+        Ok(())
+    }
+
+    /// Point this worktree's HEAD at `bookmark`.
+    ///
+    /// Refused when another worktree already has the bookmark checked out.
+    pub fn checkout_bookmark(&self, bookmark: &str) -> Result<(), Error> {
+        self.ensure_not_checked_out(bookmark, Some(&self.nss_dir()))?;
+        self.head()
+            .write(Head::Bookmarker(PathBuf::from(bookmark)))?;
+
+        Ok(())
+    }
+
+    fn ensure_not_checked_out(&self, bookmark: &str, skip: Option<&Path>) -> Result<(), Error> {
+        let mut heads: Vec<(PathBuf, PathBuf)> = vec![];
+
+        // A bare main repository has nothing checked out
+        if !self.is_bare_config() {
+            let main_root = match self.is_linked_worktree() {
+                true => self.common_dir().parent().unwrap().to_path_buf(),
+                false => self.root.clone(),
+            };
+            heads.push((main_root, self.common_dir()));
+        }
+        for worktree in self.worktrees()? {
+            heads.push((worktree.path, worktree.nss_dir));
+        }
+
+        for (path, nss_dir) in heads {
+            if Some(nss_dir.as_path()) == skip {
+                continue;
+            }
+            let head = NssRepository::with_nss_dir(path.clone(), nss_dir)
+                .head()
+                .read();
+            if let Ok(Head::Bookmarker(b)) = head {
+                if b == Path::new(bookmark) {
+                    return Err(Error::BookmarkCheckedOut(bookmark.to_string(), path));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_bare_config(&self) -> bool {
+        self.config().read().map(|c| c.is_bare()).unwrap_or(false)
+    }
+}
+
+/// A worktree name is one path component not starting with `.`, hidden
+/// names are kept for admin directories under construction.
+fn is_valid_worktree_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\', '\0'])
+}
+
+/// Remove everything inside `dir`, keeping `dir` itself.
+fn clear_dir(dir: &Path) -> Result<(), Error> {
+    for dir_entry in dir.read_dir()? {
+        let path = dir_entry?.path();
+        match path.is_dir() && !path.is_symlink() {
+            true => fs::remove_dir_all(path)?,
+            false => fs::remove_file(path)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the files of `worktree` match its INDEX, and its INDEX the tree
+/// of the HEAD commit (none on an unborn bookmark).
+fn is_clean(worktree: &NssRepository) -> Result<bool, Error> {
+    let files = entries(&Index::new_all(worktree)?);
+    let index = entries(&worktree.index().read()?);
+
+    let head_hash = ObjectId::from_hex(&worktree.reflog().head_hash()?)?;
+    let head = match head_hash.is_zero() {
+        true => BTreeSet::new(),
+        false => {
+            let commit = worktree.objects().read_commit(head_hash)?;
+            match worktree.objects().read(commit.tree_hash)? {
+                Object::Tree(tree) => entries(&Index::try_from_tree(worktree, tree)?),
+                _ => {
+                    return Err(Error::NssStruct(StructError::DontMatchType(
+                        "Tree".to_string(),
+                        commit.tree_hash.to_hex(),
+                    )))
+                }
+            }
+        }
+    };
+
+    Ok(files == index && index == head)
+}
+
+fn entries(index: &Index) -> BTreeSet<(OsString, ObjectId)> {
+    index
+        .filemetas
+        .iter()
+        .map(|f| (f.filename.clone(), f.hash))
+        .collect()
+}
+
+/// Write `tree` under `base_path`, large files from `large`.
+fn write_tree_files<D: ObjectDatabase + ?Sized>(
+    database: &D,
+    large: &LargeObjectStore,
+    tree: Tree,
+    base_path: &Path,
+) -> Result<(), Error> {
+    for entry in tree.entries {
+        entry.check_name()?;
+        let path = base_path.join(&entry.name);
+        let hash = entry.hash.to_hex();
+
+        match database.read_object(&hash)? {
+            Object::Blob(blob) => match LargePointer::from_blob(&blob) {
+                Some(pointer) => large.checkout(&pointer, &path)?,
+                None => write_content(&path, &blob.content, WriteMode::default())?,
+            },
+            Object::Tree(sub_tree) => {
+                create_dir(&path)?;
+                write_tree_files(database, large, sub_tree, &path)?;
+            }
+            _ => {
+                return Err(Error::NssStruct(StructError::DontMatchType(
+                    "Blob".to_string(),
+                    hash,
+                )))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::config::Config;
+    use crate::repo::discovery::Discovery;
+    use crate::struct_set::{Blob, BookMark, Commit, Entry, Hashable};
+
+    use anyhow::Result;
+    use std::env;
+    use testdir::testdir;
+
+    fn commit_first_file(repository: &NssRepository) -> Result<String> {
+        let test_file = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("tests")
+            .join("test_repo")
+            .join("first.rs");
+        fs::copy(&test_file, repository.path().join("first.rs"))?;
+
+        let blob = Object::Blob(Blob::new(repository.path().join("first.rs"))?);
+        let entry = Entry::new(repository.path().join("first.rs"), blob.clone())?;
+        let tree = Object::Tree(Tree::from_entries(vec![entry]));
+        let commit = Commit::new(tree.object_id(), vec![], "noshishi", "noshishi", "initial")?;
+        let commit = Object::Commit(commit);
+        let commit_hash = hex::encode(commit.to_hash());
+
+        repository.objects().write(blob)?;
+        repository.objects().write(tree)?;
+        repository.objects().write(commit)?;
+
+        Ok(commit_hash)
+    }
+
+    fn write_bookmark(repository: &NssRepository, name: &str, hash: &str) -> Result<()> {
+        fs::File::create(repository.common_dir().join("bookmarks").join(name))?;
+        repository
+            .bookmark()
+            .write(BookMark::new(PathBuf::from(name), hash.parse()?))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_worktree() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let main_dir = temp_dir.join("main");
+        let repository = NssRepository::init(main_dir.clone(), Config::default(), false)?;
+        let commit_hash = commit_first_file(&repository)?;
+        write_bookmark(&repository, "main", &commit_hash)?;
+        write_bookmark(&repository, "feature", &commit_hash)?;
+
+        // main is checked out in the main worktree
+        let result = repository.add_worktree("main", temp_dir.join("linked"), "main");
+        assert!(matches!(result, Err(Error::BookmarkCheckedOut(_, _))));
+
+        let worktree = repository.add_worktree("feature", temp_dir.join("linked"), "feature")?;
+        assert!(worktree.is_linked_worktree());
+        assert_eq!(worktree.common_dir(), main_dir.join(".nss"));
+        assert!(temp_dir.join("linked").join("first.rs").is_file());
+        assert_eq!(worktree.index().read()?.filemetas.len(), 1);
+
+        // Shared store, own HEAD
+        let found = Discovery::default().discover(temp_dir.join("linked"))?;
+        assert_eq!(found.path(), temp_dir.join("linked"));
+        assert!(found.objects().read_commit(&commit_hash).is_ok());
+        match found.head().read()? {
+            Head::Bookmarker(b) => assert_eq!(b, PathBuf::from("feature")),
+            head => panic!("unexpected head: {:?}", head),
+        }
+
+        let worktrees = repository.worktrees()?;
+        assert_eq!(worktrees.len(), 1);
+        assert_eq!(worktrees[0].name, "feature");
+        assert_eq!(worktrees[0].path, temp_dir.join("linked"));
+
+        let result = repository.add_worktree("feature", temp_dir.join("other"), "other");
+        assert!(matches!(result, Err(Error::AlreadyExistsWorktree(_))));
+
+        // Names that are no single component, and user files, are refused
+        for name in ["", ".hidden", "a/b", ".."] {
+            let result = repository.add_worktree(name, temp_dir.join("other"), "other");
+            assert!(matches!(result, Err(Error::InvalidWorktreeName(_))));
+        }
+        fs::create_dir(temp_dir.join("used"))?;
+        fs::write(temp_dir.join("used").join("notes.txt"), "mine")?;
+        let result = repository.add_worktree("used", temp_dir.join("used"), "other");
+        assert!(matches!(result, Err(Error::NotEmptyWorktreePath(_))));
+        assert_eq!(fs::read(temp_dir.join("used").join("notes.txt"))?, b"mine");
+
+        // A failed add leaves no admin or working directory behind
+        write_bookmark(&repository, "broken", &"a".repeat(40))?;
+        fs::create_dir(temp_dir.join("empty"))?;
+        assert!(repository
+            .add_worktree("broken", temp_dir.join("empty"), "broken")
+            .is_err());
+        assert!(repository
+            .add_worktree("broken", temp_dir.join("missing"), "broken")
+            .is_err());
+        assert_eq!(fs::read_dir(temp_dir.join("empty"))?.count(), 0);
+        assert!(!temp_dir.join("missing").exists());
+        assert_eq!(repository.worktrees()?.len(), 1);
+        assert_eq!(
+            fs::read_dir(main_dir.join(".nss").join("worktrees"))?.count(),
+            1
+        );
+        assert!(!main_dir.join(".nss").join("worktrees.lock").exists());
+
+        // Entry names escaping the worktree are not checked out
+        let blob = Object::Blob(Blob {
+            content: b"escaped".to_vec(),
+        });
+        let tree = Object::Tree(Tree::from_entries(vec![Entry {
+            mode: 0o100644,
+            name: OsString::from("../escaped"),
+            hash: blob.object_id(),
+        }]));
+        let commit = Object::Commit(Commit::new(
+            tree.object_id(),
+            vec![],
+            "noshishi",
+            "noshishi",
+            "escape",
+        )?);
+        for object in [blob, tree, commit.clone()] {
+            repository.objects().write(object)?;
+        }
+        write_bookmark(&repository, "escape", &commit.object_id().to_hex())?;
+        assert!(repository
+            .add_worktree("escape", temp_dir.join("escape"), "escape")
+            .is_err());
+        assert!(!temp_dir.join("escaped").exists());
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_worktree_large_file() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let main_dir = temp_dir.join("main");
+        let mut config = Config::default();
+        config.add_large_pattern("*.bin");
+        let repository = NssRepository::init(main_dir.clone(), config, false)?;
+
+        let content = vec![42u8; 50_000];
+        fs::write(main_dir.join("model.bin"), &content)?;
+        let hash = repository.write_file(main_dir.join("model.bin"))?;
+
+        // Only the pointer is in the object database
+        let blob = match repository.objects().read(&hash)? {
+            Object::Blob(blob) => blob,
+            object => panic!("unexpected object: {:?}", object),
+        };
+        let pointer = LargePointer::from_blob(&blob).unwrap();
+        assert_eq!(pointer.size, content.len() as u64);
+        assert!(repository.large_objects().path(&pointer).is_file());
+
+        let tree = Object::Tree(Tree::from_entries(vec![Entry {
+            mode: 0o100644,
+            name: "model.bin".into(),
+            hash: hash.parse()?,
+        }]));
+        let commit = Object::Commit(Commit::new(
+            tree.object_id(),
+            vec![],
+            "noshishi",
+            "noshishi",
+            "large",
+        )?);
+        repository.objects().write(tree.clone())?;
+        repository.objects().write(commit.clone())?;
+        write_bookmark(&repository, "feature", &hex::encode(commit.to_hash()))?;
+
+        // Checkout resolves the pointer, the index keeps the pointer hash
+        let worktree = repository.add_worktree("feature", temp_dir.join("linked"), "feature")?;
+        assert_eq!(
+            fs::read(temp_dir.join("linked").join("model.bin"))?,
+            content
+        );
+        let index = worktree.index().read()?;
+        assert_eq!(index.filemetas[0].hash.to_hex(), hash);
+
+        let tree = match tree {
+            Object::Tree(tree) => tree,
+            _ => unreachable!(),
+        };
+        let index = Index::try_from_tree(&repository, tree)?;
+        assert_eq!(index.filemetas[0].filesize, content.len() as u32);
+        assert_eq!(index.filemetas[0].hash.to_hex(), hash);
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_worktree() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let main_dir = temp_dir.join("main");
+        let repository = NssRepository::init(main_dir.clone(), Config::default(), false)?;
+        let commit_hash = commit_first_file(&repository)?;
+        write_bookmark(&repository, "feature", &commit_hash)?;
+        write_bookmark(&repository, "topic", &commit_hash)?;
+
+        // Clean worktrees go without force
+        repository.add_worktree("topic", temp_dir.join("topic"), "topic")?;
+        repository.remove_worktree("topic", false)?;
+        assert!(!temp_dir.join("topic").exists());
+
+        // Changed and untracked files are kept unless forced
+        let linked = temp_dir.join("linked");
+        repository.add_worktree("feature", &linked, "feature")?;
+        fs::write(linked.join("first.rs"), b"changed")?;
+        assert!(matches!(
+            repository.remove_worktree("feature", false),
+            Err(Error::DirtyWorktree(_))
+        ));
+        let worktree = repository.worktrees()?.remove(0).open();
+        worktree.index().write(Index::new_all(&worktree)?)?;
+        assert!(matches!(
+            repository.remove_worktree("feature", false),
+            Err(Error::DirtyWorktree(_))
+        ));
+        fs::copy(main_dir.join("first.rs"), linked.join("first.rs"))?;
+        worktree.index().write(Index::new_all(&worktree)?)?;
+        fs::write(linked.join("untracked.rs"), b"new")?;
+        assert!(matches!(
+            repository.remove_worktree("feature", false),
+            Err(Error::DirtyWorktree(_))
+        ));
+        assert!(linked.join("untracked.rs").is_file());
+
+        repository.remove_worktree("feature", true)?;
+        assert!(!linked.exists());
+        assert!(repository.worktrees()?.is_empty());
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_bookmark() -> Result<()> {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let main_dir = temp_dir.join("main");
+        let repository = NssRepository::init(main_dir.clone(), Config::default(), false)?;
+        let worktree = repository.add_worktree("feature", temp_dir.join("linked"), "feature")?;
+
+        // Unborn bookmark gives an empty worktree
+        assert_eq!(worktree.index().read()?, Index::empty());
+
+        let result = repository.checkout_bookmark("feature");
+        assert!(matches!(result, Err(Error::BookmarkCheckedOut(_, _))));
+
+        let result = worktree.checkout_bookmark("main");
+        assert!(matches!(result, Err(Error::BookmarkCheckedOut(_, _))));
+
+        // Free again once the worktree moves on
+        worktree.checkout_bookmark("topic")?;
+        repository.checkout_bookmark("feature")?;
+
+        repository.remove_worktree("feature", false)?;
+        assert!(!temp_dir.join("linked").exists());
+        assert!(repository.worktrees()?.is_empty());
+        assert!(matches!(
+            repository.remove_worktree("feature", false),
+            Err(Error::NotFoundWorktree(_))
+        ));
+
+        // Clean up: Remove the temporary directory
+        fs::remove_dir_all(temp_dir)?;
+
+        Ok(())
+    }
+}
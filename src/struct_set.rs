@@ -2,10 +2,14 @@ pub mod blob;
 pub mod bookmark;
 pub mod commit;
 pub mod diff;
+pub mod encoding;
+pub mod hash;
 pub mod head;
 pub mod index;
 pub mod metadata;
 pub mod object;
+pub mod object_id;
+pub mod tag;
 pub mod tree;
 
 pub mod error;
@@ -14,8 +18,12 @@ pub use blob::Blob;
 pub use bookmark::BookMark;
 pub use commit::Commit;
 pub use diff::{DIffTag, Diff};
+pub use encoding::ObjectEncoding;
+pub use hash::{HashAlgorithm, StreamHasher};
 pub use head::Head;
 pub use index::{Index, IndexVesion1};
 pub use metadata::FileMeta;
 pub use object::{Hashable, Object};
+pub use object_id::ObjectId;
+pub use tag::Tag;
 pub use tree::{Entry, Tree};
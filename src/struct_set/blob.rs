@@ -1,4 +1,5 @@
 // Std
+use std::io::{self, Read};
 use std::path::Path;
 
 // External
@@ -7,7 +8,7 @@ use serde::{Deserialize, Serialize};
 // Internal
 use super::error::Error;
 use crate::nss_io::file_system::{read_content, ReadMode};
-use crate::struct_set::Hashable;
+use crate::struct_set::{HashAlgorithm, Hashable};
 
 /// **Blob Struct**
 ///
@@ -24,6 +25,32 @@ impl Blob {
         Ok(Self { content })
     }
 
+    /// Object header of a blob of `len` bytes, `blob <len>\0`.
+    pub fn header(len: u64) -> Vec<u8> {
+        format!("blob {}\0", len).into_bytes()
+    }
+
+    /// Hash the next `len` bytes of `reader` as a blob without holding
+    /// them in memory.
+    pub fn hash_reader<R: Read>(
+        reader: R,
+        len: u64,
+        algorithm: HashAlgorithm,
+    ) -> Result<Vec<u8>, Error> {
+        let mut hasher = algorithm.hasher();
+        hasher.update(&Self::header(len));
+
+        let copied = io::copy(&mut reader.take(len), &mut hasher)?;
+        if copied != len {
+            return Err(Error::InvalidObject(format!(
+                "blob has {} bytes, expected {}",
+                copied, len
+            )));
+        }
+
+        Ok(hasher.finalize())
+    }
+
     pub fn from_rawobject(contnet: &[u8]) -> Result<Self, Error> {
         Ok(Self {
             content: contnet.to_vec(),
@@ -40,8 +67,8 @@ impl std::fmt::Display for Blob {
 impl Hashable for Blob {
     fn as_bytes(&self) -> Vec<u8> {
         // "blob filesize\0contnet" to bytes
-        let header = format!("blob {}\0", self.content.len());
-        let store = [header.as_bytes(), &self.content].concat();
+        let header = Self::header(self.content.len() as u64);
+        let store = [header.as_slice(), &self.content].concat();
 
         store
     }
@@ -173,6 +200,26 @@ fn commit(message: &str) -> std::io::Result<()> {
         );
     }
 
+    #[test]
+    fn test_blob_hash_reader() -> Result<()> {
+        let content = b"streamed content".repeat(10_000);
+        let blob = Blob {
+            content: content.clone(),
+        };
+
+        for algorithm in [HashAlgorithm::Sha1, HashAlgorithm::Sha256] {
+            let hash = Blob::hash_reader(&content[..], content.len() as u64, algorithm)?;
+            assert_eq!(hash, blob.to_hash_with(algorithm));
+        }
+
+        // Only `len` bytes are hashed, a short reader is an error
+        let hash = Blob::hash_reader(&b"hellow and more"[..], 6, HashAlgorithm::Sha1)?;
+        assert_eq!(hash, Blob::from_rawobject(b"hellow")?.to_hash());
+        assert!(Blob::hash_reader(&b"hel"[..], 6, HashAlgorithm::Sha1).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_blob_display() {
         // Create a Blob instance
@@ -1,14 +1,17 @@
 // Std
 use std::path::PathBuf;
 
+// Internal
+use super::ObjectId;
+
 #[derive(Debug)]
 pub struct BookMark {
     pub name: PathBuf,
-    pub hash: String,
+    pub hash: ObjectId,
 }
 
 impl BookMark {
-    pub fn new(name: PathBuf, hash: String) -> Self {
+    pub fn new(name: PathBuf, hash: ObjectId) -> Self {
         BookMark { name, hash }
     }
 }
@@ -4,72 +4,202 @@ use std::fmt::Write;
 // External
 use anyhow::Result;
 use chrono::prelude::{DateTime, Utc};
-use chrono::TimeZone;
+use chrono::{FixedOffset, TimeZone};
 
 // Internal
+use super::encoding::{format_signature, parse_signature};
 use super::error::Error;
 use super::object::Hashable;
+use super::{HashAlgorithm, ObjectEncoding, ObjectId};
 
 /// **Commit Struct**
 ///
 /// This struct represents ...
+///
+/// `date` and `timezone` are the committer's. The nss encoding stores only
+/// that date, so `author_date` reads back the same. In the git encoding
+/// both are kept, and headers nss does not know (`gpgsig`, `encoding`,
+/// `mergetag`, ...) are kept in `extra_headers` and written back after
+/// the committer line, where git puts them.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Commit {
-    pub tree_hash: String,
-    pub parents: Vec<String>,
+    pub tree_hash: ObjectId,
+    pub parents: Vec<ObjectId>,
     pub author: String,
     pub committer: String,
     pub date: DateTime<Utc>,
+    /// As stored, so the commit hashes back the same. New messages end
+    /// with `\n`.
     pub message: String,
+    pub encoding: ObjectEncoding,
+    pub timezone: FixedOffset,
+    pub author_date: DateTime<Utc>,
+    pub author_timezone: FixedOffset,
+    /// `(key, value)`, multi-line values joined with `\n`
+    pub extra_headers: Vec<(String, String)>,
 }
 
 impl Commit {
     pub fn new<S: Into<String>>(
-        tree_hash: S,
-        parents: Vec<String>,
+        tree_hash: ObjectId,
+        parents: Vec<ObjectId>,
         author: S,
         committer: S,
         message: S,
     ) -> Result<Self> {
+        let date = Utc::now();
+        let timezone = FixedOffset::east_opt(0).unwrap();
+
         Ok(Self {
-            tree_hash: tree_hash.into(),
+            tree_hash,
             parents,
             author: author.into(),
             committer: committer.into(),
-            date: Utc::now(),
-            message: message.into(),
+            date,
+            message: with_newline(message.into()),
+            encoding: ObjectEncoding::default(),
+            timezone,
+            author_date: date,
+            author_timezone: timezone,
+            extra_headers: vec![],
         })
     }
 
+    /// Lay the commit out in `encoding`, see [`ObjectEncoding`].
+    pub fn with_encoding(mut self, encoding: ObjectEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Timezone of the author and committer in the git encoding.
+    pub fn with_timezone(mut self, timezone: FixedOffset) -> Self {
+        self.timezone = timezone;
+        self.author_timezone = timezone;
+        self
+    }
+
+    /// Author date differing from the commit date, e.g. of an amended or
+    /// rebased commit. Only the git encoding stores it.
+    pub fn with_author_date(mut self, date: DateTime<Utc>, timezone: FixedOffset) -> Self {
+        self.author_date = date;
+        self.author_timezone = timezone;
+        self
+    }
+
+    /// Create Commit with RawObject of a SHA-1 repository.
     pub fn from_rawobject(content: &[u8]) -> Result<Self, Error> {
-        let all_line = content
-            .split(|&x| x == b'\n')
-            .filter(|x| x != b"")
-            .map(|x| String::from_utf8(x.to_vec()).unwrap())
-            .collect::<Vec<String>>();
-
-        let mut tree_hash = String::new();
-        let mut parents: Vec<String> = Vec::new();
+        Self::from_rawobject_with(content, HashAlgorithm::Sha1)
+    }
+
+    /// Create Commit with RawObject whose tree and parents are `algorithm`
+    /// hashes.
+    pub fn from_rawobject_with(content: &[u8], algorithm: HashAlgorithm) -> Result<Self, Error> {
+        Self::from_rawobject_with_encoding(content, algorithm, ObjectEncoding::Nss)
+    }
+
+    /// Create Commit with RawObject laid out in `encoding`.
+    pub fn from_rawobject_with_encoding(
+        content: &[u8],
+        algorithm: HashAlgorithm,
+        encoding: ObjectEncoding,
+    ) -> Result<Self, Error> {
+        let content = String::from_utf8(content.to_vec())
+            .map_err(|_| Error::InvalidObject("commit is not utf8".to_string()))?;
+
+        // "<header lines>\n\n<message>", the message usually ending in `\n`
+        let (headers, message) = content
+            .split_once("\n\n")
+            .ok_or(Error::InvalidObject("commit without message".to_string()))?;
+        let message = message.to_string();
+
+        let mut tree_hash = None;
+        let mut parents: Vec<ObjectId> = Vec::new();
         let mut author = String::new();
         let mut committer = String::new();
         let mut date = String::new();
-        let mut message = String::new();
-
-        all_line.iter().for_each(|l| {
-            let mut split = l.split_whitespace();
-
-            match split.next().unwrap() {
-                "tree" => tree_hash = split.next().unwrap().to_string(),
-                "parent" => parents.push(split.next().unwrap().to_string()),
-                "author" => author = split.next().unwrap().to_string(),
-                "committer" => committer = split.next().unwrap().to_string(),
-                "date" => date = split.next().unwrap().to_string(),
-                s => message = s.to_string(),
+        let mut author_signature = None;
+        let mut committer_signature = None;
+        let mut extra_headers: Vec<(String, String)> = vec![];
+
+        for line in headers.lines() {
+            // Git continues a multi-line header with a leading space
+            if let (Some(value), ObjectEncoding::Git) = (line.strip_prefix(' '), encoding) {
+                let (_, last) = extra_headers
+                    .last_mut()
+                    .ok_or(Error::InvalidObject(format!(
+                        "broken commit line {:?}",
+                        line
+                    )))?;
+                last.push('\n');
+                last.push_str(value);
+                continue;
             }
-        });
 
-        let timestamp = date.parse::<i64>().unwrap();
-        let date = Utc.timestamp_opt(timestamp, 0).unwrap();
+            let (key, value) = line.split_once(' ').ok_or(Error::InvalidObject(format!(
+                "broken commit line {:?}",
+                line
+            )))?;
+
+            match key {
+                "tree" => tree_hash = Some(parse_hash(value, algorithm)?),
+                "parent" => parents.push(parse_hash(value, algorithm)?),
+                "author" if encoding == ObjectEncoding::Git => {
+                    let (identity, date, timezone) = parse_signature(value)?;
+                    author = identity;
+                    author_signature = Some((date, timezone));
+                }
+                "committer" if encoding == ObjectEncoding::Git => {
+                    let (identity, date, timezone) = parse_signature(value)?;
+                    committer = identity;
+                    committer_signature = Some((date, timezone));
+                }
+                "author" => author = value.to_string(),
+                "committer" => committer = value.to_string(),
+                "date" if encoding == ObjectEncoding::Nss => date = value.to_string(),
+                k if encoding == ObjectEncoding::Git => {
+                    extra_headers.push((k.to_string(), value.to_string()))
+                }
+                k => return Err(Error::InvalidObject(format!("unknown commit header {}", k))),
+            }
+        }
+
+        let tree_hash = tree_hash.ok_or(Error::InvalidObject("commit without tree".to_string()))?;
+
+        if encoding == ObjectEncoding::Git {
+            let ((author_date, author_timezone), (date, timezone)) =
+                match (author_signature, committer_signature) {
+                    (Some(author), Some(committer)) => (author, committer),
+                    _ => {
+                        return Err(Error::InvalidObject(
+                            "commit without author or committer".to_string(),
+                        ))
+                    }
+                };
+
+            return Ok(Self {
+                tree_hash,
+                parents,
+                author,
+                committer,
+                date,
+                message,
+                encoding,
+                timezone,
+                author_date,
+                author_timezone,
+                extra_headers,
+            });
+        }
+
+        let date = date
+            .parse::<i64>()
+            .ok()
+            .and_then(|t| Utc.timestamp_opt(t, 0).single())
+            .ok_or(Error::InvalidObject(format!(
+                "broken commit date {:?}",
+                date
+            )))?;
+        let timezone = FixedOffset::east_opt(0).unwrap();
 
         Ok(Self {
             tree_hash,
@@ -78,43 +208,77 @@ impl Commit {
             committer,
             date,
             message,
+            encoding,
+            timezone,
+            author_date: date,
+            author_timezone: timezone,
+            extra_headers,
         })
     }
-}
 
-impl std::fmt::Display for Commit {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    fn content(&self) -> String {
         let tree = format!("tree {}", self.tree_hash);
         let parents: String = self.parents.iter().fold(String::new(), |mut s, b| {
             let _ = writeln!(s, "parent {b}");
             s
         });
-        let author = format!("author {}", self.author);
-        let committer = format!("committer {}", self.committer);
-        let date = format!("date {}", self.date.timestamp());
-
-        write!(
-            f,
-            "{}\n{}{}\n{}\n{}\n\n{}\n",
-            tree, parents, author, committer, date, self.message
-        )
+
+        match self.encoding {
+            ObjectEncoding::Nss => format!(
+                "{}\n{}author {}\ncommitter {}\ndate {}\n\n{}",
+                tree,
+                parents,
+                self.author,
+                self.committer,
+                self.date.timestamp(),
+                self.message
+            ),
+            ObjectEncoding::Git => {
+                let extra_headers: String =
+                    self.extra_headers
+                        .iter()
+                        .fold(String::new(), |mut s, (key, value)| {
+                            let _ = writeln!(s, "{} {}", key, value.replace('\n', "\n "));
+                            s
+                        });
+
+                format!(
+                    "{}\n{}author {}\ncommitter {}\n{}\n{}",
+                    tree,
+                    parents,
+                    format_signature(&self.author, &self.author_date, &self.author_timezone),
+                    format_signature(&self.committer, &self.date, &self.timezone),
+                    extra_headers,
+                    self.message
+                )
+            }
+        }
+    }
+}
+
+/// `message` ending with `\n`, as git writes new commits and tags.
+pub(crate) fn with_newline(mut message: String) -> String {
+    if !message.ends_with('\n') {
+        message.push('\n');
+    }
+
+    message
+}
+
+fn parse_hash(hash: &str, algorithm: HashAlgorithm) -> Result<ObjectId, Error> {
+    ObjectId::from_hex_with(hash, algorithm)
+        .map_err(|_| Error::InvalidObject(format!("commit hash {:?} is not {}", hash, algorithm)))
+}
+
+impl std::fmt::Display for Commit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.content())
     }
 }
 
 impl Hashable for Commit {
     fn as_bytes(&self) -> Vec<u8> {
-        let tree_hash = format!("tree {}", self.tree_hash);
-        let parents: String = self.parents.iter().fold(String::new(), |mut s, b| {
-            let _ = writeln!(s, "parent {b}");
-            s
-        });
-        let author = format!("author {}", self.author);
-        let committer = format!("committer {}", self.committer);
-        let date = format!("date {}", self.date.timestamp());
-        let content = format!(
-            "{}\n{}{}\n{}\n{}\n\n{}\n",
-            tree_hash, parents, author, committer, date, self.message
-        );
+        let content = self.content();
         let store = format!("commit {}\0{}", content.len(), content);
 
         Vec::from(store.as_bytes())
@@ -125,11 +289,15 @@ impl Hashable for Commit {
 mod tests {
     use super::*;
 
+    fn id(hex: &str) -> ObjectId {
+        ObjectId::from_hex(hex).unwrap()
+    }
+
     #[test]
     fn test_commit_new() {
         let result = Commit::new(
-            "c192349d0ee530038e5d925fdd701652ca755ba8",
-            vec!["a02b83cb54ba139e5c9d623a2fcf5424552946e0".to_string()],
+            id("c192349d0ee530038e5d925fdd701652ca755ba8"),
+            vec![id("a02b83cb54ba139e5c9d623a2fcf5424552946e0")],
             "nopeNoshihsi",
             "nopeNoshihsi",
             "initial",
@@ -140,12 +308,17 @@ mod tests {
         let time = commit.date;
 
         let test_commit = Commit {
-            tree_hash: "c192349d0ee530038e5d925fdd701652ca755ba8".to_string(),
-            parents: vec!["a02b83cb54ba139e5c9d623a2fcf5424552946e0".to_string()],
+            tree_hash: id("c192349d0ee530038e5d925fdd701652ca755ba8"),
+            parents: vec![id("a02b83cb54ba139e5c9d623a2fcf5424552946e0")],
             author: "nopeNoshihsi".to_string(),
             committer: "nopeNoshihsi".to_string(),
             date: time,
-            message: "initial".to_string(),
+            message: "initial\n".to_string(),
+            encoding: ObjectEncoding::Nss,
+            timezone: FixedOffset::east_opt(0).unwrap(),
+            author_date: time,
+            author_timezone: FixedOffset::east_opt(0).unwrap(),
+            extra_headers: vec![],
         };
 
         assert_eq!(commit, test_commit);
@@ -168,27 +341,85 @@ initial
 
         // Verify the Commit instance's properties
         let test_commit = Commit {
-            tree_hash: "c192349d0ee530038e5d925fdd701652ca755ba8".to_string(),
-            parents: vec!["a02b83cb54ba139e5c9d623a2fcf5424552946e0".to_string()],
+            tree_hash: id("c192349d0ee530038e5d925fdd701652ca755ba8"),
+            parents: vec![id("a02b83cb54ba139e5c9d623a2fcf5424552946e0")],
             author: "nopeNoshihsi".to_string(),
             committer: "nopeNoshihsi".to_string(),
             date: Utc.timestamp_opt(1687619045, 0).unwrap(),
-            message: "initial".to_string(),
+            message: "initial\n".to_string(),
+            encoding: ObjectEncoding::Nss,
+            timezone: FixedOffset::east_opt(0).unwrap(),
+            author_date: Utc.timestamp_opt(1687619045, 0).unwrap(),
+            author_timezone: FixedOffset::east_opt(0).unwrap(),
+            extra_headers: vec![],
         };
 
         assert_eq!(commit, test_commit);
     }
 
+    #[test]
+    fn test_commit_from_rawobject_round_trip() {
+        let content = b"tree c192349d0ee530038e5d925fdd701652ca755ba8
+parent a02b83cb54ba139e5c9d623a2fcf5424552946e0
+parent 5c73008ba75573c20d6a8a6e557d0556d4a84133
+author nope noshishi <noshishi@nope.com>
+committer nope noshishi <noshishi@nope.com>
+date 1687619045
+
+first line
+
+second line
+";
+
+        let commit = Commit::from_rawobject(content).unwrap();
+        assert_eq!(commit.parents.len(), 2);
+        assert_eq!(commit.author, "nope noshishi <noshishi@nope.com>");
+        assert_eq!(commit.message, "first line\n\nsecond line\n");
+
+        let store = commit.as_bytes();
+        assert_eq!(&store[store.len() - content.len()..], content);
+
+        // Messages are kept as stored, whatever their trailing newlines
+        for message in ["", "no newline", "two newlines\n\n"] {
+            let content = format!(
+                "tree c192349d0ee530038e5d925fdd701652ca755ba8\nauthor a\ncommitter a\ndate 1\n\n{}",
+                message
+            );
+            let commit = Commit::from_rawobject(content.as_bytes()).unwrap();
+            assert_eq!(commit.message, message);
+            assert_eq!(commit.to_string(), content);
+        }
+
+        // Broken commits are errors
+        assert!(Commit::from_rawobject(b"tree abc\n").is_err());
+        assert!(Commit::from_rawobject(b"tree abc\ndate now\n\nmsg\n").is_err());
+        assert!(Commit::from_rawobject(b"tree abc\nsigned x\ndate 1\n\nmsg\n").is_err());
+        assert!(Commit::from_rawobject(b"tree abc\ndate 1\n\nmsg\n").is_err());
+
+        // Hashes must match the algorithm
+        assert!(Commit::from_rawobject_with(content, HashAlgorithm::Sha256).is_err());
+        let tree_hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let content = format!("tree {}\nauthor a\ncommitter a\ndate 1\n\nmsg\n", tree_hash);
+        let commit =
+            Commit::from_rawobject_with(content.as_bytes(), HashAlgorithm::Sha256).unwrap();
+        assert_eq!(commit.tree_hash.to_hex(), tree_hash);
+    }
+
     #[test]
     fn test_commit_as_bytes() {
         let time = Utc.timestamp_opt(1687619045, 0).unwrap();
         let commit = Commit {
-            tree_hash: "c192349d0ee530038e5d925fdd701652ca755ba8".to_string(),
-            parents: vec!["a02b83cb54ba139e5c9d623a2fcf5424552946e0".to_string()],
+            tree_hash: id("c192349d0ee530038e5d925fdd701652ca755ba8"),
+            parents: vec![id("a02b83cb54ba139e5c9d623a2fcf5424552946e0")],
             author: "nopeNoshihsi".to_string(),
             committer: "nopeNoshihsi".to_string(),
             date: time,
-            message: "initial".to_string(),
+            message: "initial\n".to_string(),
+            encoding: ObjectEncoding::Nss,
+            timezone: FixedOffset::east_opt(0).unwrap(),
+            author_date: time,
+            author_timezone: FixedOffset::east_opt(0).unwrap(),
+            extra_headers: vec![],
         };
 
         let content = commit.as_bytes();
@@ -204,6 +435,96 @@ initial
         assert_eq!(content, test_content);
     }
 
+    #[test]
+    fn test_commit_git_encoding() {
+        let content = b"tree 42a9894a7c9e078cfa481b075b7346fd00e3d1dc
+author nope noshishi <noshishi@nope.com> 1687619045 +0900
+committer nope noshishi <noshishi@nope.com> 1687619045 +0900
+
+initial
+";
+
+        let commit =
+            Commit::from_rawobject_with_encoding(content, HashAlgorithm::Sha1, ObjectEncoding::Git)
+                .unwrap();
+        assert_eq!(commit.author, "nope noshishi <noshishi@nope.com>");
+        assert_eq!(commit.date, Utc.timestamp_opt(1687619045, 0).unwrap());
+        assert_eq!(commit.timezone, FixedOffset::east_opt(9 * 3600).unwrap());
+
+        // Same hash as `git commit-tree`
+        assert_eq!(commit.to_string().as_bytes(), content);
+        assert_eq!(
+            commit.object_id().to_hex(),
+            "381c05cbc557ce5acfa7f369b87c952c8ff148ff"
+        );
+
+        // Each encoding only reads its own layout
+        assert!(Commit::from_rawobject(content).is_err());
+        let nss = b"tree 42a9894a7c9e078cfa481b075b7346fd00e3d1dc
+author nope
+committer nope
+date 1687619045
+
+initial
+";
+        assert!(Commit::from_rawobject_with_encoding(
+            nss,
+            HashAlgorithm::Sha1,
+            ObjectEncoding::Git
+        )
+        .is_err());
+
+        // Amended and signed commits keep both dates and every header
+        let content = b"tree 42a9894a7c9e078cfa481b075b7346fd00e3d1dc
+parent 381c05cbc557ce5acfa7f369b87c952c8ff148ff
+author nope noshishi <noshishi@nope.com> 1687619045 +0900
+committer other <other@nope.com> 1687700000 -0130
+encoding ISO-8859-1
+gpgsig -----BEGIN PGP SIGNATURE-----
+ 
+ iQEzBAABCAAdFiEE
+ -----END PGP SIGNATURE-----
+
+amended
+";
+        let commit =
+            Commit::from_rawobject_with_encoding(content, HashAlgorithm::Sha1, ObjectEncoding::Git)
+                .unwrap();
+        assert_eq!(
+            commit.author_date,
+            Utc.timestamp_opt(1687619045, 0).unwrap()
+        );
+        assert_eq!(commit.date, Utc.timestamp_opt(1687700000, 0).unwrap());
+        assert_eq!(commit.timezone, FixedOffset::west_opt(5400).unwrap());
+        assert_eq!(
+            commit.extra_headers,
+            vec![
+                ("encoding".to_string(), "ISO-8859-1".to_string()),
+                (
+                    "gpgsig".to_string(),
+                    "-----BEGIN PGP SIGNATURE-----\n\niQEzBAABCAAdFiEE\n-----END PGP SIGNATURE-----"
+                        .to_string()
+                ),
+            ]
+        );
+        assert_eq!(commit.to_string().as_bytes(), content);
+        assert_eq!(
+            commit.object_id().to_hex(),
+            "6a82f6570b8858f316e8f59c21e390336f2b650a"
+        );
+
+        // Both signatures are required
+        let content = String::from_utf8(content.to_vec())
+            .unwrap()
+            .replace("committer", "signer");
+        assert!(Commit::from_rawobject_with_encoding(
+            content.as_bytes(),
+            HashAlgorithm::Sha1,
+            ObjectEncoding::Git
+        )
+        .is_err());
+    }
+
     #[test]
     fn test_commit_to_hash() {}
 
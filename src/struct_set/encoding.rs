@@ -0,0 +1,118 @@
+// External
+use chrono::prelude::{DateTime, Utc};
+use chrono::{FixedOffset, TimeZone};
+use serde::{Deserialize, Serialize};
+
+// Internal
+use super::error::Error;
+
+/// **ObjectEncoding Enum**
+///
+/// How trees, commits and tags are laid out. Blobs are the same in both.
+///     - nss ... decimal tree modes, entries in the given order, a `date`
+///       line in commits and tags
+///     - git ... git's layout, so objects hash the same as in git: octal
+///       normalized modes, git's entry order, timestamp and timezone on the
+///       author, committer and tagger lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectEncoding {
+    #[default]
+    Nss,
+    Git,
+}
+
+impl ObjectEncoding {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Nss => "nss",
+            Self::Git => "git",
+        }
+    }
+}
+
+impl std::fmt::Display for ObjectEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// `<identity> <timestamp> <+hhmm>` of a git author, committer or tagger.
+pub(crate) fn format_signature(
+    identity: &str,
+    date: &DateTime<Utc>,
+    timezone: &FixedOffset,
+) -> String {
+    let offset = timezone.local_minus_utc();
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.abs() / 60;
+
+    format!(
+        "{} {} {}{:02}{:02}",
+        identity,
+        date.timestamp(),
+        sign,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+/// Split a git signature into identity, date and timezone.
+pub(crate) fn parse_signature(value: &str) -> Result<(String, DateTime<Utc>, FixedOffset), Error> {
+    let broken = || Error::InvalidObject(format!("broken signature {:?}", value));
+
+    let mut parts = value.rsplitn(3, ' ');
+    let (timezone, timestamp, identity) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(timezone), Some(timestamp), Some(identity)) => (timezone, timestamp, identity),
+        _ => return Err(broken()),
+    };
+
+    let date = timestamp
+        .parse::<i64>()
+        .ok()
+        .and_then(|t| Utc.timestamp_opt(t, 0).single())
+        .ok_or_else(broken)?;
+
+    let (sign, digits) = timezone.split_at(timezone.len().min(1));
+    let sign = match sign {
+        "+" => 1,
+        "-" => -1,
+        _ => return Err(broken()),
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(broken());
+    }
+    let hours: i32 = digits[..2].parse().unwrap();
+    let minutes: i32 = digits[2..].parse().unwrap();
+    let timezone =
+        FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or_else(broken)?;
+
+    Ok((identity.to_string(), date, timezone))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature() {
+        let date = Utc.timestamp_opt(1687619045, 0).unwrap();
+        let timezone = FixedOffset::west_opt(5 * 3600 + 30 * 60).unwrap();
+        let signature = format_signature("nope <nope@nope.com>", &date, &timezone);
+        assert_eq!(signature, "nope <nope@nope.com> 1687619045 -0530");
+        assert_eq!(
+            parse_signature(&signature).unwrap(),
+            ("nope <nope@nope.com>".to_string(), date, timezone)
+        );
+
+        for broken in [
+            "nope",
+            "nope 1687619045",
+            "nope now +0900",
+            "nope 1 0900",
+            "nope 1 +9",
+        ] {
+            assert!(parse_signature(broken).is_err());
+        }
+    }
+}
@@ -22,6 +22,15 @@ pub enum Error {
     #[error("{0} is not {1} hash")]
     DontMatchType(String, String),
 
+    #[error("Invalid object: {0}")]
+    InvalidObject(String),
+
+    #[error("Invalid object id: {0:?}")]
+    InvalidObjectId(String),
+
+    #[error("This operation must be run in a work tree (bare repository)")]
+    BareRepository,
+
     #[error("nss repository error: {0}")]
     NssIoError(#[from] NssIoError),
 
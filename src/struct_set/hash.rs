@@ -0,0 +1,142 @@
+// Std
+use std::io::{self, Write};
+
+// External
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// **HashAlgorithm Enum**
+///
+/// Hash function of object ids, chosen once per repository.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub fn digest(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha1 => Sha1::digest(bytes).to_vec(),
+            Self::Sha256 => Sha256::digest(bytes).to_vec(),
+        }
+    }
+
+    /// Hash length in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Sha1 => 20,
+            Self::Sha256 => 32,
+        }
+    }
+
+    /// Hash length in hex digits.
+    pub fn hex_size(&self) -> usize {
+        self.size() * 2
+    }
+
+    /// Whether `hash` is a full lowercase hex hash of this algorithm, the
+    /// only spelling objects are stored and cached under.
+    pub fn is_hex_hash(&self, hash: &str) -> bool {
+        hash.len() == self.hex_size()
+            && hash
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    }
+
+    /// Incremental hasher, for content too large to hold in memory.
+    pub fn hasher(&self) -> StreamHasher {
+        match self {
+            Self::Sha1 => StreamHasher::Sha1(Sha1::new()),
+            Self::Sha256 => StreamHasher::Sha256(Sha256::new()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Sha1 => "sha1",
+            Self::Sha256 => "sha256",
+        }
+    }
+}
+
+impl std::fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// **StreamHasher Enum**
+///
+/// Running state of a [`HashAlgorithm`], fed through `update` or `Write`.
+#[derive(Debug, Clone)]
+pub enum StreamHasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl StreamHasher {
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Sha1(hasher) => hasher.update(bytes),
+            Self::Sha256(hasher) => hasher.update(bytes),
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::Sha1(hasher) => hasher.finalize().to_vec(),
+            Self::Sha256(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+}
+
+impl Write for StreamHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_algorithm_digest() {
+        assert_eq!(
+            hex::encode(HashAlgorithm::Sha1.digest(b"blob 6\0hellow")),
+            hex::encode(sha1::Sha1::digest(b"blob 6\0hellow"))
+        );
+        assert_eq!(
+            hex::encode(HashAlgorithm::Sha256.digest(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        for algorithm in [HashAlgorithm::Sha1, HashAlgorithm::Sha256] {
+            let hash = hex::encode(algorithm.digest(b"abc"));
+            assert_eq!(hash.len(), algorithm.hex_size());
+            assert!(algorithm.is_hex_hash(&hash));
+        }
+        assert!(!HashAlgorithm::Sha256.is_hex_hash(&"a".repeat(40)));
+        assert!(!HashAlgorithm::Sha1.is_hex_hash(&"g".repeat(40)));
+        assert!(!HashAlgorithm::Sha1.is_hex_hash(&"A".repeat(40)));
+    }
+
+    #[test]
+    fn test_stream_hasher() {
+        for algorithm in [HashAlgorithm::Sha1, HashAlgorithm::Sha256] {
+            let mut hasher = algorithm.hasher();
+            hasher.update(b"blob 6\0");
+            hasher.write_all(b"hellow").unwrap();
+            assert_eq!(hasher.finalize(), algorithm.digest(b"blob 6\0hellow"));
+        }
+    }
+}
@@ -4,8 +4,11 @@ use std::path::PathBuf;
 // External
 use serde::{Deserialize, Serialize};
 
+// Internal
+use super::ObjectId;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub enum Head {
     Bookmarker(PathBuf),
-    ObjectHash(String),
+    ObjectHash(ObjectId),
 }
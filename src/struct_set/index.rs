@@ -9,9 +9,10 @@ use byteorder::{BigEndian, ByteOrder};
 
 // Internal
 use super::error::Error;
-use super::{Blob, DIffTag, Diff, FileMeta, Hashable, Object, Tree};
-use crate::nss_io::file_system::{create_dir, remove_dir_all, write_content, WriteMode};
-use crate::repo::repository::{get_all_paths_ignore, NssRepository, PathRepository};
+use super::metadata::STAT_SIZE;
+use super::{Blob, DIffTag, Diff, FileMeta, HashAlgorithm, Object, ObjectId, Tree};
+use crate::repo::database::ObjectDatabase;
+use crate::repo::repository::{get_all_paths_ignore, NssRepository};
 
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct Index {
@@ -28,6 +29,10 @@ impl Index {
     }
 
     pub fn new_all(repository: &NssRepository) -> Result<Self, Error> {
+        if repository.is_bare() {
+            return Err(Error::BareRepository);
+        }
+
         let mut all_paths = get_all_paths_ignore(repository.root.clone(), &repository.root);
         all_paths.sort();
 
@@ -49,7 +54,7 @@ impl Index {
         temp_prefix: Option<P>,
     ) -> Result<(), Error> {
         let add_filemeta = match temp_prefix {
-            Some(p) => FileMeta::new_temp(file_path, p)?,
+            Some(p) => FileMeta::new_temp_with(file_path, p, repository.hash_algorithm())?,
             None => FileMeta::new(repository, file_path)?,
         };
 
@@ -68,65 +73,91 @@ impl Index {
         Ok(())
     }
 
-    pub fn try_from_tree(repository: &NssRepository, tree: Tree) -> Result<Self, Error> {
-        let mut index = Index::empty();
-        let mut path_blob: HashMap<PathBuf, Blob> = HashMap::new();
-
-        let temp_dir = repository.temp_path(hex::encode(tree.to_hash()));
-        create_dir(&temp_dir)?;
-
-        push_paths(repository, &mut path_blob, tree, &temp_dir)?;
+    /// Parse an index whose entry hashes are of `algorithm`.
+    pub fn from_rawindex_with(buf: Vec<u8>, algorithm: HashAlgorithm) -> Result<Self, Error> {
+        if buf.is_empty() {
+            return Ok(Index::default());
+        }
 
-        // Tempolary create file -> filemeta
-        for (path, blob) in path_blob {
-            create_dir(path.parent().unwrap())?;
-            write_content(&path, &blob.content, WriteMode::CreateNewTrucateWithZlib)?;
+        let entry_num = BigEndian::read_u32(&buf[8..12]) as usize;
+        let name_start = STAT_SIZE + algorithm.size() + 2;
+        let mut start_size = 12_usize;
+        let mut filemetas: Vec<FileMeta> = vec![];
+        for _ in 0..entry_num {
+            let name_size =
+                BigEndian::read_u16(&buf[(start_size + name_start - 2)..(start_size + name_start)])
+                    as usize;
+            let len = name_start + name_size;
+            filemetas.push(FileMeta::from_rawindex_with(
+                &buf[(start_size)..(start_size + len)],
+                algorithm,
+            ));
 
-            index.add(repository, path, Some(temp_dir.clone()))?;
+            start_size = start_size + len + padding(len);
         }
 
-        remove_dir_all(temp_dir)?;
+        Ok(Self {
+            version: 1,
+            filemetas,
+        })
+    }
 
-        Ok(index)
+    pub fn try_from_tree<D: ObjectDatabase + ?Sized>(
+        database: &D,
+        tree: Tree,
+    ) -> Result<Self, Error> {
+        let mut path_blob: HashMap<PathBuf, (u32, Blob)> = HashMap::new();
+        push_paths(database, &mut path_blob, tree, Path::new(""))?;
+
+        let mut filemetas = path_blob
+            .into_iter()
+            .map(|(path, (mode, blob))| {
+                FileMeta::from_blob(path, mode, &blob, database.hash_algorithm())
+            })
+            .collect::<Vec<_>>();
+        filemetas.sort_by(|a, b| b.filename.cmp(&a.filename));
+
+        Ok(Self {
+            version: 1,
+            filemetas,
+        })
     }
 }
 
 fn padding(size: usize) -> usize {
-    // calclate padding size
-    let floor = (size - 2) / 8;
-    let target = (floor + 1) * 8 + 2;
-
-    target - size
+    // calclate padding size, every entry ends with at least one nul
+    8 - size % 8
 }
 
-fn push_paths(
-    repository: &NssRepository,
-    path_blob: &mut HashMap<PathBuf, Blob>,
+fn push_paths<D: ObjectDatabase + ?Sized>(
+    database: &D,
+    path_blob: &mut HashMap<PathBuf, (u32, Blob)>,
     tree: Tree,
     base_path: &Path,
 ) -> Result<(), Error> {
     for entry in tree.entries {
+        entry.check_name()?;
         let path = base_path.join(&entry.name);
 
         if entry.as_type() == "blob" {
-            let blob = match repository.objects().read(hex::encode(&entry.hash)) {
+            let blob = match database.read_object(&entry.hash.to_hex()) {
                 Ok(Object::Blob(b)) => b,
                 _ => {
                     return Err(Error::DontMatchType(
                         "Blob".to_string(),
-                        hex::encode(entry.hash),
+                        entry.hash.to_hex(),
                     ))
                 }
             };
-            path_blob.insert(path, blob);
+            path_blob.insert(path, (entry.mode, blob));
         } else {
-            let hash = hex::encode(entry.hash);
-            let sub_tree = match repository.objects().read(&hash) {
+            let hash = entry.hash.to_hex();
+            let sub_tree = match database.read_object(&hash) {
                 Ok(Object::Tree(t)) => t,
                 _ => return Err(Error::DontMatchType("Tree".to_string(), hash)),
             };
 
-            push_paths(repository, path_blob, sub_tree, &path)?
+            push_paths(database, path_blob, sub_tree, &path)?
         }
     }
 
@@ -154,8 +185,9 @@ impl IndexVesion1 for Index {
 
         let mut filemetas_vec: Vec<Vec<u8>> = vec![];
         for filemeta in &self.filemetas {
-            let len = 62 + filemeta.filename_size as usize;
-            let padding = (0..(8 - len % 8)).map(|_| b'\0').collect::<Vec<u8>>();
+            let len =
+                STAT_SIZE + filemeta.hash.as_bytes().len() + 2 + filemeta.filename_size as usize;
+            let padding = (0..padding(len)).map(|_| b'\0').collect::<Vec<u8>>();
             let filemeta_vec = [filemeta.as_bytes(), padding].concat();
 
             filemetas_vec.push(filemeta_vec)
@@ -165,28 +197,7 @@ impl IndexVesion1 for Index {
     }
 
     fn from_rawindex(buf: Vec<u8>) -> Result<Self, Error> {
-        if buf.is_empty() {
-            return Ok(Index::default());
-        }
-
-        let entry_num = BigEndian::read_u32(&buf[8..12]) as usize;
-        let mut start_size = 12_usize;
-        let mut filemetas: Vec<FileMeta> = vec![];
-        for _ in 0..entry_num {
-            let name_size =
-                BigEndian::read_u16(&buf[(start_size + 60)..(start_size + 62)]) as usize;
-            filemetas.push(FileMeta::from_rawindex(
-                &buf[(start_size)..(start_size + 62 + name_size)],
-            ));
-
-            let padding_size = padding(name_size);
-            start_size = start_size + 62 + name_size + padding_size;
-        }
-
-        Ok(Self {
-            version: 1,
-            filemetas,
-        })
+        Index::from_rawindex_with(buf, HashAlgorithm::Sha1)
     }
 }
 
@@ -215,15 +226,15 @@ impl Diff<Index, OsString> for Index {
     fn diff(&self, vs: Index) -> Vec<(DIffTag, OsString)> {
         let mut changes = Vec::new();
 
-        let new_metas: HashMap<OsString, Vec<u8>> = self
+        let new_metas: HashMap<OsString, ObjectId> = self
             .filemetas
             .iter()
-            .map(|f| (f.filename.clone(), f.hash.clone()))
+            .map(|f| (f.filename.clone(), f.hash))
             .collect();
-        let old_metas: HashMap<OsString, Vec<u8>> = vs
+        let old_metas: HashMap<OsString, ObjectId> = vs
             .filemetas
             .iter()
-            .map(|f| (f.filename.clone(), f.hash.clone()))
+            .map(|f| (f.filename.clone(), f.hash))
             .collect();
 
         old_metas.iter().for_each(|(k, v)| {
@@ -271,7 +282,29 @@ mod tests {
     fn test_index_new_all() {}
 
     #[test]
-    fn test_index_from_rawindex() {}
+    fn test_index_from_rawindex() {
+        let blob = Blob {
+            content: b"content".to_vec(),
+        };
+
+        for algorithm in [HashAlgorithm::Sha1, HashAlgorithm::Sha256] {
+            let index = Index {
+                version: 1,
+                filemetas: vec![
+                    FileMeta::from_blob("src/main.rs", 0o100644, &blob, algorithm),
+                    FileMeta::from_blob("a", 0o100755, &blob, algorithm),
+                ],
+            };
+
+            let read = Index::from_rawindex_with(index.as_bytes(), algorithm).unwrap();
+            assert_eq!(read.filemetas.len(), 2);
+            assert_eq!(read.filemetas[0].filename, OsString::from("src/main.rs"));
+            assert_eq!(read.filemetas[1].filename, OsString::from("a"));
+            assert_eq!(read.filemetas[1].mode, 0o100755);
+            assert_eq!(read.filemetas[1].hash.as_bytes().len(), algorithm.size());
+            assert_eq!(read.as_bytes(), index.as_bytes());
+        }
+    }
 
     #[test]
     fn test_index_add() {}
@@ -280,10 +313,67 @@ mod tests {
     fn test_as_bytes() {}
 
     #[test]
-    fn test_padding() {}
+    fn test_padding() {
+        assert_eq!(padding(62 + 2), 8);
+        assert_eq!(padding(62 + 9), 1);
+        assert_eq!(padding(74 + 3), 3);
+    }
 
     #[test]
-    fn test_index_try_from_tree() {}
+    fn test_index_try_from_tree() {
+        use crate::repo::database::MemoryObjectDatabase;
+        use crate::struct_set::{Entry, Hashable};
+
+        let database = MemoryObjectDatabase::new();
+        let first = Blob {
+            content: b"first".to_vec(),
+        };
+        let second = Blob {
+            content: b"second file".to_vec(),
+        };
+        database.write_object(&Object::Blob(first.clone())).unwrap();
+        database
+            .write_object(&Object::Blob(second.clone()))
+            .unwrap();
+
+        let sub_tree = Tree::from_entries(vec![Entry {
+            mode: 0o100644,
+            name: OsString::from("second.txt"),
+            hash: second.object_id(),
+        }]);
+        database
+            .write_object(&Object::Tree(sub_tree.clone()))
+            .unwrap();
+        let tree = Tree::from_entries(vec![
+            Entry {
+                mode: 0o100755,
+                name: OsString::from("first.txt"),
+                hash: first.object_id(),
+            },
+            Entry {
+                mode: 0o40755,
+                name: OsString::from("sub"),
+                hash: sub_tree.object_id(),
+            },
+        ]);
+
+        let index = Index::try_from_tree(&database, tree.clone()).unwrap();
+        let filenames: Vec<OsString> = index.filemetas.iter().map(|f| f.filename.clone()).collect();
+        assert_eq!(
+            filenames,
+            vec![
+                OsString::from("sub/second.txt"),
+                OsString::from("first.txt")
+            ]
+        );
+        assert_eq!(index.filemetas[0].hash, second.object_id());
+        assert_eq!(index.filemetas[0].filesize, 11);
+        assert_eq!(index.filemetas[1].mode, 0o100755);
+
+        // Missing objects
+        let empty = MemoryObjectDatabase::new();
+        assert!(Index::try_from_tree(&empty, tree).is_err());
+    }
 
     #[test]
     fn test_push_paths() {}
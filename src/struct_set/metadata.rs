@@ -1,5 +1,6 @@
 // Std
 use std::ffi::OsString;
+use std::fs::File;
 use std::path::Path;
 
 // External
@@ -11,9 +12,13 @@ use chrono::TimeZone;
 
 // Internal
 use super::error::Error;
-use super::{Blob, Hashable};
+use super::{Blob, HashAlgorithm, Hashable, ObjectId};
+use crate::repo::large::LargePointer;
 use crate::repository::NssRepository;
 
+/// Bytes of the stat fields at the head of an index entry.
+pub(crate) const STAT_SIZE: usize = 40;
+
 #[derive(Debug, Clone)]
 pub struct FileMeta {
     pub ctime: u32,
@@ -26,7 +31,7 @@ pub struct FileMeta {
     pub uid: u32,
     pub gid: u32,
     pub filesize: u32,
-    pub hash: Vec<u8>,
+    pub hash: ObjectId,
     pub filename_size: u16,
     pub filename: OsString,
 }
@@ -36,6 +41,10 @@ impl FileMeta {
         // NOTE: Only unix metadata
         use std::os::unix::prelude::MetadataExt;
 
+        if repository.is_bare() {
+            return Err(Error::BareRepository);
+        }
+
         let path = path.as_ref();
         // Exstract metadata on file
         let metadata = path.metadata().unwrap();
@@ -50,15 +59,22 @@ impl FileMeta {
         let gid = metadata.gid();
         let filesize = metadata.size() as u32;
 
-        let object = Blob::new(path)?;
-        let hash = object.to_hash();
-
         // absolute path -> relative path (from repo path)
         let filename = path
             .strip_prefix(repository.root.clone())
             .unwrap()
             .as_os_str()
             .to_os_string();
+
+        // Streamed, large files are never read whole
+        let algorithm = repository.hash_algorithm();
+        let file = File::open(path)?;
+        let hash = match repository.large_objects().is_large(&filename) {
+            true => LargePointer::from_reader(file, metadata.size(), algorithm)?
+                .to_blob()
+                .object_id_with(algorithm),
+            false => ObjectId::from_bytes(&Blob::hash_reader(file, metadata.size(), algorithm)?)?,
+        };
         let filename_size = filename.len() as u16;
 
         Ok(Self {
@@ -79,6 +95,15 @@ impl FileMeta {
     }
 
     pub fn new_temp<P: AsRef<Path>>(temp_path: P, temp_prefix: P) -> Result<Self, Error> {
+        Self::new_temp_with(temp_path, temp_prefix, HashAlgorithm::Sha1)
+    }
+
+    /// [`FileMeta::new_temp`] with the blob named by `algorithm`.
+    pub fn new_temp_with<P: AsRef<Path>>(
+        temp_path: P,
+        temp_prefix: P,
+        algorithm: HashAlgorithm,
+    ) -> Result<Self, Error> {
         // NOTE: Only unix metadata
         use std::os::unix::prelude::MetadataExt;
 
@@ -97,7 +122,7 @@ impl FileMeta {
         let filesize = metadata.size() as u32;
 
         let object = Blob::new(path)?;
-        let hash = object.to_hash();
+        let hash = object.object_id_with(algorithm);
 
         // absolute path -> relative path (from temp path)
         let filename = path
@@ -124,7 +149,47 @@ impl FileMeta {
         })
     }
 
+    /// Filemeta of a stored blob, stat fields are zero as no file exists.
+    ///
+    /// A large-file pointer has the size of the content it points to, the
+    /// size of the file once checked out.
+    pub fn from_blob<S: Into<OsString>>(
+        filename: S,
+        mode: u32,
+        blob: &Blob,
+        algorithm: HashAlgorithm,
+    ) -> Self {
+        let filename = filename.into();
+        let filename_size = filename.len() as u16;
+        let filesize = match LargePointer::from_blob(blob) {
+            Some(pointer) => pointer.size as u32,
+            None => blob.content.len() as u32,
+        };
+
+        Self {
+            ctime: 0,
+            ctime_nsec: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            filesize,
+            hash: blob.object_id_with(algorithm),
+            filename_size,
+            filename,
+        }
+    }
+
+    /// Create FileMeta with a SHA-1 index entry.
     pub fn from_rawindex(buf: &[u8]) -> Self {
+        Self::from_rawindex_with(buf, HashAlgorithm::Sha1)
+    }
+
+    /// Create FileMeta with an index entry whose hash is of `algorithm`.
+    pub fn from_rawindex_with(buf: &[u8], algorithm: HashAlgorithm) -> Self {
         let ctime = BigEndian::read_u32(&buf[0..4]);
         let ctime_nsec = BigEndian::read_u32(&buf[4..8]);
         let mtime = BigEndian::read_u32(&buf[8..12]);
@@ -135,10 +200,15 @@ impl FileMeta {
         let uid = BigEndian::read_u32(&buf[28..32]);
         let gid = BigEndian::read_u32(&buf[32..36]);
         let filesize = BigEndian::read_u32(&buf[36..40]);
-        let hash = Vec::from(&buf[40..60]);
-        let filename_size = BigEndian::read_u16(&buf[60..62]);
+        let name_start = STAT_SIZE + algorithm.size() + 2;
+        // Exactly one hash of `algorithm`, always a valid id
+        let hash = ObjectId::from_bytes(&buf[STAT_SIZE..(name_start - 2)]).unwrap();
+        let filename_size = BigEndian::read_u16(&buf[(name_start - 2)..name_start]);
         let filename = OsString::from(
-            String::from_utf8(Vec::from(&buf[62..(62 + (filename_size as usize))])).unwrap(),
+            String::from_utf8(Vec::from(
+                &buf[name_start..(name_start + (filename_size as usize))],
+            ))
+            .unwrap(),
         );
         Self {
             ctime,
@@ -174,7 +244,7 @@ impl FileMeta {
 
         let filemeta_vec = [
             entry_meta,
-            self.hash.clone(),
+            self.hash.as_bytes().to_vec(),
             Vec::from(self.filename_size.to_be_bytes()),
             self.filename.to_str().unwrap().as_bytes().to_vec(),
         ]
@@ -210,7 +280,7 @@ impl std::fmt::Display for FileMeta {
             "Name {} / Size {} / Hash {}",
             self.filename.to_str().unwrap(),
             self.filesize,
-            hex::encode(self.hash.clone())
+            self.hash
         );
 
         write!(
@@ -256,6 +326,26 @@ mod tests {
         fs::remove_dir_all(temp_dir).unwrap();
     }
 
+    #[test]
+    fn test_filemeta_new_bare() {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let test_file_root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("tests")
+            .join("test_repo")
+            .join("first.rs");
+
+        let repository = NssRepository::new_bare(temp_dir.clone());
+        fs::copy(test_file_root, repository.path().join("first.rs")).unwrap();
+
+        let result = FileMeta::new(&repository, repository.path().join("first.rs"));
+        assert!(matches!(result, Err(Error::BareRepository)));
+
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
     #[test]
     fn test_filemeta_new_temp() {
         // Create a temporary directory for testing
@@ -442,7 +532,7 @@ Name first.rs / Size 250 / Hash 5c73008ba75573c20d6a8a6e557d0556d4a84133",
 
         let debug = format!("{:?}", filemeta);
 
-        let test_debug = format!("FileMeta {{ ctime: {}, ctime_nsec: {}, mtime: {}, mtime_nsec: {}, dev: {}, ino: {}, mode: {}, uid: {}, gid: {}, filesize: 250, hash: [92, 115, 0, 139, 167, 85, 115, 194, 13, 106, 138, 110, 85, 125, 5, 86, 212, 168, 65, 51], filename_size: 8, filename: \"first.rs\" }}",
+        let test_debug = format!("FileMeta {{ ctime: {}, ctime_nsec: {}, mtime: {}, mtime_nsec: {}, dev: {}, ino: {}, mode: {}, uid: {}, gid: {}, filesize: 250, hash: ObjectId(5c73008ba75573c20d6a8a6e557d0556d4a84133), filename_size: 8, filename: \"first.rs\" }}",
             filemeta.ctime,
             filemeta.ctime_nsec,
             filemeta.mtime,
@@ -1,12 +1,9 @@
 // Std
 use std::path::Path;
 
-// External
-use sha1::{Digest, Sha1};
-
 // Internal
 use super::error::Error;
-use super::{Blob, Commit, Tree};
+use super::{Blob, Commit, HashAlgorithm, ObjectEncoding, ObjectId, Tag, Tree};
 
 /// **Object Enum**
 ///
@@ -16,6 +13,7 @@ pub enum Object {
     Blob(Blob),
     Tree(Tree),
     Commit(Commit),
+    Tag(Tag),
 }
 
 impl Object {
@@ -23,30 +21,112 @@ impl Object {
     ///
     /// This path must be in the working directory.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::new_with_encoding(path, ObjectEncoding::Nss)
+    }
+
+    /// Create object with the path, directories becoming trees laid out in
+    /// `encoding`, e.g. the repository's.
+    pub fn new_with_encoding<P: AsRef<Path>>(
+        path: P,
+        encoding: ObjectEncoding,
+    ) -> Result<Self, Error> {
+        Self::new_with(path, HashAlgorithm::Sha1, encoding)
+    }
+
+    /// Create object with the path, sub trees named by `algorithm` and laid
+    /// out in `encoding`, e.g. the repository's.
+    pub fn new_with<P: AsRef<Path>>(
+        path: P,
+        algorithm: HashAlgorithm,
+        encoding: ObjectEncoding,
+    ) -> Result<Self, Error> {
         if !path.as_ref().exists() {
             return Err(Error::NotFoundPath);
         }
         match path.as_ref().is_file() {
             true => Blob::new(path.as_ref()).map(Object::Blob),
-            false => Tree::new(path.as_ref()).map(Object::Tree),
+            false => Tree::new_with(path.as_ref(), algorithm, encoding).map(Object::Tree),
         }
     }
 
+    /// Parse a SHA-1 object, see [`Object::from_content_with`].
     pub fn from_content(raw_content: Vec<u8>) -> Result<Self, Error> {
+        Self::from_content_with(raw_content, HashAlgorithm::Sha1)
+    }
+
+    /// Parse `<type> <size>\0<content>`, tree, commit and tag hashes being of
+    /// `algorithm`.
+    pub fn from_content_with(
+        raw_content: Vec<u8>,
+        algorithm: HashAlgorithm,
+    ) -> Result<Self, Error> {
+        Self::from_content_with_encoding(raw_content, algorithm, ObjectEncoding::Nss)
+    }
+
+    /// Parse `<type> <size>\0<content>` laid out in `encoding`.
+    pub fn from_content_with_encoding(
+        raw_content: Vec<u8>,
+        algorithm: HashAlgorithm,
+        encoding: ObjectEncoding,
+    ) -> Result<Self, Error> {
         let mut iter = raw_content.splitn(2, |&x| x == b'\0');
 
         // header ≒ b"<object-type> <contnet-size>"
         let header = iter.next().unwrap().to_vec();
-        let header = String::from_utf8(header).unwrap();
-        let object_type = header.split(' ').collect::<Vec<&str>>()[0];
+        let header = String::from_utf8(header)
+            .map_err(|_| Error::InvalidObject("header is not utf8".to_string()))?;
+        let (object_type, size) = header
+            .split_once(' ')
+            .ok_or(Error::InvalidObject(format!("broken header {:?}", header)))?;
 
         // content ≒ b"<contnet>"
-        let content = iter.next().unwrap();
+        let content = iter
+            .next()
+            .ok_or(Error::InvalidObject("no header terminator".to_string()))?;
+        if size.parse::<usize>().ok() != Some(content.len()) {
+            return Err(Error::InvalidObject(format!(
+                "header size {} does not match content size {}",
+                size,
+                content.len()
+            )));
+        }
+
         match object_type {
             "blob" => Blob::from_rawobject(content).map(Object::Blob),
-            "tree" => Tree::from_rawobject(content).map(Object::Tree),
-            "commit" => Commit::from_rawobject(content).map(Object::Commit),
-            _ => todo!(),
+            "tree" => {
+                Tree::from_rawobject_with_encoding(content, algorithm, encoding).map(Object::Tree)
+            }
+            "commit" => Commit::from_rawobject_with_encoding(content, algorithm, encoding)
+                .map(Object::Commit),
+            "tag" => {
+                Tag::from_rawobject_with_encoding(content, algorithm, encoding).map(Object::Tag)
+            }
+            t => Err(Error::InvalidObject(format!("unknown object type {}", t))),
+        }
+    }
+
+    /// Layout of trees, commits and tags, `None` for blobs.
+    pub fn encoding(&self) -> Option<ObjectEncoding> {
+        match self {
+            Self::Blob(_) => None,
+            Self::Tree(tree) => Some(tree.encoding),
+            Self::Commit(commit) => Some(commit.encoding),
+            Self::Tag(tag) => Some(tag.encoding),
+        }
+    }
+
+    /// Ids this object embeds: a tree's entries, a commit's tree and
+    /// parents, a tag's target.
+    pub fn object_ids(&self) -> Vec<&ObjectId> {
+        match self {
+            Self::Blob(_) => vec![],
+            Self::Tree(tree) => tree.entries.iter().map(|e| &e.hash).collect(),
+            Self::Commit(commit) => {
+                let mut ids = vec![&commit.tree_hash];
+                ids.extend(commit.parents.iter());
+                ids
+            }
+            Self::Tag(tag) => vec![&tag.object],
         }
     }
 
@@ -56,6 +136,7 @@ impl Object {
             Self::Blob(_) => "blob",
             Self::Tree(_) => "tree",
             Self::Commit(_) => "commit",
+            Self::Tag(_) => "tag",
         }
     }
 }
@@ -66,6 +147,7 @@ impl std::fmt::Display for Object {
             Self::Blob(blob) => blob.fmt(f),
             Self::Tree(tree) => tree.fmt(f),
             Self::Commit(commit) => commit.fmt(f),
+            Self::Tag(tag) => tag.fmt(f),
         }
     }
 }
@@ -76,6 +158,7 @@ impl Hashable for Object {
             Self::Blob(blob) => blob.as_bytes(),
             Self::Tree(tree) => tree.as_bytes(),
             Self::Commit(commit) => commit.as_bytes(),
+            Self::Tag(tag) => tag.as_bytes(),
         }
     }
 
@@ -84,6 +167,7 @@ impl Hashable for Object {
             Self::Blob(blob) => blob.to_hash(),
             Self::Tree(tree) => tree.to_hash(),
             Self::Commit(commit) => commit.to_hash(),
+            Self::Tag(tag) => tag.to_hash(),
         }
     }
 }
@@ -93,19 +177,54 @@ pub trait Hashable {
 
     /// Content to hash by sha1 hash function.
     fn to_hash(&self) -> Vec<u8> {
-        Vec::from(Sha1::digest(self.as_bytes()).as_slice())
+        self.to_hash_with(HashAlgorithm::Sha1)
+    }
+
+    /// Content to hash by the `algorithm` of the repository.
+    fn to_hash_with(&self, algorithm: HashAlgorithm) -> Vec<u8> {
+        algorithm.digest(&self.as_bytes())
+    }
+
+    /// Name of the content in a SHA-1 repository.
+    fn object_id(&self) -> ObjectId {
+        self.object_id_with(HashAlgorithm::Sha1)
+    }
+
+    /// Name of the content in a repository of `algorithm`.
+    fn object_id_with(&self, algorithm: HashAlgorithm) -> ObjectId {
+        ObjectId::from_bytes(&self.to_hash_with(algorithm)).unwrap()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
 
     #[test]
     fn test_object_new() {}
 
     #[test]
-    fn test_object_from_content() {}
+    fn test_object_from_content() {
+        let blob = Object::from_content(b"blob 6\0hellow".to_vec()).unwrap();
+        assert_eq!(blob.as_str(), "blob");
+        assert_eq!(blob.as_bytes(), b"blob 6\0hellow");
+
+        // Broken objects are errors, not panics
+        let broken: Vec<&[u8]> = vec![
+            b"",
+            b"blob 6",
+            b"blob\0hellow",
+            b"blob 7\0hellow",
+            b"blub 6\0hellow",
+            b"tree 3\0abc",
+            b"commit 5\0hello",
+            b"tag 5\0hello",
+        ];
+        for content in broken {
+            let result = Object::from_content(content.to_vec());
+            assert!(matches!(result, Err(Error::InvalidObject(_))));
+        }
+    }
 
     #[test]
     fn test_object_as_str() {}
@@ -0,0 +1,190 @@
+// Std
+use std::str::FromStr;
+
+// External
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// Internal
+use super::error::Error;
+use super::HashAlgorithm;
+
+/// **ObjectId Enum**
+///
+/// Name of a stored object, the raw hash of its content. Parsed once where
+/// a hash enters the crate, so a malformed one never reaches a file read.
+/// Shown and serialized as lowercase hex.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ObjectId {
+    Sha1([u8; 20]),
+    Sha256([u8; 32]),
+}
+
+impl ObjectId {
+    /// Parse a full hex hash of either algorithm, told apart by length.
+    pub fn from_hex(hex: &str) -> Result<Self, Error> {
+        let bytes = hex::decode(hex).map_err(|_| Error::InvalidObjectId(hex.to_string()))?;
+
+        Self::from_bytes(&bytes).map_err(|_| Error::InvalidObjectId(hex.to_string()))
+    }
+
+    /// Parse a full hex hash of `algorithm`.
+    pub fn from_hex_with(hex: &str, algorithm: HashAlgorithm) -> Result<Self, Error> {
+        match Self::from_hex(hex)? {
+            id if id.algorithm() == algorithm => Ok(id),
+            _ => Err(Error::InvalidObjectId(hex.to_string())),
+        }
+    }
+
+    /// Take a raw hash of 20 (SHA-1) or 32 (SHA-256) bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        match bytes.len() {
+            20 => Ok(Self::Sha1(bytes.try_into().unwrap())),
+            32 => Ok(Self::Sha256(bytes.try_into().unwrap())),
+            _ => Err(Error::InvalidObjectId(hex::encode(bytes))),
+        }
+    }
+
+    /// All zero id, naming no object, e.g. the old side of a creation.
+    pub fn zero(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha1 => Self::Sha1([0; 20]),
+            HashAlgorithm::Sha256 => Self::Sha256([0; 32]),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.as_bytes().iter().all(|&b| b == 0)
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            Self::Sha1(_) => HashAlgorithm::Sha1,
+            Self::Sha256(_) => HashAlgorithm::Sha256,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Sha1(bytes) => bytes,
+            Self::Sha256(bytes) => bytes,
+        }
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.as_bytes())
+    }
+}
+
+impl std::fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+impl std::fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ObjectId({})", self.to_hex())
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_hex(s)
+    }
+}
+
+impl AsRef<[u8]> for ObjectId {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl From<ObjectId> for String {
+    fn from(id: ObjectId) -> Self {
+        id.to_hex()
+    }
+}
+
+impl From<&ObjectId> for String {
+    fn from(id: &ObjectId) -> Self {
+        id.to_hex()
+    }
+}
+
+impl Serialize for ObjectId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for ObjectId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+
+        Self::from_hex(&hex).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "c192349d0ee530038e5d925fdd701652ca755ba8";
+
+    #[test]
+    fn test_object_id_hex() {
+        let id = ObjectId::from_hex(SHA1).unwrap();
+        assert_eq!(id.algorithm(), HashAlgorithm::Sha1);
+        assert_eq!(id.to_string(), SHA1);
+        assert_eq!(format!("{:?}", id), format!("ObjectId({})", SHA1));
+        assert_eq!(SHA1.to_uppercase().parse::<ObjectId>().unwrap(), id);
+        assert_eq!(ObjectId::from_bytes(id.as_bytes()).unwrap(), id);
+
+        let id = ObjectId::from_hex(&"ab".repeat(32)).unwrap();
+        assert_eq!(id.algorithm(), HashAlgorithm::Sha256);
+        assert!(ObjectId::from_hex_with(&id.to_hex(), HashAlgorithm::Sha1).is_err());
+
+        for invalid in [
+            "",
+            "c19234",
+            &SHA1.replace('c', "g"),
+            &format!("{}00", SHA1),
+        ] {
+            assert!(matches!(
+                ObjectId::from_hex(invalid),
+                Err(Error::InvalidObjectId(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_object_id_zero_and_order() {
+        let zero = ObjectId::zero(HashAlgorithm::Sha1);
+        assert!(zero.is_zero());
+        assert_eq!(zero.to_hex(), "0".repeat(40));
+        assert_eq!(ObjectId::zero(HashAlgorithm::Sha256).as_bytes().len(), 32);
+
+        // Same order as the hex
+        let mut ids = [ObjectId::from_hex(SHA1).unwrap(), zero];
+        ids.sort();
+        assert_eq!(ids[0], zero);
+    }
+
+    #[test]
+    fn test_object_id_serde() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Wrapper {
+            id: ObjectId,
+        }
+
+        let wrapper = Wrapper {
+            id: ObjectId::from_hex(SHA1).unwrap(),
+        };
+        let toml = toml::to_string(&wrapper).unwrap();
+        assert_eq!(toml, format!("id = \"{}\"\n", SHA1));
+        assert_eq!(toml::from_str::<Wrapper>(&toml).unwrap(), wrapper);
+        assert!(toml::from_str::<Wrapper>("id = \"xyz\"").is_err());
+    }
+}
@@ -0,0 +1,283 @@
+// Std
+use std::fmt::Write;
+
+// External
+use chrono::prelude::{DateTime, Utc};
+use chrono::{FixedOffset, TimeZone};
+
+// Internal
+use super::commit::with_newline;
+use super::encoding::{format_signature, parse_signature};
+use super::error::Error;
+use super::object::Hashable;
+use super::{HashAlgorithm, ObjectEncoding, ObjectId};
+
+// Objects a tag may point at
+const TARGET_TYPES: [&str; 4] = ["blob", "tree", "commit", "tag"];
+
+/// **Tag Struct**
+///
+/// Annotated tag: a named, signed-off pointer at another object, usually
+/// a release commit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub object: ObjectId,
+    pub object_type: String,
+    pub name: String,
+    pub tagger: String,
+    pub date: DateTime<Utc>,
+    /// As stored, so the tag hashes back the same. New messages end with
+    /// `\n`.
+    pub message: String,
+    pub encoding: ObjectEncoding,
+    pub timezone: FixedOffset,
+}
+
+impl Tag {
+    pub fn new<S: Into<String>>(
+        object: ObjectId,
+        object_type: S,
+        name: S,
+        tagger: S,
+        message: S,
+    ) -> Result<Self, Error> {
+        let object_type = object_type.into();
+        if !TARGET_TYPES.contains(&object_type.as_str()) {
+            return Err(Error::InvalidObject(format!(
+                "tag of unknown type {}",
+                object_type
+            )));
+        }
+
+        let name = name.into();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(Error::InvalidObject(format!("broken tag name {:?}", name)));
+        }
+
+        Ok(Self {
+            object,
+            object_type,
+            name,
+            tagger: tagger.into(),
+            date: Utc::now(),
+            message: with_newline(message.into()),
+            encoding: ObjectEncoding::default(),
+            timezone: FixedOffset::east_opt(0).unwrap(),
+        })
+    }
+
+    /// Lay the tag out in `encoding`, see [`ObjectEncoding`].
+    pub fn with_encoding(mut self, encoding: ObjectEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Timezone written after the date in the git encoding.
+    pub fn with_timezone(mut self, timezone: FixedOffset) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    /// Create Tag with RawObject of a SHA-1 repository.
+    pub fn from_rawobject(content: &[u8]) -> Result<Self, Error> {
+        Self::from_rawobject_with(content, HashAlgorithm::Sha1)
+    }
+
+    /// Create Tag with RawObject whose target is an `algorithm` hash.
+    pub fn from_rawobject_with(content: &[u8], algorithm: HashAlgorithm) -> Result<Self, Error> {
+        Self::from_rawobject_with_encoding(content, algorithm, ObjectEncoding::Nss)
+    }
+
+    /// Create Tag with RawObject laid out in `encoding`.
+    pub fn from_rawobject_with_encoding(
+        content: &[u8],
+        algorithm: HashAlgorithm,
+        encoding: ObjectEncoding,
+    ) -> Result<Self, Error> {
+        let content = String::from_utf8(content.to_vec())
+            .map_err(|_| Error::InvalidObject("tag is not utf8".to_string()))?;
+
+        // "<header lines>\n\n<message>", the message usually ending in `\n`
+        let (headers, message) = content
+            .split_once("\n\n")
+            .ok_or(Error::InvalidObject("tag without message".to_string()))?;
+        let message = message.to_string();
+
+        let mut object = None;
+        let mut object_type = String::new();
+        let mut name = String::new();
+        let mut tagger = String::new();
+        let mut date = String::new();
+        let mut signature = None;
+
+        for line in headers.lines() {
+            let (key, value) = line
+                .split_once(' ')
+                .ok_or(Error::InvalidObject(format!("broken tag line {:?}", line)))?;
+
+            match key {
+                "object" => {
+                    object = Some(ObjectId::from_hex_with(value, algorithm).map_err(|_| {
+                        Error::InvalidObject(format!("tag hash {:?} is not {}", value, algorithm))
+                    })?)
+                }
+                "type" => object_type = value.to_string(),
+                "tag" => name = value.to_string(),
+                "tagger" if encoding == ObjectEncoding::Git => {
+                    let (identity, date, timezone) = parse_signature(value)?;
+                    tagger = identity;
+                    signature = Some((date, timezone));
+                }
+                "tagger" => tagger = value.to_string(),
+                "date" if encoding == ObjectEncoding::Nss => date = value.to_string(),
+                k => return Err(Error::InvalidObject(format!("unknown tag header {}", k))),
+            }
+        }
+
+        let object = object.ok_or(Error::InvalidObject("tag without object".to_string()))?;
+        let tag = Self::new(object, object_type, name, tagger, String::new())?;
+        let tag = Self {
+            message,
+            ..tag.with_encoding(encoding)
+        };
+        if encoding == ObjectEncoding::Git {
+            let (date, timezone) =
+                signature.ok_or(Error::InvalidObject("tag without tagger".to_string()))?;
+
+            return Ok(Self {
+                date,
+                timezone,
+                ..tag
+            });
+        }
+
+        let date = date
+            .parse::<i64>()
+            .ok()
+            .and_then(|t| Utc.timestamp_opt(t, 0).single())
+            .ok_or(Error::InvalidObject(format!("broken tag date {:?}", date)))?;
+
+        Ok(Self { date, ..tag })
+    }
+
+    fn content(&self) -> String {
+        let mut content = String::new();
+        let _ = writeln!(content, "object {}", self.object);
+        let _ = writeln!(content, "type {}", self.object_type);
+        let _ = writeln!(content, "tag {}", self.name);
+        match self.encoding {
+            ObjectEncoding::Nss => {
+                let _ = writeln!(content, "tagger {}", self.tagger);
+                let _ = writeln!(content, "date {}", self.date.timestamp());
+            }
+            ObjectEncoding::Git => {
+                let signature = format_signature(&self.tagger, &self.date, &self.timezone);
+                let _ = writeln!(content, "tagger {}", signature);
+            }
+        }
+        let _ = write!(content, "\n{}", self.message);
+
+        content
+    }
+}
+
+impl std::fmt::Display for Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.content())
+    }
+}
+
+impl Hashable for Tag {
+    fn as_bytes(&self) -> Vec<u8> {
+        let content = self.content();
+        let store = format!("tag {}\0{}", content.len(), content);
+
+        Vec::from(store.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: &str = "a02b83cb54ba139e5c9d623a2fcf5424552946e0";
+
+    #[test]
+    fn test_tag_round_trip() {
+        let content = b"object a02b83cb54ba139e5c9d623a2fcf5424552946e0
+type commit
+tag v1.0.0
+tagger nope noshishi <noshishi@nope.com>
+date 1687619045
+
+Release 1.0.0
+
+Signed-off-by: nope noshishi <noshishi@nope.com>
+";
+
+        let tag = Tag::from_rawobject(content).unwrap();
+        assert_eq!(tag.object, ObjectId::from_hex(TARGET).unwrap());
+        assert_eq!(tag.object_type, "commit");
+        assert_eq!(tag.name, "v1.0.0");
+        assert_eq!(tag.tagger, "nope noshishi <noshishi@nope.com>");
+        assert_eq!(tag.date, Utc.timestamp_opt(1687619045, 0).unwrap());
+        assert_eq!(
+            tag.message,
+            "Release 1.0.0\n\nSigned-off-by: nope noshishi <noshishi@nope.com>\n"
+        );
+
+        assert_eq!(tag.to_string().as_bytes(), content);
+
+        // A message without a trailing newline is kept as is
+        let content = &content[..content.len() - 1];
+        let tag = Tag::from_rawobject(content).unwrap();
+        assert_eq!(tag.to_string().as_bytes(), content);
+        let store = tag.as_bytes();
+        assert!(store.starts_with(format!("tag {}\0", content.len()).as_bytes()));
+        assert_eq!(&store[store.len() - content.len()..], content);
+    }
+
+    #[test]
+    fn test_tag_git_encoding() {
+        let content = b"object 381c05cbc557ce5acfa7f369b87c952c8ff148ff
+type commit
+tag v1.0.0
+tagger nope noshishi <noshishi@nope.com> 1687619045 +0900
+
+Release 1.0.0
+";
+
+        let tag =
+            Tag::from_rawobject_with_encoding(content, HashAlgorithm::Sha1, ObjectEncoding::Git)
+                .unwrap();
+        assert_eq!(tag.tagger, "nope noshishi <noshishi@nope.com>");
+        assert_eq!(tag.date, Utc.timestamp_opt(1687619045, 0).unwrap());
+        assert_eq!(tag.timezone, FixedOffset::east_opt(9 * 3600).unwrap());
+
+        // Same hash as `git mktag`
+        assert_eq!(tag.to_string().as_bytes(), content);
+        assert_eq!(
+            tag.object_id().to_hex(),
+            "298c2a6f64a94245cc3e8b0295bcac6e885e3b5a"
+        );
+        assert!(Tag::from_rawobject(content).is_err());
+    }
+
+    #[test]
+    fn test_tag_invalid() {
+        let target = ObjectId::from_hex(TARGET).unwrap();
+        assert!(Tag::new(target, "commit", "v1", "nope", "msg").is_ok());
+        assert!(Tag::new(target, "branch", "v1", "nope", "msg").is_err());
+        assert!(Tag::new(target, "commit", "v 1", "nope", "msg").is_err());
+        assert!(Tag::new(target, "commit", "", "nope", "msg").is_err());
+
+        // Broken tags are errors
+        assert!(Tag::from_rawobject(b"object abc\n").is_err());
+        assert!(Tag::from_rawobject(b"type commit\ntag v1\ndate 1\n\nmsg\n").is_err());
+        let content = format!("object {}\ntype commit\ntag v1\ndate now\n\nmsg\n", TARGET);
+        assert!(Tag::from_rawobject(content.as_bytes()).is_err());
+        let content = format!("object {}\ntype commit\ntag v1\ndate 1\n\nmsg\n", TARGET);
+        assert!(Tag::from_rawobject(content.as_bytes()).is_ok());
+        assert!(Tag::from_rawobject_with(content.as_bytes(), HashAlgorithm::Sha256).is_err());
+    }
+}
@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 
 // Internal
 use super::error::Error;
-use super::{FileMeta, Hashable, Object};
+use super::{FileMeta, HashAlgorithm, Hashable, Object, ObjectEncoding, ObjectId};
 
 /// **Entry Struct**
 ///
@@ -19,15 +19,24 @@ use super::{FileMeta, Hashable, Object};
 pub struct Entry {
     pub mode: u32,
     pub name: OsString,
-    pub hash: Vec<u8>,
+    pub hash: ObjectId,
 }
 
 impl Entry {
     pub fn new<P: AsRef<Path>>(path: P, object: Object) -> Result<Self, Error> {
+        Self::new_with(path, object, HashAlgorithm::Sha1)
+    }
+
+    /// Entry for `object` at `path`, named by `algorithm`.
+    pub fn new_with<P: AsRef<Path>>(
+        path: P,
+        object: Object,
+        algorithm: HashAlgorithm,
+    ) -> Result<Self, Error> {
         let metadata = path.as_ref().metadata()?;
         let mode = metadata.mode();
 
-        let hash = object.to_hash();
+        let hash = object.object_id_with(algorithm);
 
         let name = path.as_ref().file_name().unwrap().to_os_string();
 
@@ -35,11 +44,22 @@ impl Entry {
     }
 
     pub fn new_group<P: AsRef<Path>>(path: P, entries: Vec<Entry>) -> Result<Self, Error> {
+        Self::new_group_with(path, entries, HashAlgorithm::Sha1, ObjectEncoding::Nss)
+    }
+
+    /// Entry for the sub tree of `entries` at `path`, laid out in `encoding`
+    /// and named by `algorithm`.
+    pub fn new_group_with<P: AsRef<Path>>(
+        path: P,
+        entries: Vec<Entry>,
+        algorithm: HashAlgorithm,
+        encoding: ObjectEncoding,
+    ) -> Result<Self, Error> {
         let metadata = path.as_ref().metadata()?;
         let mode = metadata.mode();
 
-        let tree = Tree::from_entries(entries);
-        let hash = tree.to_hash();
+        let tree = Tree::from_entries(entries).with_encoding(encoding);
+        let hash = tree.object_id_with(algorithm);
 
         let name = path.as_ref().file_name().unwrap().to_os_string();
 
@@ -49,24 +69,90 @@ impl Entry {
     /// Create Entry with RawObject.
     ///
     /// **Note:** This related function is intended to be called through Tree sturuct.
-    fn from_rawobject(meta: &[u8], hash: &[u8]) -> Result<Self, Error> {
-        // meta = b"<pre_file hash><this file mode> <this file relative path>"
-        // hash_next = b"<this_file hash><next file mode> <next file relative path>"
+    fn from_rawobject(meta: &[u8], hash: &[u8], encoding: ObjectEncoding) -> Result<Self, Error> {
+        // meta = b"<this file mode> <this file relative path>"
+        // hash = b"<this file hash>"
+
+        let meta = String::from_utf8(meta.to_vec())
+            .map_err(|_| Error::InvalidObject("tree entry is not utf8".to_string()))?;
+        let (mode, name) = meta.split_once(' ').ok_or(Error::InvalidObject(format!(
+            "broken tree entry {:?}",
+            meta
+        )))?;
+        let radix = match encoding {
+            ObjectEncoding::Nss => 10,
+            ObjectEncoding::Git => 8,
+        };
+        let mode = u32::from_str_radix(mode, radix)
+            .map_err(|_| Error::InvalidObject(format!("broken tree entry mode {:?}", mode)))?;
+
+        let entry = Self {
+            mode,
+            name: OsString::from(name),
+            hash: ObjectId::from_bytes(hash)?,
+        };
+        entry.check_name()?;
+
+        Ok(entry)
+    }
 
-        let meta = String::from_utf8(meta.to_vec()).unwrap();
-        let mode_name = meta.split_whitespace().collect::<Vec<&str>>();
+    /// Refuse names that are not one path component, so a tree read from
+    /// elsewhere cannot place files outside the directory it is checked
+    /// out into.
+    pub(crate) fn check_name(&self) -> Result<(), Error> {
+        let name = self.name.as_encoded_bytes();
+        if name.is_empty()
+            || name == b"."
+            || name == b".."
+            || name.contains(&b'/')
+            || name.contains(&b'\0')
+        {
+            return Err(Error::InvalidObject(format!(
+                "unsafe tree entry name {:?}",
+                self.name
+            )));
+        }
 
-        Ok(Self {
-            mode: mode_name[0].parse::<u32>().unwrap(),
-            name: OsString::from(mode_name[1]),
-            hash: hash.to_vec(),
-        })
+        Ok(())
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
-        let header = format!("{} {}\0", self.mode, self.name.to_str().unwrap());
+        self.as_bytes_with(ObjectEncoding::Nss)
+    }
+
+    /// `<mode> <name>\0<hash>`, the mode in decimal for nss and in octal,
+    /// normalized, for git.
+    pub fn as_bytes_with(&self, encoding: ObjectEncoding) -> Vec<u8> {
+        let header = match encoding {
+            ObjectEncoding::Nss => format!("{} {}\0", self.mode, self.name.to_str().unwrap()),
+            ObjectEncoding::Git => {
+                format!("{:o} {}\0", self.git_mode(), self.name.to_str().unwrap())
+            }
+        };
 
-        [header.as_bytes(), &self.hash].concat()
+        [header.as_bytes(), self.hash.as_bytes()].concat()
+    }
+
+    /// The file mode as git records it: only the type and, for files, the
+    /// executable bit are kept.
+    pub fn git_mode(&self) -> u32 {
+        match self.mode & 0o170000 {
+            0o040000 => 0o040000,
+            0o120000 => 0o120000,
+            0o160000 => 0o160000,
+            _ if self.mode & 0o111 != 0 => 0o100755,
+            _ => 0o100644,
+        }
+    }
+
+    /// Git orders a tree entry by its name, with a `/` after tree names.
+    fn git_sort_key(&self) -> Vec<u8> {
+        let mut key = self.name.to_str().unwrap().as_bytes().to_vec();
+        if self.git_mode() == 0o040000 {
+            key.push(b'/');
+        }
+
+        key
     }
 
     pub fn as_type(&self) -> &str {
@@ -105,7 +191,7 @@ impl std::fmt::Display for Entry {
             "{:0>6o} {} {}\t{}",
             self.mode,
             object_type,
-            hex::encode(&self.hash),
+            self.hash,
             self.name.to_str().unwrap()
         )
     }
@@ -117,6 +203,8 @@ impl std::fmt::Display for Entry {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Tree {
     pub entries: Vec<Entry>,
+    #[serde(default)]
+    pub encoding: ObjectEncoding,
 }
 
 impl Tree {
@@ -124,6 +212,25 @@ impl Tree {
     ///
     /// This path must be in the working directory.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::new_with_encoding(path, ObjectEncoding::Nss)
+    }
+
+    /// Create Tree with the path, sub trees included, laid out in
+    /// `encoding`.
+    pub fn new_with_encoding<P: AsRef<Path>>(
+        path: P,
+        encoding: ObjectEncoding,
+    ) -> Result<Self, Error> {
+        Self::new_with(path, HashAlgorithm::Sha1, encoding)
+    }
+
+    /// Create Tree with the path, entries named by `algorithm` and sub trees
+    /// laid out in `encoding`.
+    pub fn new_with<P: AsRef<Path>>(
+        path: P,
+        algorithm: HashAlgorithm,
+        encoding: ObjectEncoding,
+    ) -> Result<Self, Error> {
         let read_dir = path.as_ref().read_dir().unwrap();
 
         let ignores = [PathBuf::from(".git"), PathBuf::from(".nss")];
@@ -136,44 +243,72 @@ impl Tree {
                 continue;
             }
 
-            let object = Object::new(&path)?;
-            let entry = Entry::new(path, object)?;
+            let object = Object::new_with(&path, algorithm, encoding)?;
+            let entry = Entry::new_with(path, object, algorithm)?;
 
             entries.push(entry)
         }
 
         entries.sort_by(|a, b| a.name.cmp(&b.name));
 
-        Ok(Self { entries })
+        Ok(Self::from_entries(entries).with_encoding(encoding))
     }
 
     pub fn from_entries(entries: Vec<Entry>) -> Self {
-        Self { entries }
+        Self {
+            entries,
+            encoding: ObjectEncoding::default(),
+        }
     }
 
-    /// Create Object with RawObject.
-    pub fn from_rawobject(content: &[u8]) -> Result<Self, Error> {
-        let entries: Vec<Entry> = Vec::new();
-        let mut contnets = content.splitn(2, |&b| b == b'\0');
-        let mut header = contnets.next().unwrap();
-        let split_content = split_content(contnets.next().unwrap());
+    /// Lay the tree out in `encoding`, see [`ObjectEncoding`].
+    pub fn with_encoding(mut self, encoding: ObjectEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
 
-        let mut entries = split_content
-            .iter()
-            .try_fold(entries, |mut acc, x| {
-                let (hash, next_header) = x.split_at(20);
-                let entry = Entry::from_rawobject(header, hash).unwrap();
+    /// Create Object with RawObject of a SHA-1 repository.
+    pub fn from_rawobject(content: &[u8]) -> Result<Self, Error> {
+        Self::from_rawobject_with(content, HashAlgorithm::Sha1)
+    }
 
-                acc.push(entry);
-                header = next_header;
+    /// Create Object with RawObject whose entry hashes are of `algorithm`.
+    ///
+    /// Entries keep their stored order, so the tree hashes back to the same
+    /// value.
+    pub fn from_rawobject_with(content: &[u8], algorithm: HashAlgorithm) -> Result<Self, Error> {
+        Self::from_rawobject_with_encoding(content, algorithm, ObjectEncoding::Nss)
+    }
 
-                Some(acc)
-            })
-            .unwrap();
+    /// Create Object with RawObject laid out in `encoding`.
+    pub fn from_rawobject_with_encoding(
+        content: &[u8],
+        algorithm: HashAlgorithm,
+        encoding: ObjectEncoding,
+    ) -> Result<Self, Error> {
+        // content = b"<mode> <name>\0<hash><mode> <name>\0<hash>..."
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut rest = content;
+
+        while !rest.is_empty() {
+            let nul = rest
+                .iter()
+                .position(|&b| b == b'\0')
+                .ok_or(Error::InvalidObject("tree entry without hash".to_string()))?;
+            let (meta, remain) = (&rest[..nul], &rest[nul + 1..]);
+
+            if remain.len() < algorithm.size() {
+                return Err(Error::InvalidObject(
+                    "tree entry hash is too short".to_string(),
+                ));
+            }
+            let (hash, next) = remain.split_at(algorithm.size());
 
-        entries.sort_by(|a, b| a.name.cmp(&b.name));
+            entries.push(Entry::from_rawobject(meta, hash, encoding)?);
+            rest = next;
+        }
 
-        Ok(Self { entries })
+        Ok(Self { entries, encoding })
     }
 }
 
@@ -194,10 +329,13 @@ impl std::fmt::Display for Tree {
 impl Hashable for Tree {
     fn as_bytes(&self) -> Vec<u8> {
         // "tree content_size\0entry\nentry\nentry\n..." to bytes
-        let entries = self
-            .entries
+        let mut entries: Vec<&Entry> = self.entries.iter().collect();
+        if self.encoding == ObjectEncoding::Git {
+            entries.sort_by_key(|e| e.git_sort_key());
+        }
+        let entries = entries
             .iter()
-            .map(|x| x.as_bytes())
+            .map(|x| x.as_bytes_with(self.encoding))
             .collect::<Vec<_>>();
 
         let content = entries.concat();
@@ -207,28 +345,6 @@ impl Hashable for Tree {
     }
 }
 
-fn split_content(contents: &[u8]) -> Vec<&[u8]> {
-    let mut result: Vec<&[u8]> = Vec::new();
-    let mut index = 0;
-
-    while let Some(b_index) = &contents[index + 20..]
-        .iter()
-        .position(|&byte| byte == b'\0')
-    {
-        let split_index = index + 20 + b_index;
-
-        result.push(&contents[index..split_index]);
-        index = split_index + 1;
-
-        if index + 20 > contents.len() {
-            break;
-        }
-    }
-
-    result.push(&contents[index..]);
-    result
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -261,7 +377,7 @@ mod tests {
         assert_eq!(entry.mode, 0o100644);
         assert_eq!(entry.name, OsString::from("first.rs"));
         assert_eq!(
-            hex::encode(entry.hash),
+            entry.hash.to_hex(),
             "5c73008ba75573c20d6a8a6e557d0556d4a84133"
         );
 
@@ -274,7 +390,7 @@ mod tests {
         assert_eq!(entry.mode, 0o040755);
         assert_eq!(entry.name, OsString::from("test_entry_new"));
         assert_eq!(
-            hex::encode(entry.hash),
+            entry.hash.to_hex(),
             "c192349d0ee530038e5d925fdd701652ca755ba8"
         );
 
@@ -310,7 +426,7 @@ mod tests {
 
         let entries = vec![entry1, entry2];
 
-        let result = Entry::new_group(&temp_dir, entries);
+        let result = Entry::new_group(&temp_dir, entries.clone());
 
         assert!(result.is_ok());
 
@@ -318,7 +434,17 @@ mod tests {
         assert_eq!(entry.mode, 0o040755);
         assert_eq!(entry.name, OsString::from("test_entry_group_new"));
         assert_eq!(
-            hex::encode(entry.hash),
+            entry.hash.to_hex(),
+            "e6cc44b0e9902bb5f81ec384dc92093df7ecf36d"
+        );
+
+        // Git sub trees hash in git's layout
+        let git = Tree::from_entries(entries.clone()).with_encoding(ObjectEncoding::Git);
+        let entry =
+            Entry::new_group_with(&temp_dir, entries, HashAlgorithm::Sha1, ObjectEncoding::Git)?;
+        assert_eq!(entry.hash, git.object_id());
+        assert_ne!(
+            entry.hash.to_hex(),
             "e6cc44b0e9902bb5f81ec384dc92093df7ecf36d"
         );
 
@@ -330,12 +456,102 @@ mod tests {
 
     #[test]
     fn test_from_rawobject() -> Result<()> {
-        // let meta = b"<this file relative path>";
-        // let hash_next = b"<next file mode> <next file relative path>";
+        let content = b"33188 second.rs\x00xj\xc6%\x91\xa38\xfc\xebv)RkW\x8bq\x0f\xca\x01\x0033188 first file.rs\x00\\s\x00\x8b\xa7Us\xc2\rj\x8anU}\x05V\xd4\xa8A3";
 
-        // let cont = b"33188 first.rs\x00\\s\x00\x8b\xa7Us\xc2\rj\x8anU}\x05V\xd4\xa8A333188 second.rs\x00xj\xc6%\x91\xa38\xfc\xebv)RkW\x8bq\x0f\xca\x01";
-        // Clean up: Remove the test dir
-        // fs::remove_dir_all(temp_dir).unwrap();
+        let tree = Tree::from_rawobject(content)?;
+        assert_eq!(tree.entries.len(), 2);
+        assert_eq!(tree.entries[0].name, OsString::from("second.rs"));
+        assert_eq!(tree.entries[1].name, OsString::from("first file.rs"));
+        assert_eq!(tree.entries[1].mode, 0o100644);
+        assert_eq!(
+            tree.entries[1].hash.to_hex(),
+            "5c73008ba75573c20d6a8a6e557d0556d4a84133"
+        );
+
+        // Stored order is kept so the bytes round-trip
+        let store = tree.as_bytes();
+        assert_eq!(&store[store.len() - content.len()..], content);
+
+        // Broken trees are errors
+        assert!(Tree::from_rawobject(b"33188 first.rs").is_err());
+        assert!(Tree::from_rawobject(b"33188 first.rs\x00\\s").is_err());
+        assert!(Tree::from_rawobject(
+            b"mode first.rs\x00\\s\x00\x8b\xa7Us\xc2\rj\x8anU}\x05V\xd4\xa8A3"
+        )
+        .is_err());
+
+        // Names that are not one path component are refused
+        let hash = [0x5cu8; 20];
+        for name in ["", ".", "..", "a/b", "/abs", "../up"] {
+            let content = [format!("33188 {}\0", name).as_bytes(), &hash].concat();
+            assert!(Tree::from_rawobject(&content).is_err());
+        }
+
+        // SHA-256 entries are 32 bytes
+        let hash = HashAlgorithm::Sha256.digest(b"blob 0\0");
+        let content = [
+            &b"33188 first.rs\x00"[..],
+            &hash,
+            b"33188 second.rs\x00",
+            &hash,
+        ]
+        .concat();
+        let tree = Tree::from_rawobject_with(&content, HashAlgorithm::Sha256)?;
+        assert_eq!(tree.entries.len(), 2);
+        assert_eq!(tree.entries[1].hash.as_bytes(), hash);
+        assert!(Tree::from_rawobject_with(&content[..40], HashAlgorithm::Sha256).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_encoding() -> Result<()> {
+        let blob = ObjectId::from_hex("5c73008ba75573c20d6a8a6e557d0556d4a84133")?;
+        let entry = |mode, name: &str, hash| Entry {
+            mode,
+            name: OsString::from(name),
+            hash,
+        };
+
+        // Same hashes as `git mktree`
+        let sub = Tree::from_entries(vec![entry(0o100644, "inner.rs", blob)])
+            .with_encoding(ObjectEncoding::Git);
+        assert_eq!(
+            sub.object_id().to_hex(),
+            "25b5637b3847d39ef8ce8c505807b0c4c2b577ba"
+        );
+
+        let tree = Tree::from_entries(vec![
+            entry(0o040755, "sub", sub.object_id()),
+            entry(0o100644, "sub.rs", blob),
+            entry(0o100755, "first.rs.bak", blob),
+            entry(0o100664, "first.rs", blob),
+        ])
+        .with_encoding(ObjectEncoding::Git);
+        assert_eq!(
+            tree.object_id().to_hex(),
+            "42a9894a7c9e078cfa481b075b7346fd00e3d1dc"
+        );
+        let empty = Tree::from_entries(vec![]).with_encoding(ObjectEncoding::Git);
+        assert_eq!(
+            empty.object_id().to_hex(),
+            "4b825dc642cb6eb9a060e54bf8d69288fbee4904"
+        );
+
+        // Read back in git order with normalized modes
+        let store = tree.as_bytes();
+        let content = &store[store.iter().position(|&b| b == b'\0').unwrap() + 1..];
+        assert!(content.starts_with(b"100644 first.rs\0"));
+        let read =
+            Tree::from_rawobject_with_encoding(content, HashAlgorithm::Sha1, ObjectEncoding::Git)?;
+        let names: Vec<_> = read.entries.iter().map(|e| e.name.clone()).collect();
+        assert_eq!(names, ["first.rs", "first.rs.bak", "sub.rs", "sub"]);
+        assert_eq!(read.entries[3].mode, 0o040000);
+        assert_eq!(read.as_bytes(), store);
+
+        // Octal modes are not decimal ones
+        let nss = Tree::from_rawobject_with(content, HashAlgorithm::Sha1)?;
+        assert_eq!(nss.entries[0].mode, 100644);
 
         Ok(())
     }
@@ -5,7 +5,8 @@ use std::collections::{HashSet, VecDeque};
 use anyhow::Result;
 
 // Internal
-use crate::repository::NssRepository;
+use crate::repo::database::ObjectDatabase;
+use crate::struct_set::ObjectId;
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Eq, Ord, Hash)]
 pub struct VertexIndex(usize);
@@ -127,7 +128,7 @@ impl<T: Clone + PartialEq + Eq + std::hash::Hash> Graph<T> {
         self.vertexs.iter().map(|v| &v.value).collect()
     }
 
-    pub fn common_vertex_value<'a>(&'a self, another_graph: &'a Graph<T>) -> Option<&T> {
+    pub fn common_vertex_value<'a>(&'a self, another_graph: &'a Graph<T>) -> Option<&'a T> {
         let vertexs_set = self.to_value_set();
         let t_vertexs_set = another_graph.to_value_set();
 
@@ -154,22 +155,26 @@ impl<T: Clone + PartialEq + Eq + std::hash::Hash> Default for Graph<T> {
     }
 }
 
-pub type CommitHash = String;
+pub type CommitHash = ObjectId;
 
 pub type CommitGraph = Graph<CommitHash>;
 
 impl CommitGraph {
-    pub fn build(start_hash: String, repo: &NssRepository, deep: usize) -> Result<Self> {
+    pub fn build<D: ObjectDatabase + ?Sized>(
+        start_hash: CommitHash,
+        repo: &D,
+        deep: usize,
+    ) -> Result<Self> {
         let mut graph = Graph::<CommitHash>::new();
         Self::commit_history(&mut graph, start_hash, repo, 0, deep)?;
 
         Ok(graph)
     }
 
-    fn commit_history(
+    fn commit_history<D: ObjectDatabase + ?Sized>(
         graph: &mut Graph<CommitHash>,
-        current_hash: String,
-        repo: &NssRepository,
+        current_hash: CommitHash,
+        repo: &D,
         current_depth: usize,
         max_depth: usize,
     ) -> Result<()> {
@@ -177,12 +182,12 @@ impl CommitGraph {
             return Ok(());
         }
 
-        let commit = repo.objects().read_commit(&current_hash)?;
+        let commit = repo.read_commit(&current_hash.to_hex())?;
 
         let child_id = graph.add_vertex(current_hash);
 
         for parent_hash in commit.parents {
-            let parent_id = graph.add_vertex(parent_hash.clone());
+            let parent_id = graph.add_vertex(parent_hash);
             graph.add_edges(child_id, parent_id);
 
             Self::commit_history(graph, parent_hash, repo, current_depth + 1, max_depth)?;
@@ -198,7 +203,7 @@ mod tests {
 
     #[test]
     fn test_common_vertex_value() {
-        let mut graph = Graph::<CommitHash>::new();
+        let mut graph = Graph::<String>::new();
 
         let v7_id = graph.add_vertex("v7".to_string());
         let v4_id = graph.add_vertex("v4".to_string());
@@ -211,7 +216,7 @@ mod tests {
         let _ = graph.add_edges(v4_id, v2_id);
         let _ = graph.add_edges(v2_id, v1_id);
 
-        let mut another_graph = Graph::<CommitHash>::new();
+        let mut another_graph = Graph::<String>::new();
         let v5_id = another_graph.add_vertex("v5".to_string());
         let v2_id = another_graph.add_vertex("v2".to_string());
         let v1_id = another_graph.add_vertex("v1".to_string());